@@ -0,0 +1,253 @@
+use crate::savestate::{Snapshot, StateReader, StateWriter};
+
+// delta modulation channel. plays 1 bit deltas that it pulls out of PRG space itself,
+// the bus does the actual fetch when dma_address says a byte is wanted
+
+// in CPU cycles
+const RATE_TABLE: [u16; 16] = [428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54];
+const PAL_RATE_TABLE: [u16; 16] = [398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50];
+
+#[derive(Clone)]
+pub struct Dmc {
+    pub irq: bool,
+    irq_enabled: bool,
+    looping: bool,
+    period: u16,
+    rates: &'static [u16; 16],
+    timer: u16,
+    output_level: u8, // 7 bits
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+}
+
+impl Default for Dmc {
+    fn default() -> Self {
+        Dmc::new()
+    }
+}
+
+impl Dmc {
+    pub fn new() -> Self {
+        Dmc {
+            irq: false,
+            irq_enabled: false,
+            looping: false,
+            period: RATE_TABLE[0],
+            rates: &RATE_TABLE,
+            timer: 0,
+            output_level: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+        }
+    }
+
+    pub fn set_pal(&mut self, pal: bool) {
+        self.rates = if pal { &PAL_RATE_TABLE } else { &RATE_TABLE };
+    }
+
+    // register is 0-3 for $4010-$4013
+    pub fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.irq_enabled = data & 0b1000_0000 != 0;
+                self.looping = data & 0b0100_0000 != 0;
+                self.period = self.rates[(data & 0x0F) as usize];
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+            },
+            1 => self.output_level = data & 0b0111_1111,
+            2 => self.sample_address = 0xC000 | (data as u16) << 6,
+            _ => self.sample_length = (data as u16) << 4 | 1,
+        }
+    }
+
+    // the DMC bit of a $4015 write
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    // where the sample starts and how many bytes it is, from $4012/$4013
+    pub fn sample_range(&self) -> (u16, u16) {
+        (self.sample_address, self.sample_length)
+    }
+
+    pub fn active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    // where the next sample byte should come from, if the buffer wants one
+    pub fn dma_address(&self) -> Option<u16> {
+        (self.sample_buffer.is_none() && self.bytes_remaining > 0).then_some(self.current_address)
+    }
+
+    // the bus hands over the byte it fetched for dma_address
+    pub fn fill(&mut self, data: u8) {
+        self.sample_buffer = Some(data);
+        // wraps round to $8000, not $0000
+        self.current_address = if self.current_address == 0xFFFF { 0x8000 } else { self.current_address + 1 };
+        self.bytes_remaining -= 1;
+
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    // every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period - 1;
+
+        if !self.silence {
+            if self.shift_register & 1 != 0 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(data) => {
+                    self.silence = false;
+                    self.shift_register = data;
+                },
+                None => self.silence = true,
+            }
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        self.output_level
+    }
+}
+
+// the rate table comes from the region
+impl Snapshot for Dmc {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.irq);
+        w.bool(self.irq_enabled);
+        w.bool(self.looping);
+        w.u16(self.period);
+        w.u16(self.timer);
+        w.u8(self.output_level);
+        w.u16(self.sample_address);
+        w.u16(self.sample_length);
+        w.u16(self.current_address);
+        w.u16(self.bytes_remaining);
+        w.option(&self.sample_buffer, |w, data| w.u8(*data));
+        w.u8(self.shift_register);
+        w.u8(self.bits_remaining);
+        w.bool(self.silence);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.irq = r.bool()?;
+        self.irq_enabled = r.bool()?;
+        self.looping = r.bool()?;
+        self.period = r.u16()?;
+        self.timer = r.u16()?;
+        self.output_level = r.u8()?;
+        self.sample_address = r.u16()?;
+        self.sample_length = r.u16()?;
+        self.current_address = r.u16()?;
+        self.bytes_remaining = r.u16()?;
+        self.sample_buffer = r.option(|r| r.u8())?;
+        self.shift_register = r.u8()?;
+        self.bits_remaining = r.u8()?;
+        self.silence = r.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fetches_and_raises_irq_at_the_end() {
+        let mut dmc = Dmc::new();
+        dmc.write_register(0, 0b1000_0000); // irq on
+        dmc.write_register(2, 0xFF); // $FFC0
+        dmc.write_register(3, 0x04); // 65 bytes, runs off the end of memory
+        dmc.set_enabled(true);
+
+        let mut addresses = Vec::new();
+        while let Some(addr) = dmc.dma_address() {
+            addresses.push(addr);
+            dmc.fill(0);
+            dmc.sample_buffer = None;
+        }
+
+        assert_eq!(addresses.len(), 65);
+        assert_eq!(addresses[63], 0xFFFF);
+        assert_eq!(addresses[64], 0x8000);
+        assert!(dmc.irq && !dmc.active());
+    }
+
+    #[test]
+    fn test_looping_restarts_without_irq() {
+        let mut dmc = Dmc::new();
+        dmc.write_register(0, 0b1100_0000);
+        dmc.write_register(3, 0x00); // 1 byte
+        dmc.set_enabled(true);
+
+        dmc.fill(0);
+        assert!(!dmc.irq);
+        assert!(dmc.active());
+        assert_eq!(dmc.current_address, 0xC000);
+    }
+
+    #[test]
+    fn test_output_follows_the_deltas() {
+        let mut dmc = Dmc::new();
+        dmc.write_register(0, 0x0F); // fastest rate
+        dmc.write_register(1, 64);
+        dmc.write_register(3, 0x00);
+        dmc.set_enabled(true);
+        dmc.fill(0b0000_0111);
+
+        // the first byte gets picked up once the empty shift register runs out
+        for _ in 0..8 * 54 {
+            dmc.clock_timer();
+        }
+        for _ in 0..8 * 54 {
+            dmc.clock_timer();
+        }
+        assert_eq!(dmc.output(), 64 + 3 * 2 - 5 * 2);
+    }
+}
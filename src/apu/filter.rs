@@ -0,0 +1,98 @@
+use crate::savestate::{Snapshot, StateReader, StateWriter};
+
+// first order filters, run on the resampled output. the NES has two high passes and a low pass
+// between the APU and the RF/AV out, without them everything sits off centre and sounds harsh
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterKind {
+    HighPass,
+    LowPass,
+}
+
+#[derive(Clone, Debug)]
+pub struct Filter {
+    kind: FilterKind,
+    alpha: f32,
+    prev_in: f32,
+    prev_out: f32,
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind, cutoff: f32, sample_rate: u32) -> Self {
+        let rc = 1.0 / (2.0 * std::f32::consts::PI * cutoff);
+        let dt = 1.0 / sample_rate as f32;
+        let alpha = match kind {
+            FilterKind::HighPass => rc / (rc + dt),
+            FilterKind::LowPass => dt / (rc + dt),
+        };
+        Filter { kind, alpha, prev_in: 0.0, prev_out: 0.0 }
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let output = match self.kind {
+            FilterKind::HighPass => self.alpha * (self.prev_out + input - self.prev_in),
+            FilterKind::LowPass => self.prev_out + self.alpha * (input - self.prev_out),
+        };
+        self.prev_in = input;
+        self.prev_out = output;
+        output
+    }
+}
+
+// what the console does: 90Hz and 440Hz high passes, then a 14kHz low pass
+pub fn nes_filters(sample_rate: u32) -> Vec<Filter> {
+    vec![
+        Filter::new(FilterKind::HighPass, 90.0, sample_rate),
+        Filter::new(FilterKind::HighPass, 440.0, sample_rate),
+        Filter::new(FilterKind::LowPass, 14_000.0, sample_rate),
+    ]
+}
+
+// only what it's remembering, the coefficients come from the sample rate
+impl Snapshot for Filter {
+    fn save(&self, w: &mut StateWriter) {
+        w.f32(self.prev_in);
+        w.f32(self.prev_out);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.prev_in = r.f32()?;
+        self.prev_out = r.f32()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_high_pass_removes_dc() {
+        let mut filter = Filter::new(FilterKind::HighPass, 90.0, 44_100);
+        let mut out = 0.0;
+        for _ in 0..44_100 {
+            out = filter.process(1.0);
+        }
+        assert!(out.abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_low_pass_keeps_dc_and_cuts_nyquist() {
+        let mut filter = Filter::new(FilterKind::LowPass, 14_000.0, 44_100);
+        let mut out = 0.0;
+        for _ in 0..1000 {
+            out = filter.process(1.0);
+        }
+        assert!((out - 1.0).abs() < 1e-3);
+
+        let mut filter = Filter::new(FilterKind::LowPass, 14_000.0, 44_100);
+        let mut peak: f32 = 0.0;
+        for i in 0..1000 {
+            let out = filter.process(if i % 2 == 0 { 1.0 } else { -1.0 });
+            if i > 100 {
+                peak = peak.max(out.abs());
+            }
+        }
+        assert!(peak < 0.6);
+    }
+}
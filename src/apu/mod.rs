@@ -0,0 +1,615 @@
+pub mod dmc;
+pub mod filter;
+pub mod noise;
+pub mod pulse;
+pub mod resampler;
+pub mod triangle;
+pub mod units;
+
+use crate::region::Region;
+use crate::savestate::{Snapshot, StateReader, StateWriter};
+use dmc::Dmc;
+use filter::{Filter, nes_filters};
+use noise::Noise;
+use pulse::Pulse;
+use resampler::Resampler;
+use triangle::Triangle;
+
+pub const CPU_CLOCK_NTSC: u32 = 1_789_773;
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+pub const STEM_NAMES: [&str; 6] = ["pulse1", "pulse2", "triangle", "noise", "dmc", "expansion"];
+
+// frame sequencer steps, in CPU cycles since the sequencer was last reset. the first three,
+// then the last of the 4 step sequence, then the last of the 5 step one
+const FRAME_STEPS: [u32; 5] = [7457, 14913, 22371, 29829, 37281];
+const PAL_FRAME_STEPS: [u32; 5] = [8313, 16627, 24939, 33253, 41565];
+
+/*
+ APU registers
+ $4000-$4003 pulse 1
+ $4004-$4007 pulse 2
+ $4008-$400B triangle
+ $400C-$400F noise
+ $4010-$4013 DMC
+ $4015       channel enables / status
+ $4017       frame counter (reads are controller 2)
+*/
+pub struct APU {
+    pub pulse1: Pulse,
+    pub pulse2: Pulse,
+    pub triangle: Triangle,
+    pub noise: Noise,
+    pub dmc: Dmc,
+    pub expansion_output: f32, // cartridge audio, already scaled to mix with the rest
+    mix: Signal,
+    stems: Option<Vec<Signal>>, // one per STEM_NAMES entry when enabled
+    clock_rate: u32, // CPU cycles per second
+    frame_steps: [u32; 5],
+    block_cycles: u64, // CPU cycles since the resampler last caught up
+    cycle: u64,
+    frame_cycle: u32,
+    five_step: bool,
+    irq_inhibit: bool,
+    frame_irq: bool,
+    frame_counter_reset: Option<u8>, // cycles until a $4017 write takes effect
+}
+
+impl Default for APU {
+    fn default() -> Self {
+        APU::new()
+    }
+}
+
+impl APU {
+    pub fn new() -> Self {
+        APU {
+            pulse1: Pulse::new(true),
+            pulse2: Pulse::new(false),
+            triangle: Triangle::default(),
+            noise: Noise::new(),
+            dmc: Dmc::new(),
+            expansion_output: 0.0,
+            mix: Signal::new(CPU_CLOCK_NTSC, DEFAULT_SAMPLE_RATE),
+            stems: None,
+            clock_rate: CPU_CLOCK_NTSC,
+            frame_steps: FRAME_STEPS,
+            block_cycles: 0,
+            cycle: 0,
+            frame_cycle: 0,
+            five_step: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            frame_counter_reset: None,
+        }
+    }
+
+    // anything already buffered is flushed at the old rate first
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.flush();
+        self.mix.set_rates(self.clock_rate, sample_rate);
+        for stem in self.stems.iter_mut().flatten() {
+            stem.set_rates(self.clock_rate, sample_rate);
+        }
+    }
+
+    // the PAL APU counts out its own periods, the Dendy's CPU is near enough NTSC speed that
+    // it kept the NTSC ones. either way the CPU clock is different so the pitch follows it
+    pub fn set_region(&mut self, region: Region) {
+        let pal = region == Region::Pal;
+        self.noise.set_pal(pal);
+        self.dmc.set_pal(pal);
+        self.frame_steps = if pal { PAL_FRAME_STEPS } else { FRAME_STEPS };
+
+        self.flush();
+        self.clock_rate = region.cpu_clock();
+        let sample_rate = self.sample_rate();
+        self.mix.set_rates(self.clock_rate, sample_rate);
+        for stem in self.stems.iter_mut().flatten() {
+            stem.set_rates(self.clock_rate, sample_rate);
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.mix.resampler.sample_rate()
+    }
+
+    // starts resampling each channel on its own as well as the mix, see take_stems
+    pub fn enable_stems(&mut self) {
+        let rate = self.sample_rate();
+        self.stems = Some(STEM_NAMES.iter().map(|_| Signal::new(self.clock_rate, rate)).collect());
+    }
+
+    pub fn stems_enabled(&self) -> bool {
+        self.stems.is_some()
+    }
+
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4000 ..= 0x4003 => self.pulse1.write_register(addr & 0b11, data),
+            0x4004 ..= 0x4007 => self.pulse2.write_register(addr & 0b11, data),
+            0x4008 ..= 0x400B => self.triangle.write_register(addr & 0b11, data),
+            0x400C ..= 0x400F => self.noise.write_register(addr & 0b11, data),
+            0x4010 ..= 0x4013 => self.dmc.write_register(addr & 0b11, data),
+            0x4015 => {
+                self.pulse1.length.set_enabled(data & 0b0001 != 0);
+                self.pulse2.length.set_enabled(data & 0b0010 != 0);
+                self.triangle.length.set_enabled(data & 0b0100 != 0);
+                self.noise.length.set_enabled(data & 0b1000 != 0);
+                self.dmc.set_enabled(data & 0b1_0000 != 0);
+            },
+            0x4017 => {
+                self.five_step = data & 0b1000_0000 != 0;
+                self.irq_inhibit = data & 0b0100_0000 != 0;
+                if self.irq_inhibit {
+                    self.frame_irq = false;
+                }
+                // the reset lands 3 or 4 cycles later depending on where in the APU cycle the write hit
+                self.frame_counter_reset = Some(if self.cycle.is_multiple_of(2) { 3 } else { 4 });
+            },
+            _ => {},
+        }
+    }
+
+    // the reset button silences everything and restarts the frame counter in the mode it was in
+    pub fn reset(&mut self) {
+        self.write_register(0x4015, 0);
+        self.frame_irq = false;
+        self.frame_counter_reset = Some(if self.cycle.is_multiple_of(2) { 3 } else { 4 });
+    }
+
+    // $4015
+    pub fn read_status(&mut self) -> u8 {
+        let mut status = 0;
+        if self.pulse1.length.active() {
+            status |= 0b0000_0001;
+        }
+        if self.pulse2.length.active() {
+            status |= 0b0000_0010;
+        }
+        if self.triangle.length.active() {
+            status |= 0b0000_0100;
+        }
+        if self.noise.length.active() {
+            status |= 0b0000_1000;
+        }
+        if self.dmc.active() {
+            status |= 0b0001_0000;
+        }
+        if self.frame_irq {
+            status |= 0b0100_0000;
+        }
+        if self.dmc.irq {
+            status |= 0b1000_0000;
+        }
+
+        // reading acknowledges the frame interrupt, but not the DMC one
+        self.frame_irq = false;
+        status
+    }
+
+    pub fn irq(&self) -> bool {
+        self.frame_irq || self.dmc.irq
+    }
+
+    // one CPU cycle
+    pub fn tick(&mut self) {
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        if self.cycle.is_multiple_of(2) {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+
+        self.clock_frame_counter();
+
+        let clock = self.block_cycles;
+        self.mix.update(clock, self.output());
+        if self.stems.is_some() {
+            let levels = self.stem_levels();
+            for (stem, level) in self.stems.iter_mut().flatten().zip(levels) {
+                stem.update(clock, level);
+            }
+        }
+        self.block_cycles += 1;
+        self.cycle += 1;
+
+        // keeps the resampler's buffer small even if nobody is draining samples
+        if self.block_cycles >= self.clock_rate as u64 / 240 {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        self.mix.flush(self.block_cycles);
+        for stem in self.stems.iter_mut().flatten() {
+            stem.flush(self.block_cycles);
+        }
+        self.block_cycles = 0;
+    }
+
+    fn clock_frame_counter(&mut self) {
+        if let Some(delay) = self.frame_counter_reset {
+            if delay > 0 {
+                self.frame_counter_reset = Some(delay - 1);
+            } else {
+                self.frame_counter_reset = None;
+                self.frame_cycle = 0;
+                // 5 step mode clocks everything straight away
+                if self.five_step {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+                return;
+            }
+        }
+
+        self.frame_cycle += 1;
+        let [step_1, step_2, step_3, four_step_last, five_step_last] = self.frame_steps;
+        match (self.five_step, self.frame_cycle) {
+            (_, c) if c == step_1 || c == step_3 => self.clock_quarter_frame(),
+            (_, c) if c == step_2 => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            },
+            (false, c) if c == four_step_last => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+                self.set_frame_irq();
+            },
+            // the IRQ flag gets set on the cycle either side of the last step too
+            (false, c) if c == four_step_last - 1 => self.set_frame_irq(),
+            (false, c) if c == four_step_last + 1 => {
+                self.set_frame_irq();
+                self.frame_cycle = 0;
+            },
+            (true, c) if c == five_step_last => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            },
+            (true, c) if c == five_step_last + 1 => self.frame_cycle = 0,
+            _ => {},
+        }
+    }
+
+    fn set_frame_irq(&mut self) {
+        if !self.irq_inhibit {
+            self.frame_irq = true;
+        }
+    }
+
+    // envelopes and the triangle's linear counter
+    fn clock_quarter_frame(&mut self) {
+        self.pulse1.clock_quarter_frame();
+        self.pulse2.clock_quarter_frame();
+        self.triangle.clock_quarter_frame();
+        self.noise.clock_quarter_frame();
+    }
+
+    // length counters and sweeps
+    fn clock_half_frame(&mut self) {
+        self.pulse1.clock_half_frame();
+        self.pulse2.clock_half_frame();
+        self.triangle.clock_half_frame();
+        self.noise.clock_half_frame();
+    }
+
+    // the real mixer isn't linear, these are the usual approximations of it. 0.0 to about 1.0
+    pub fn output(&self) -> f32 {
+        mix(self.pulse1.output(), self.pulse2.output(), self.triangle.output(), self.noise.output(), self.dmc.output())
+            + self.expansion_output
+    }
+
+    // each channel as if it was playing alone. the mixer isn't linear so these don't add up to the mix exactly
+    fn stem_levels(&self) -> [f32; 6] {
+        [
+            mix(self.pulse1.output(), 0, 0, 0, 0),
+            mix(0, self.pulse2.output(), 0, 0, 0),
+            mix(0, 0, self.triangle.output(), 0, 0),
+            mix(0, 0, 0, self.noise.output(), 0),
+            mix(0, 0, 0, 0, self.dmc.output()),
+            self.expansion_output,
+        ]
+    }
+
+    // everything produced since the last call, usually called once a frame
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.flush();
+        std::mem::take(&mut self.mix.samples)
+    }
+
+    // same order as STEM_NAMES, None unless enable_stems was called
+    pub fn take_stems(&mut self) -> Option<Vec<Vec<f32>>> {
+        self.flush();
+        self.stems.as_mut().map(|stems| stems.iter_mut().map(|stem| std::mem::take(&mut stem.samples)).collect())
+    }
+
+    pub fn take_samples_i16(&mut self) -> Vec<i16> {
+        self.take_samples().into_iter().map(to_i16).collect()
+    }
+}
+
+// the period tables and clock rate come from the region. stems are a capture setting, they
+// start over empty after a load
+impl Snapshot for APU {
+    fn save(&self, w: &mut StateWriter) {
+        self.pulse1.save(w);
+        self.pulse2.save(w);
+        self.triangle.save(w);
+        self.noise.save(w);
+        self.dmc.save(w);
+        w.f32(self.expansion_output);
+        self.mix.save(w);
+        w.u64(self.block_cycles);
+        w.u64(self.cycle);
+        w.u32(self.frame_cycle);
+        w.bool(self.five_step);
+        w.bool(self.irq_inhibit);
+        w.bool(self.frame_irq);
+        w.option(&self.frame_counter_reset, |w, delay| w.u8(*delay));
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.pulse1.load(r)?;
+        self.pulse2.load(r)?;
+        self.triangle.load(r)?;
+        self.noise.load(r)?;
+        self.dmc.load(r)?;
+        self.expansion_output = r.f32()?;
+        self.mix.load(r)?;
+        self.block_cycles = r.u64()?;
+        self.cycle = r.u64()?;
+        self.frame_cycle = r.u32()?;
+        self.five_step = r.bool()?;
+        self.irq_inhibit = r.bool()?;
+        self.frame_irq = r.bool()?;
+        self.frame_counter_reset = r.option(|r| r.u8())?;
+        if self.stems.is_some() {
+            self.enable_stems();
+        }
+        Ok(())
+    }
+}
+
+// one signal on its way from the CPU clock rate to the output rate
+struct Signal {
+    resampler: Resampler,
+    filters: Vec<Filter>,
+    level: f32,
+    samples: Vec<f32>,
+}
+
+impl Signal {
+    fn new(clock_rate: u32, sample_rate: u32) -> Self {
+        Signal {
+            resampler: Resampler::new(clock_rate as f64, sample_rate),
+            filters: nes_filters(sample_rate),
+            level: 0.0,
+            samples: Vec::new(),
+        }
+    }
+
+    fn set_rates(&mut self, clock_rate: u32, sample_rate: u32) {
+        self.resampler = Resampler::new(clock_rate as f64, sample_rate);
+        self.filters = nes_filters(sample_rate);
+        // the new resampler starts from 0, the next update brings it back up to the current level
+        self.level = 0.0;
+    }
+
+    // the resampler only needs to hear about changes
+    fn update(&mut self, clock: u64, level: f32) {
+        if level != self.level {
+            self.resampler.add_delta(clock, level - self.level);
+            self.level = level;
+        }
+    }
+
+    fn flush(&mut self, clocks: u64) {
+        let start = self.samples.len();
+        self.resampler.end_block(clocks, &mut self.samples);
+        for sample in &mut self.samples[start..] {
+            *sample = self.filters.iter_mut().fold(*sample, |s, filter| filter.process(s));
+        }
+    }
+}
+
+// the sample rate is the frontend's choice. a state saved at a different one can't pick up the
+// resampler where it left off, so that part is read past and this signal starts over from silence
+impl Snapshot for Signal {
+    fn save(&self, w: &mut StateWriter) {
+        w.u32(self.resampler.sample_rate());
+        self.resampler.save(w);
+        w.u32(self.filters.len() as u32);
+        for filter in &self.filters {
+            filter.save(w);
+        }
+        w.f32(self.level);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        let sample_rate = r.u32()?;
+        if sample_rate == self.resampler.sample_rate() {
+            return self.load_parts(r);
+        }
+
+        let clock_rate = self.resampler.clock_rate() as u32;
+        Signal::new(clock_rate, sample_rate).load_parts(r)?;
+        self.set_rates(clock_rate, self.resampler.sample_rate());
+        self.samples.clear();
+        Ok(())
+    }
+}
+
+impl Signal {
+    fn load_parts(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.resampler.load(r)?;
+        let filters = r.u32()? as usize;
+        if filters != self.filters.len() {
+            return Err(format!("save state has {} audio filters, expected {}", filters, self.filters.len()));
+        }
+        for filter in self.filters.iter_mut() {
+            filter.load(r)?;
+        }
+        self.level = r.f32()?;
+        self.samples.clear();
+        Ok(())
+    }
+}
+
+pub fn to_i16(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
+
+pub fn mix(pulse1: u8, pulse2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+    let pulse = pulse1 as f32 + pulse2 as f32;
+    let pulse_out = if pulse == 0.0 { 0.0 } else { 95.88 / (8128.0 / pulse + 100.0) };
+
+    let tnd = triangle as f32 / 8227.0 + noise as f32 / 12241.0 + dmc as f32 / 22638.0;
+    let tnd_out = if tnd == 0.0 { 0.0 } else { 159.79 / (1.0 / tnd + 100.0) };
+
+    pulse_out + tnd_out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const FOUR_STEP_LAST: u32 = FRAME_STEPS[3];
+    const FIVE_STEP_LAST: u32 = FRAME_STEPS[4];
+
+    #[test]
+    fn test_frame_irq_in_four_step_mode() {
+        let mut apu = APU::new();
+        for _ in 0..FOUR_STEP_LAST - 2 {
+            apu.tick();
+        }
+        assert!(!apu.irq());
+        apu.tick();
+        assert!(apu.irq());
+
+        assert_eq!(apu.read_status() & 0b0100_0000, 0b0100_0000);
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn test_pal_frame_counter_and_pitch() {
+        let mut apu = APU::new();
+        apu.set_region(Region::Pal);
+        for _ in 0..PAL_FRAME_STEPS[3] - 1 {
+            apu.tick();
+        }
+        assert!(apu.irq());
+
+        // a second of PAL CPU cycles is still a second of samples
+        apu.take_samples();
+        for _ in 0..Region::Pal.cpu_clock() {
+            apu.tick();
+        }
+        assert!((apu.take_samples().len() as i64 - DEFAULT_SAMPLE_RATE as i64).abs() <= 1);
+    }
+
+    #[test]
+    fn test_inhibit_and_five_step_mode_have_no_irq() {
+        let mut apu = APU::new();
+        apu.write_register(0x4017, 0b0100_0000);
+        for _ in 0..FIVE_STEP_LAST * 2 {
+            apu.tick();
+        }
+        assert!(!apu.irq());
+
+        apu.write_register(0x4017, 0b1000_0000);
+        for _ in 0..FIVE_STEP_LAST * 2 {
+            apu.tick();
+        }
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn test_status_reports_length_counters() {
+        let mut apu = APU::new();
+        apu.write_register(0x4015, 0b0000_0101);
+        apu.write_register(0x4003, 0b0000_1000);
+        apu.write_register(0x400B, 0b0000_1000);
+        apu.write_register(0x400F, 0b0000_1000); // noise isn't enabled, doesn't load
+        assert_eq!(apu.read_status(), 0b0000_0101);
+
+        apu.write_register(0x4015, 0);
+        assert_eq!(apu.read_status(), 0);
+    }
+
+    #[test]
+    fn test_length_counter_runs_out_on_half_frames() {
+        let mut apu = APU::new();
+        apu.write_register(0x4015, 0b0000_0001);
+        apu.write_register(0x4003, 0b0001_1000); // index 3, length 2
+
+        // two half frames in 4 step mode is one whole sequence
+        for _ in 0..FOUR_STEP_LAST + 1 {
+            apu.tick();
+        }
+        assert_eq!(apu.read_status() & 1, 0);
+    }
+
+    #[test]
+    fn test_samples_come_out_at_the_sample_rate() {
+        let mut apu = APU::new();
+        apu.set_sample_rate(48_000);
+        apu.write_register(0x4015, 0b0000_0001);
+        apu.write_register(0x4000, 0b1011_1111);
+        apu.write_register(0x4002, 0xFD); // about 440Hz
+        apu.write_register(0x4003, 0b0000_1000);
+
+        let mut samples = Vec::new();
+        for _ in 0..4 {
+            for _ in 0..CPU_CLOCK_NTSC / 4 {
+                apu.tick();
+            }
+            samples.extend(apu.take_samples());
+        }
+
+        assert!((samples.len() as i64 - 48_000).abs() <= 1);
+        // the high passes pull the square down around 0
+        let mean = samples[24_000..].iter().sum::<f32>() / 24_000.0;
+        assert!(mean.abs() < 0.01);
+        assert!(samples.iter().any(|&s| s > 0.05) && samples.iter().any(|&s| s < -0.05));
+    }
+
+    #[test]
+    fn test_state_from_another_sample_rate() {
+        let square = |apu: &mut APU| {
+            apu.write_register(0x4015, 0b0000_0001);
+            apu.write_register(0x4000, 0b1011_1111);
+            apu.write_register(0x4002, 0xFD);
+            apu.write_register(0x4003, 0b0000_1000);
+            for _ in 0..10_000 {
+                apu.tick();
+            }
+        };
+        let mut saved = APU::new();
+        square(&mut saved);
+        let mut w = StateWriter::headerless();
+        saved.save(&mut w);
+        let state = w.finish();
+
+        let mut apu = APU::new();
+        apu.set_sample_rate(48_000);
+        square(&mut apu);
+        assert!(!apu.mix.samples.is_empty());
+        apu.load(&mut StateReader::new(&state)).unwrap();
+        assert_eq!(apu.sample_rate(), 48_000);
+        assert!(apu.mix.samples.is_empty());
+        // starts over from silence
+        assert_eq!(apu.mix.level, 0.0);
+        assert!(apu.take_samples().iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn test_mixer() {
+        assert_eq!(mix(0, 0, 0, 0, 0), 0.0);
+        let loudest = mix(15, 15, 15, 15, 127);
+        assert!(loudest > 0.95 && loudest < 1.05);
+        // two pulses at once is less than twice as loud
+        assert!(mix(15, 15, 0, 0, 0) < 2.0 * mix(15, 0, 0, 0, 0));
+    }
+}
@@ -0,0 +1,138 @@
+use super::units::{Envelope, LengthCounter};
+use crate::savestate::{Snapshot, StateReader, StateWriter};
+
+// in CPU cycles
+const PERIOD_TABLE: [u16; 16] = [4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068];
+const PAL_PERIOD_TABLE: [u16; 16] = [4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778];
+
+#[derive(Clone)]
+pub struct Noise {
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+    short_mode: bool, // feedback from bit 6 instead of bit 1, gives the metallic 93 step loop
+    shift_register: u16,
+    timer: u16,
+    period: u16,
+    periods: &'static [u16; 16],
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Noise::new()
+    }
+}
+
+impl Noise {
+    pub fn new() -> Self {
+        Noise {
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+            short_mode: false,
+            shift_register: 1,
+            timer: 0,
+            period: PERIOD_TABLE[0],
+            periods: &PERIOD_TABLE,
+        }
+    }
+
+    pub fn set_pal(&mut self, pal: bool) {
+        self.periods = if pal { &PAL_PERIOD_TABLE } else { &PERIOD_TABLE };
+    }
+
+    // register is 0-3 for $400C-$400F, $400D does nothing
+    pub fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.envelope.write(data);
+                self.length.halt = data & 0b0010_0000 != 0;
+            },
+            1 => {},
+            2 => {
+                self.short_mode = data & 0b1000_0000 != 0;
+                self.period = self.periods[(data & 0x0F) as usize];
+            },
+            _ => {
+                self.length.load(data >> 3);
+                self.envelope.start = true;
+            },
+        }
+    }
+
+    // every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+
+        self.timer = self.period - 1;
+        let tap = if self.short_mode { 6 } else { 1 };
+        let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 1;
+        self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.length.active() || self.shift_register & 1 != 0 {
+            return 0;
+        }
+        self.envelope.output()
+    }
+}
+
+// the period table comes from the region
+impl Snapshot for Noise {
+    fn save(&self, w: &mut StateWriter) {
+        self.envelope.save(w);
+        self.length.save(w);
+        w.bool(self.short_mode);
+        w.u16(self.shift_register);
+        w.u16(self.timer);
+        w.u16(self.period);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.envelope.load(r)?;
+        Snapshot::load(&mut self.length, r)?;
+        self.short_mode = r.bool()?;
+        self.shift_register = r.u16()?;
+        self.timer = r.u16()?;
+        self.period = r.u16()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sequence_length(short_mode: bool) -> usize {
+        let mut noise = Noise::new();
+        noise.write_register(2, if short_mode { 0x80 } else { 0x00 });
+
+        let start = noise.shift_register;
+        let mut steps = 0;
+        loop {
+            for _ in 0..noise.period {
+                noise.clock_timer();
+            }
+            steps += 1;
+            if noise.shift_register == start {
+                return steps;
+            }
+        }
+    }
+
+    #[test]
+    fn test_lfsr_periods() {
+        assert_eq!(sequence_length(false), 32767);
+        assert_eq!(sequence_length(true), 93);
+    }
+}
@@ -0,0 +1,206 @@
+use super::units::{Envelope, LengthCounter};
+use crate::savestate::{Snapshot, StateReader, StateWriter};
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0], // 12.5%
+    [0, 1, 1, 0, 0, 0, 0, 0], // 25%
+    [0, 1, 1, 1, 1, 0, 0, 0], // 50%
+    [1, 0, 0, 1, 1, 1, 1, 1], // 25% negated
+];
+
+#[derive(Clone)]
+pub struct Pulse {
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+    // pulse 1 negates with ones' complement, so sweeping down goes one further
+    ones_complement: bool,
+    duty: u8,
+    step: u8,
+    timer: u16,
+    period: u16,
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    pub fn new(ones_complement: bool) -> Self {
+        Pulse {
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+            ones_complement,
+            duty: 0,
+            step: 0,
+            timer: 0,
+            period: 0,
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_divider: 0,
+            sweep_reload: false,
+        }
+    }
+
+    // register is 0-3, the low bits of $4000-$4003 / $4004-$4007
+    pub fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.duty = data >> 6;
+                self.envelope.write(data);
+                self.length.halt = data & 0b0010_0000 != 0;
+            },
+            1 => {
+                self.sweep_enabled = data & 0b1000_0000 != 0;
+                self.sweep_period = (data >> 4) & 0b111;
+                self.sweep_negate = data & 0b0000_1000 != 0;
+                self.sweep_shift = data & 0b111;
+                self.sweep_reload = true;
+            },
+            2 => self.period = (self.period & 0x700) | data as u16,
+            _ => {
+                self.period = (self.period & 0x0FF) | ((data as u16 & 0b111) << 8);
+                self.length.load(data >> 3);
+                self.step = 0;
+                self.envelope.start = true;
+            },
+        }
+    }
+
+    // once every APU cycle (2 CPU cycles)
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.sweep_muting() {
+            self.period = self.sweep_target();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    fn sweep_target(&self) -> u16 {
+        let change = self.period >> self.sweep_shift;
+        if !self.sweep_negate {
+            self.period + change
+        } else if self.ones_complement {
+            self.period.saturating_sub(change + 1)
+        } else {
+            self.period.saturating_sub(change)
+        }
+    }
+
+    // the sweep unit mutes the channel even when it's disabled
+    fn sweep_muting(&self) -> bool {
+        self.period < 8 || self.sweep_target() > 0x7FF
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.length.active() || self.sweep_muting() || DUTY_TABLE[self.duty as usize][self.step as usize] == 0 {
+            return 0;
+        }
+        self.envelope.output()
+    }
+}
+
+// which pulse it is doesn't change, so ones_complement stays out
+impl Snapshot for Pulse {
+    fn save(&self, w: &mut StateWriter) {
+        self.envelope.save(w);
+        self.length.save(w);
+        w.u8(self.duty);
+        w.u8(self.step);
+        w.u16(self.timer);
+        w.u16(self.period);
+        w.bool(self.sweep_enabled);
+        w.u8(self.sweep_period);
+        w.bool(self.sweep_negate);
+        w.u8(self.sweep_shift);
+        w.u8(self.sweep_divider);
+        w.bool(self.sweep_reload);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.envelope.load(r)?;
+        Snapshot::load(&mut self.length, r)?;
+        self.duty = r.u8()?;
+        self.step = r.u8()?;
+        self.timer = r.u16()?;
+        self.period = r.u16()?;
+        self.sweep_enabled = r.bool()?;
+        self.sweep_period = r.u8()?;
+        self.sweep_negate = r.bool()?;
+        self.sweep_shift = r.u8()?;
+        self.sweep_divider = r.u8()?;
+        self.sweep_reload = r.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn playing(ones_complement: bool) -> Pulse {
+        let mut pulse = Pulse::new(ones_complement);
+        pulse.length.set_enabled(true);
+        pulse.write_register(0, 0b1011_1111); // 50% duty, halted, constant volume 15
+        pulse.write_register(2, 0x00);
+        pulse.write_register(3, 0b0000_1001); // period $100
+        pulse
+    }
+
+    #[test]
+    fn test_duty_cycle() {
+        let mut pulse = playing(false);
+        let mut wave = Vec::new();
+        for _ in 0..8 {
+            wave.push(pulse.output());
+            for _ in 0..=0x100 {
+                pulse.clock_timer();
+            }
+        }
+        assert_eq!(wave, vec![0, 15, 15, 15, 15, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_sweep_negate_differs_between_channels() {
+        let mut pulse1 = playing(true);
+        let mut pulse2 = playing(false);
+        for pulse in [&mut pulse1, &mut pulse2] {
+            pulse.write_register(1, 0b1000_1001); // enabled, period 0, negate, shift 1
+            pulse.clock_half_frame();
+        }
+        assert_eq!(pulse1.period, 0x100 - 0x80 - 1);
+        assert_eq!(pulse2.period, 0x100 - 0x80);
+    }
+
+    #[test]
+    fn test_sweep_overflow_mutes() {
+        let mut pulse = playing(false);
+        pulse.write_register(2, 0xFF);
+        pulse.write_register(3, 0b0000_1111); // period $7FF, any upward sweep overflows
+        pulse.write_register(1, 0b0000_0001); // sweep disabled but still mutes
+        pulse.step = 1;
+        assert_eq!(pulse.output(), 0);
+    }
+}
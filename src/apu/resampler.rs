@@ -0,0 +1,155 @@
+use crate::savestate::{Snapshot, StateReader, StateWriter};
+
+// band limited step synthesis, the same idea as blip_buf. the APU only tells us when its output
+// changes and by how much. each change gets spread over a few output samples using a windowed
+// sinc, so we never have to run anything at the 1.79MHz CPU rate and squares don't alias
+
+const PHASES: usize = 32; // fractional sample positions the kernel is tabulated at
+const WIDTH: usize = 16; // output samples each step touches
+const CUTOFF: f64 = 0.45; // of the output rate, just under nyquist
+
+pub struct Resampler {
+    clock_rate: f64,
+    sample_rate: u32,
+    kernel: Vec<[f32; WIDTH]>,
+    offset: f64, // where clock 0 of the current block lands, in output samples
+    deltas: Vec<f32>, // index 0 is the next sample to come out
+    integrator: f32,
+}
+
+impl Resampler {
+    pub fn new(clock_rate: f64, sample_rate: u32) -> Self {
+        Resampler {
+            clock_rate,
+            sample_rate,
+            kernel: build_kernel(),
+            offset: 0.0,
+            deltas: vec![0.0; WIDTH],
+            integrator: 0.0,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn clock_rate(&self) -> f64 {
+        self.clock_rate
+    }
+
+    // output samples per input clock
+    fn factor(&self) -> f64 {
+        self.sample_rate as f64 / self.clock_rate
+    }
+
+    // the input jumped by delta, clock cycles into the current block
+    pub fn add_delta(&mut self, clock: u64, delta: f32) {
+        let position = self.offset + clock as f64 * self.factor();
+        let index = position as usize;
+        let phase = ((position - index as f64) * PHASES as f64) as usize;
+
+        if self.deltas.len() < index + WIDTH {
+            self.deltas.resize(index + WIDTH, 0.0);
+        }
+        for (slot, k) in self.deltas[index..index + WIDTH].iter_mut().zip(self.kernel[phase]) {
+            *slot += delta * k;
+        }
+    }
+
+    // finishes the block `clocks` long and appends every sample that's now final
+    pub fn end_block(&mut self, clocks: u64, out: &mut Vec<f32>) {
+        let end = self.offset + clocks as f64 * self.factor();
+        let ready = end as usize;
+
+        if self.deltas.len() < ready + WIDTH {
+            self.deltas.resize(ready + WIDTH, 0.0);
+        }
+        for delta in self.deltas.drain(..ready) {
+            self.integrator += delta;
+            out.push(self.integrator);
+        }
+        self.offset = end - ready as f64;
+    }
+}
+
+// each phase is a low passed impulse, summed up by the integrator it turns into a smooth step
+fn build_kernel() -> Vec<[f32; WIDTH]> {
+    (0..PHASES)
+        .map(|phase| {
+            let frac = phase as f64 / PHASES as f64;
+            let mut taps = [0.0f64; WIDTH];
+            for (k, tap) in taps.iter_mut().enumerate() {
+                // centred so the step lands WIDTH / 2 - 1 samples late
+                let t = k as f64 - (WIDTH / 2 - 1) as f64 - frac;
+                *tap = 2.0 * CUTOFF * sinc(2.0 * CUTOFF * t) * blackman(t);
+            }
+
+            // every phase has to add up to exactly 1 or steps drift
+            let sum: f64 = taps.iter().sum();
+            taps.map(|tap| (tap / sum) as f32)
+        })
+        .collect()
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 { 1.0 } else { (std::f64::consts::PI * x).sin() / (std::f64::consts::PI * x) }
+}
+
+fn blackman(t: f64) -> f64 {
+    let half = WIDTH as f64 / 2.0;
+    if t.abs() >= half {
+        return 0.0;
+    }
+    let x = std::f64::consts::PI * t / half;
+    0.42 + 0.5 * x.cos() + 0.08 * (2.0 * x).cos()
+}
+
+// the kernel and the rates are rebuilt from the sample rate, only the steps in flight are saved
+impl Snapshot for Resampler {
+    fn save(&self, w: &mut StateWriter) {
+        w.f64(self.offset);
+        w.u32(self.deltas.len() as u32);
+        for &delta in &self.deltas {
+            w.f32(delta);
+        }
+        w.f32(self.integrator);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.offset = r.f64()?;
+        let len = r.u32()? as usize;
+        self.deltas = (0..len).map(|_| r.f32()).collect::<Result<_, _>>()?;
+        self.integrator = r.f32()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sample_count_doesnt_drift() {
+        let mut resampler = Resampler::new(1_789_773.0, 44_100);
+        let mut out = Vec::new();
+        // a second's worth of 60Hz frames that don't divide evenly
+        for _ in 0..60 {
+            resampler.end_block(29_830, &mut out);
+        }
+        let expected = 29_830.0 * 60.0 * 44_100.0 / 1_789_773.0;
+        assert!((out.len() as f64 - expected).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_step_settles_at_its_height() {
+        let mut resampler = Resampler::new(1_789_773.0, 48_000);
+        let mut out = Vec::new();
+        resampler.add_delta(1000, 0.5);
+        resampler.end_block(10_000, &mut out);
+
+        assert_eq!(out[0], 0.0);
+        assert!((out.last().unwrap() - 0.5).abs() < 1e-4);
+        // ringing stays small
+        assert!(out.iter().all(|&s| (-0.05..0.55).contains(&s)));
+    }
+}
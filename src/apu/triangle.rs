@@ -0,0 +1,126 @@
+use super::units::LengthCounter;
+use crate::savestate::{Snapshot, StateReader, StateWriter};
+
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+#[derive(Default, Clone)]
+pub struct Triangle {
+    pub length: LengthCounter,
+    control: bool, // also halts the length counter
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+    step: u8,
+    timer: u16,
+    period: u16,
+}
+
+impl Triangle {
+    // register is 0-3 for $4008-$400B, $4009 does nothing
+    pub fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.control = data & 0b1000_0000 != 0;
+                self.length.halt = self.control;
+                self.linear_reload_value = data & 0b0111_1111;
+            },
+            1 => {},
+            2 => self.period = (self.period & 0x700) | data as u16,
+            _ => {
+                self.period = (self.period & 0x0FF) | ((data as u16 & 0b111) << 8);
+                self.length.load(data >> 3);
+                self.linear_reload = true;
+            },
+        }
+    }
+
+    // every CPU cycle, the triangle runs twice as fast as the pulses
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+
+        self.timer = self.period;
+        // with either counter at 0 the sequencer just stops where it is, it doesn't drop to 0
+        if self.length.active() && self.linear_counter > 0 {
+            self.step = (self.step + 1) % 32;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    pub fn output(&self) -> u8 {
+        SEQUENCE[self.step as usize]
+    }
+}
+
+impl Snapshot for Triangle {
+    fn save(&self, w: &mut StateWriter) {
+        self.length.save(w);
+        w.bool(self.control);
+        w.u8(self.linear_reload_value);
+        w.u8(self.linear_counter);
+        w.bool(self.linear_reload);
+        w.u8(self.step);
+        w.u16(self.timer);
+        w.u16(self.period);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        Snapshot::load(&mut self.length, r)?;
+        self.control = r.bool()?;
+        self.linear_reload_value = r.u8()?;
+        self.linear_counter = r.u8()?;
+        self.linear_reload = r.bool()?;
+        self.step = r.u8()?;
+        self.timer = r.u16()?;
+        self.period = r.u16()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_linear_counter_gates_the_sequencer() {
+        let mut triangle = Triangle::default();
+        triangle.length.set_enabled(true);
+        triangle.write_register(0, 0x02); // linear counter 2, not held
+        triangle.write_register(2, 0x00);
+        triangle.write_register(3, 0x08);
+
+        // nothing moves until a quarter frame loads the linear counter
+        triangle.clock_timer();
+        assert_eq!(triangle.output(), 15);
+
+        triangle.clock_quarter_frame();
+        triangle.clock_timer();
+        triangle.clock_timer();
+        assert_eq!(triangle.output(), 13);
+
+        triangle.clock_quarter_frame();
+        triangle.clock_quarter_frame();
+        triangle.clock_timer();
+        assert_eq!(triangle.output(), 13);
+    }
+}
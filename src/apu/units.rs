@@ -0,0 +1,158 @@
+use crate::savestate::{Snapshot, StateReader, StateWriter};
+
+// pieces the channels share
+
+// what the top 5 bits of $4003/$4007/$400B/$400F pick
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+// silences a channel after a while, clocked on half frames
+#[derive(Default, Clone)]
+pub struct LengthCounter {
+    pub counter: u8,
+    pub halt: bool,
+    enabled: bool,
+}
+
+impl LengthCounter {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    pub fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(index & 0x1F) as usize];
+        }
+    }
+
+    pub fn clock(&mut self) {
+        if self.counter > 0 && !self.halt {
+            self.counter -= 1;
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.counter > 0
+    }
+}
+
+// volume that either stays put or decays from 15, clocked on quarter frames
+#[derive(Default, Clone)]
+pub struct Envelope {
+    pub start: bool,
+    pub looping: bool, // same bit as the length counter halt
+    pub constant: bool,
+    pub volume: u8, // the constant volume, or the divider period when decaying
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    // the low 6 bits of $4000/$4004/$400C
+    pub fn write(&mut self, data: u8) {
+        self.looping = data & 0b0010_0000 != 0;
+        self.constant = data & 0b0001_0000 != 0;
+        self.volume = data & 0b0000_1111;
+    }
+
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+            return;
+        }
+
+        if self.divider > 0 {
+            self.divider -= 1;
+            return;
+        }
+
+        self.divider = self.volume;
+        if self.decay > 0 {
+            self.decay -= 1;
+        } else if self.looping {
+            self.decay = 15;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.constant { self.volume } else { self.decay }
+    }
+}
+
+impl Snapshot for LengthCounter {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.counter);
+        w.bool(self.halt);
+        w.bool(self.enabled);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.counter = r.u8()?;
+        self.halt = r.bool()?;
+        self.enabled = r.bool()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Envelope {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.start);
+        w.bool(self.looping);
+        w.bool(self.constant);
+        w.u8(self.volume);
+        w.u8(self.divider);
+        w.u8(self.decay);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.start = r.bool()?;
+        self.looping = r.bool()?;
+        self.constant = r.bool()?;
+        self.volume = r.u8()?;
+        self.divider = r.u8()?;
+        self.decay = r.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_length_counter_only_loads_when_enabled() {
+        let mut length = LengthCounter::default();
+        length.load(1);
+        assert_eq!(length.counter, 0);
+
+        length.set_enabled(true);
+        length.load(1);
+        assert_eq!(length.counter, 254);
+
+        length.set_enabled(false);
+        assert!(!length.active());
+    }
+
+    #[test]
+    fn test_envelope_decays_and_loops() {
+        let mut envelope = Envelope::default();
+        envelope.write(0b0010_0000); // loop, period 0
+        envelope.start = true;
+
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+        for _ in 0..15 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.output(), 0);
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+    }
+}
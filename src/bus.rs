@@ -0,0 +1,732 @@
+use std::any::Any;
+
+use crate::apu::APU;
+use crate::cartridge::Cartridge;
+use crate::clock::MasterClock;
+use crate::events::{Access, Event, EventLog};
+use crate::input::{InputContext, InputDevice, InputSetup};
+use crate::joypad::ButtonState;
+use crate::palette::Palette;
+use crate::vgm::VgmLogger;
+use crate::ppu::PPU;
+use crate::power_on::RamInit;
+use crate::region::Region;
+use crate::savestate::{Snapshot, StateReader, StateWriter};
+
+/*
+ CPU memory map
+ _______________ $10000
+| PRG ROM       |
+|_______________| $8000
+| PRG RAM       |
+|_______________| $6000
+| expansion     |
+|_______________| $4020
+| APU / IO      |
+|_______________| $4000
+| PPU registers | (mirrored every 8 bytes)
+|_______________| $2000
+| RAM           | (2KB mirrored 4 times)
+|_______________| $0000
+*/
+const RAM: u16 = 0x0000;
+const RAM_MIRRORS_END: u16 = 0x1FFF;
+const PPU_REGISTERS: u16 = 0x2000;
+const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
+const OAM_DMA: u16 = 0x4014;
+const APU_STATUS: u16 = 0x4015;
+const JOYPAD_1: u16 = 0x4016;
+const JOYPAD_2: u16 = 0x4017;
+
+// everything the CPU talks to goes through this
+pub trait Mem {
+    fn mem_read(&mut self, addr: u16) -> u8;
+
+    fn mem_write(&mut self, addr: u16, data: u8);
+
+    // the CPU just spent this many cycles, everything else gets to catch up
+    fn tick(&mut self, _cycles: u16) {}
+
+    // a cycle with a read or write in it. the CPU calls begin_cycle, makes the access, then
+    // calls end_cycle, so whatever it touches has been run up to that point of the cycle.
+    // the cycles counted like this are taken off the instruction's total before tick
+    fn begin_cycle(&mut self, _read: bool) {}
+
+    fn end_cycle(&mut self, _read: bool) {
+        self.tick(1);
+    }
+
+    // both polls are what the CPU would have seen at the end of the cycle before last, an
+    // interrupt that turns up on an instruction's final cycle waits for the next one
+    fn poll_nmi(&mut self) -> bool {
+        false
+    }
+
+    // IRQ is a level, it stays up until whoever raised it is acknowledged
+    fn poll_irq(&mut self) -> bool {
+        false
+    }
+
+    // cycles the CPU has to sit out, e.g. while OAM DMA is copying
+    fn take_stall_cycles(&mut self) -> u16 {
+        0
+    }
+
+    // where the CPU is about to run its next instruction from
+    fn begin_instruction(&mut self, _pc: u16, _cycles: u64) {}
+}
+
+// plain 64KB of RAM and nothing else. handy for running bare 6502 programs
+pub struct FlatMemory {
+    memory: [u8; 0x10000],
+}
+
+impl FlatMemory {
+    pub fn new() -> Self {
+        FlatMemory { memory: [0; 0x10000] }
+    }
+}
+
+impl Default for FlatMemory {
+    fn default() -> Self {
+        FlatMemory::new()
+    }
+}
+
+impl Mem for FlatMemory {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        self.memory[addr as usize] = data
+    }
+}
+
+// the actual NES
+pub struct Bus {
+    cpu_vram: [u8; 2048],
+    pub cartridge: Cartridge,
+    pub ppu: PPU,
+    pub apu: APU,
+    pub ports: [Box<dyn InputDevice>; 2],
+    pub expansion: Option<Box<dyn InputDevice>>, // the Famicom's expansion port
+    pub microphone: bool, // the mic on the Famicom's second controller, see input/microphone.rs
+    pub event_log: Option<EventLog>, // Some while recording
+    pub vgm_log: Option<VgmLogger>,
+    region: Region,
+    palette: Palette, // what the Zapper sees the frame in
+    custom_palette: Option<Palette>,
+    clock: MasterClock,
+    nmi_line: bool, // the PPU's NMI output as of the last cycle
+    nmi_edge: bool, // it went up and the CPU hasn't taken it yet
+    nmi_poll: bool, // nmi_edge a cycle ago, what the CPU sees
+    irq_line: bool,
+    irq_poll: bool,
+    stall_cycles: u16,
+    oam_dma_left: u16, // cycles of OAM DMA still to run, counted down as the stall goes by
+    open_bus: u8, // whatever was last on the data bus, for the bits nothing drives
+    input_polled: bool, // $4016 or $4017 was read since the last take_input_polled
+    last_access: Option<(u16, Access)>,
+    cpu_pc: u16,
+    cpu_cycles: u64, // counts along during an instruction, so it's the cycle of each access
+}
+
+impl Bus {
+    pub fn new(cartridge: Cartridge) -> Self {
+        let ppu = PPU::new(cartridge.mirroring());
+        let input = InputSetup::from_header(cartridge.input_device);
+        let region = cartridge.region;
+
+        let mut bus = Bus {
+            cpu_vram: [0; 2048],
+            cartridge,
+            ppu,
+            apu: APU::new(),
+            ports: input.ports,
+            expansion: input.expansion,
+            microphone: false,
+            event_log: None,
+            vgm_log: None,
+            region,
+            palette: Palette::new(region.ppu_model()),
+            custom_palette: None,
+            clock: MasterClock::default(),
+            nmi_line: false,
+            nmi_edge: false,
+            nmi_poll: false,
+            irq_line: false,
+            irq_poll: false,
+            stall_cycles: 0,
+            oam_dma_left: 0,
+            open_bus: 0,
+            input_polled: false,
+            last_access: None,
+            cpu_pc: 0,
+            cpu_cycles: 0,
+        };
+        bus.set_region(region);
+        bus
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    // meant for power on, the PPU and APU carry on from wherever they were
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.clock = MasterClock::new(region.cpu_divider(), region.ppu_divider());
+        self.ppu.set_region(region);
+        self.apu.set_region(region);
+        self.palette = self.custom_palette.clone().unwrap_or_else(|| Palette::new(region.ppu_model()));
+    }
+
+    // a .pal the frames are shown with, for devices that look at the screen. None goes back to
+    // the region's own
+    pub fn set_palette(&mut self, palette: Option<Palette>) {
+        self.custom_palette = palette;
+        self.palette = self.custom_palette.clone().unwrap_or_else(|| Palette::new(self.region.ppu_model()));
+    }
+
+    // fills every RAM in the console and on the cartridge, for right after power on
+    pub fn init_ram(&mut self, init: RamInit) {
+        let mut fill = init.filler();
+        fill.fill(&mut self.cpu_vram);
+        self.ppu.init_ram(&mut fill);
+        self.cartridge.init_ram(&mut fill);
+    }
+
+    // port 0 or 1, what's held down until the next call. 2 and 3 are players 3 and 4 on a
+    // Four Score or the Famicom adapter. there's no port past that, so those are ignored
+    pub fn set_buttons(&mut self, port: usize, buttons: ButtonState) {
+        match port {
+            0 | 1 => self.ports[port].set_buttons(0, buttons),
+            2 | 3 => {
+                self.ports[port - 2].set_buttons(1, buttons);
+                if let Some(expansion) = &mut self.expansion {
+                    expansion.set_buttons(port - 2, buttons);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    // port 0 or 1, anything else is ignored
+    pub fn plug(&mut self, port: usize, device: Box<dyn InputDevice>) {
+        if let Some(slot) = self.ports.get_mut(port) {
+            *slot = device;
+        }
+    }
+
+    pub fn plug_expansion(&mut self, device: Option<Box<dyn InputDevice>>) {
+        self.expansion = device;
+    }
+
+    // the device on port 0 or 1, or 2 for the expansion port, if it's a T
+    pub fn device_mut<T: InputDevice>(&mut self, port: usize) -> Option<&mut T> {
+        let device: &mut dyn InputDevice = match port {
+            0 | 1 => self.ports[port].as_mut(),
+            2 => self.expansion.as_deref_mut()?,
+            _ => return None,
+        };
+        (device as &mut dyn Any).downcast_mut::<T>()
+    }
+
+    // everything plugged in drives D0-D4 together, the top 3 bits are left over from the
+    // last thing on the bus, usually the $40 of the address
+    fn read_port(&mut self, port: usize) -> u8 {
+        let ctx = InputContext { frame: &self.ppu.frame, palette: &self.palette, scanline: self.ppu.scanline, dot: self.ppu.dot };
+        let mut data = self.ports[port].read(port, &ctx);
+        if let Some(expansion) = &mut self.expansion {
+            data |= expansion.read(port, &ctx);
+        }
+        if port == 0 && self.microphone {
+            data |= 0b100;
+        }
+        self.input_polled = true;
+        self.open_bus & 0xE0 | data & 0x1F
+    }
+
+    // whether the game read the controllers since the last call, a frame without a read is a lag frame
+    pub fn take_input_polled(&mut self) -> bool {
+        std::mem::take(&mut self.input_polled)
+    }
+
+    pub fn start_recording(&mut self) {
+        let mut log = EventLog::new();
+        log.scanlines = self.region.scanlines();
+        self.event_log = Some(log);
+    }
+
+    pub fn stop_recording(&mut self) -> Option<EventLog> {
+        self.event_log.take()
+    }
+
+    pub fn start_vgm_log(&mut self) {
+        self.vgm_log = Some(VgmLogger::with_region(self.cpu_cycles, self.region));
+    }
+
+    pub fn mark_vgm_loop(&mut self) {
+        let cycle = self.cpu_cycles;
+        if let Some(log) = &mut self.vgm_log {
+            log.mark_loop(cycle);
+        }
+    }
+
+    // the finished VGM file
+    pub fn stop_vgm_log(&mut self) -> Option<Vec<u8>> {
+        let cycle = self.cpu_cycles;
+        self.vgm_log.take().map(|log| log.finish(cycle))
+    }
+
+    fn log_apu_write(&mut self, addr: u16, data: u8) {
+        let cycle = self.cpu_cycles;
+        let Some(log) = &mut self.vgm_log else {
+            return;
+        };
+
+        // starting the DMC, the player needs the sample before the write that plays it
+        if addr == APU_STATUS && data & 0b0001_0000 != 0 {
+            let (start, len) = self.apu.dmc.sample_range();
+            let bytes: Vec<u8> = (0..len)
+                .map(|i| {
+                    let offset = (start as u32 + i as u32 - 0x8000) % 0x8000;
+                    self.cartridge.cpu_read(0x8000 + offset as u16).unwrap_or(0)
+                })
+                .collect();
+            log.dmc_sample(cycle, start, &bytes);
+        }
+        log.write(cycle, addr, data);
+    }
+
+    fn record(&mut self, addr: u16, value: u8, access: Access) {
+        let is_register = match addr {
+            PPU_REGISTERS ..= PPU_REGISTERS_MIRRORS_END => true,
+            OAM_DMA => access == Access::Write,
+            // PRG RAM and reads out of PRG ROM aren't registers
+            0x4020 ..= 0x5FFF => true,
+            0x8000 ..= 0xFFFF => access == Access::Write,
+            _ => false,
+        };
+        if !is_register {
+            return;
+        }
+
+        if let Some(log) = &mut self.event_log {
+            log.events.push(Event {
+                frame: self.ppu.frame_count,
+                scanline: self.ppu.scanline,
+                dot: self.ppu.dot,
+                cycle: self.cpu_cycles,
+                pc: self.cpu_pc,
+                addr,
+                value,
+                access,
+            });
+        }
+    }
+
+    fn run_ppu(&mut self, dots: u32) {
+        for _ in 0..dots {
+            self.ppu.tick(&mut self.cartridge);
+        }
+    }
+
+    fn oam_dma(&mut self, page: u8) {
+        let base = (page as u16) << 8;
+        let mut data = [0u8; 256];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = self.mem_read(base + i as u16);
+        }
+        self.ppu.write_oam_dma(&data);
+
+        // 512 cycles of copying plus one waiting for the write to finish, and another to get
+        // back in step with the read/write pairs if it would start on an odd cycle
+        let starts_odd = !(self.cpu_cycles + 1).is_multiple_of(2);
+        let cycles = if starts_odd { 514 } else { 513 };
+        self.stall_cycles += cycles;
+        self.oam_dma_left = cycles;
+    }
+
+    // the DMC grabs the bus for a sample byte by halting the CPU on a read cycle. the CPU
+    // keeps repeating the read it was halted on, which is how $2007 and the controllers
+    // lose data when a sample is playing. the halted access is the last one the CPU made
+    // before the cycle the DMA turned up on
+    fn dmc_dma(&mut self, addr: u16) {
+        let stolen = match self.last_access {
+            _ if self.oam_dma_left > 0 => 2, // lands in the middle of OAM DMA
+            Some((_, Access::Write)) => 3, // waits for the write to finish instead of repeating it
+            _ => 4,
+        };
+
+        if let Some((last, Access::Read)) = self.last_access {
+            self.mem_read(last);
+        }
+
+        let data = self.cartridge.cpu_read(addr).unwrap_or(self.open_bus);
+        self.open_bus = data;
+        self.apu.dmc.fill(data);
+        self.stall_cycles += stolen;
+    }
+}
+
+impl Mem for Bus {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        let data = match addr {
+            RAM ..= RAM_MIRRORS_END => {
+                self.cpu_vram[(addr & 0b0000_0111_1111_1111) as usize]
+            },
+            PPU_REGISTERS ..= PPU_REGISTERS_MIRRORS_END => {
+                self.ppu.read_register(addr & 0x2007, &mut self.cartridge)
+            },
+            // $4015 is inside the CPU, it never reaches the data bus and bit 5 isn't driven
+            APU_STATUS => self.apu.read_status() | self.open_bus & 0b0010_0000,
+            JOYPAD_1 => self.read_port(0),
+            JOYPAD_2 => self.read_port(1),
+            // nothing answers, the value from the last access is still floating on the bus
+            0x4000 ..= 0x401F => self.open_bus,
+            _ => self.cartridge.cpu_read(addr).unwrap_or(self.open_bus),
+        };
+
+        if self.event_log.is_some() {
+            self.record(addr, data, Access::Read);
+        }
+        self.last_access = Some((addr, Access::Read));
+        if addr != APU_STATUS {
+            self.open_bus = data;
+        }
+        data
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        if self.event_log.is_some() {
+            self.record(addr, data, Access::Write);
+        }
+        self.last_access = Some((addr, Access::Write));
+        self.open_bus = data;
+
+        match addr {
+            RAM ..= RAM_MIRRORS_END => {
+                self.cpu_vram[(addr & 0b0000_0111_1111_1111) as usize] = data;
+            },
+            PPU_REGISTERS ..= PPU_REGISTERS_MIRRORS_END => {
+                self.ppu.write_register(addr & 0x2007, data, &mut self.cartridge);
+            },
+            OAM_DMA => self.oam_dma(data),
+            JOYPAD_1 => {
+                for device in &mut self.ports {
+                    device.write(data);
+                }
+                if let Some(expansion) = &mut self.expansion {
+                    expansion.write(data);
+                }
+            },
+            0x4000 ..= 0x4013 | APU_STATUS | 0x4017 => {
+                if self.vgm_log.is_some() {
+                    self.log_apu_write(addr, data);
+                }
+                self.apu.write_register(addr, data);
+            },
+            0x4000 ..= 0x401F => {},
+            _ => {
+                self.cartridge.cpu_write(addr, data);
+                self.ppu.set_mirroring(self.cartridge.mirroring());
+            },
+        }
+    }
+
+    fn tick(&mut self, cycles: u16) {
+        for _ in 0..cycles {
+            self.begin_cycle(true);
+            self.end_cycle(true);
+        }
+    }
+
+    fn begin_cycle(&mut self, read: bool) {
+        let dots = self.clock.begin_cycle(read);
+        self.run_ppu(dots);
+    }
+
+    fn end_cycle(&mut self, read: bool) {
+        let dots = self.clock.end_cycle(read);
+        self.run_ppu(dots);
+
+        self.cartridge.tick();
+        self.apu.expansion_output = self.cartridge.audio_output();
+        self.apu.tick();
+        if let Some(addr) = self.apu.dmc.dma_address() {
+            self.dmc_dma(addr);
+        }
+        // the stall is all reads, which leaves out the $4014 write that started it
+        if read {
+            self.oam_dma_left = self.oam_dma_left.saturating_sub(1);
+        }
+
+        // NMI is edge triggered and IRQ level triggered, both get sampled at the end of every
+        // cycle and the CPU acts on what was there a cycle before it finishes an instruction
+        self.nmi_poll = self.nmi_edge;
+        self.irq_poll = self.irq_line;
+        let nmi_line = self.ppu.nmi_line();
+        if nmi_line && !self.nmi_line {
+            self.nmi_edge = true;
+        }
+        self.nmi_line = nmi_line;
+        self.irq_line = self.apu.irq() || self.cartridge.irq();
+        self.cpu_cycles += 1;
+    }
+
+    fn poll_nmi(&mut self) -> bool {
+        if !self.nmi_poll {
+            return false;
+        }
+        self.nmi_poll = false;
+        self.nmi_edge = false;
+        true
+    }
+
+    fn poll_irq(&mut self) -> bool {
+        self.irq_poll
+    }
+
+    fn take_stall_cycles(&mut self) -> u16 {
+        std::mem::take(&mut self.stall_cycles)
+    }
+
+    fn begin_instruction(&mut self, pc: u16, cycles: u64) {
+        self.cpu_pc = pc;
+        self.cpu_cycles = cycles;
+    }
+}
+
+/*
+ the console's RAM and what the bus keeps between cycles. the PPU, APU, cartridge and input
+ devices are saved on their own, and the logs are tools rather than part of the machine
+*/
+impl Snapshot for Bus {
+    fn save(&self, w: &mut StateWriter) {
+        w.bytes(&self.cpu_vram);
+        self.clock.save(w);
+        w.bool(self.nmi_line);
+        w.bool(self.nmi_edge);
+        w.bool(self.nmi_poll);
+        w.bool(self.irq_line);
+        w.bool(self.irq_poll);
+        w.u16(self.stall_cycles);
+        w.u16(self.oam_dma_left);
+        w.u8(self.open_bus);
+        w.bool(self.input_polled);
+        w.option(&self.last_access, |w, &(addr, access)| {
+            w.u16(addr);
+            w.bool(access == Access::Write);
+        });
+        w.u16(self.cpu_pc);
+        w.u64(self.cpu_cycles);
+        w.bool(self.microphone);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        r.bytes(&mut self.cpu_vram)?;
+        self.clock.load(r)?;
+        self.nmi_line = r.bool()?;
+        self.nmi_edge = r.bool()?;
+        self.nmi_poll = r.bool()?;
+        self.irq_line = r.bool()?;
+        self.irq_poll = r.bool()?;
+        self.stall_cycles = r.u16()?;
+        self.oam_dma_left = r.u16()?;
+        self.open_bus = r.u8()?;
+        self.input_polled = r.bool()?;
+        self.last_access = r.option(|r| Ok((r.u16()?, if r.bool()? { Access::Write } else { Access::Read })))?;
+        self.cpu_pc = r.u16()?;
+        self.cpu_cycles = r.u64()?;
+        self.microphone = r.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use crate::cpu::CPU;
+    use crate::input::zapper::Zapper;
+
+    #[test]
+    fn test_dmc_dma_steals_cycles_and_repeats_reads() {
+        let mut bus = Bus::new(Cartridge::new(&test_rom(&[], &[])).unwrap());
+        bus.mem_write(0x2006, 0x20);
+        bus.mem_write(0x2006, 0x00);
+
+        bus.mem_write(0x4013, 0x00);
+        bus.mem_write(0x4015, 0b0001_0000);
+        bus.mem_read(0x2007);
+        bus.tick(1);
+
+        // the halted $2007 read went through twice
+        assert_eq!(bus.ppu.v, 0x2002);
+        assert_eq!(bus.take_stall_cycles(), 4);
+        assert!(bus.apu.dmc.dma_address().is_none());
+    }
+
+    #[test]
+    fn test_oam_dma_cycles() {
+        let mut bus = Bus::new(Cartridge::new(&test_rom(&[], &[])).unwrap());
+        // the write on cycle 11, the DMA starting on 12
+        bus.begin_instruction(0x8000, 11);
+        bus.mem_write(0x4014, 0x02);
+        assert_eq!(bus.take_stall_cycles(), 513);
+        bus.tick(513);
+
+        bus.begin_instruction(0x8000, 10);
+        bus.mem_write(0x4014, 0x02);
+        // a DMC fetch in the middle of it only costs 2 more
+        bus.mem_write(0x4013, 0x00);
+        bus.mem_write(0x4015, 0b0001_0000);
+        bus.tick(1);
+        assert_eq!(bus.take_stall_cycles(), 514 + 2);
+    }
+
+    // a CPU at $8000 running the program, with the PPU just short of vblank
+    fn cpu_near_vblank(program: &[u8], dot: u16) -> CPU<Bus> {
+        let mut cpu = CPU::with_bus(Bus::new(Cartridge::new(&test_rom(program, &[])).unwrap()));
+        cpu.reset();
+        cpu.bus.ppu.scanline = 240;
+        cpu.bus.ppu.dot = dot;
+        cpu
+    }
+
+    #[test]
+    fn test_register_reads_see_the_ppu_mid_instruction() {
+        // LDA $2002 reads on its 4th cycle, 10 dots in. vblank starts on the tick at 241:1
+        let read_at = |dot| {
+            let mut cpu = cpu_near_vblank(&[0xAD, 0x02, 0x20], dot);
+            cpu.step();
+            cpu.register_a & 0x80
+        };
+        assert_eq!(read_at(333), 0x80);
+        assert_eq!(read_at(332), 0);
+    }
+
+    #[test]
+    fn test_nmi_on_the_last_cycle_waits_an_instruction() {
+        // NOP; NOP; NOP, the handler is an RTI so taking an NMI shows up as 7 + 6 cycles
+        let cycles_per_step = |dot| {
+            let mut cpu = cpu_near_vblank(&[0xEA, 0xEA, 0xEA], dot);
+            cpu.bus.mem_write(0x2000, 0x80);
+            (0..3).map(|_| {
+                let before = cpu.cycles;
+                cpu.step();
+                cpu.cycles - before
+            }).collect::<Vec<u64>>()
+        };
+        // vblank in the first NOP's first cycle, then in its second and last
+        assert_eq!(cycles_per_step(340), vec![2, 13, 2]);
+        assert_eq!(cycles_per_step(339), vec![2, 2, 13]);
+    }
+
+    #[test]
+    fn test_joypad_reads() {
+        let mut bus = Bus::new(Cartridge::new(&test_rom(&[], &[])).unwrap());
+        bus.set_buttons(1, ButtonState { a: true, right: true, ..Default::default() });
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+
+        // as if the operand fetch of LDA $4017 had just put $40 on the bus
+        bus.open_bus = 0x40;
+        let bits: Vec<u8> = (0..10).map(|_| bus.mem_read(0x4017)).collect();
+        assert_eq!(bits, vec![0x41, 0x40, 0x40, 0x40, 0x40, 0x40, 0x40, 0x41, 0x41, 0x41]);
+
+        // port 1 has nothing held
+        assert_eq!(bus.mem_read(0x4016) & 1, 0);
+    }
+
+    #[test]
+    fn test_unmapped_reads_are_open_bus() {
+        let mut bus = Bus::new(Cartridge::new(&test_rom(&[], &[])).unwrap());
+        // LDA $4000, the high byte of the operand is the last thing read
+        bus.open_bus = 0x40;
+        assert_eq!(bus.mem_read(0x4000), 0x40);
+        assert_eq!(bus.mem_read(0x5000), 0x40);
+
+        // $4015 leaves the bus alone, and shows it through bit 5
+        bus.open_bus = 0xFF;
+        assert_eq!(bus.mem_read(0x4015) & 0b0010_0000, 0b0010_0000);
+        assert_eq!(bus.mem_read(0x401A), 0xFF);
+    }
+
+    #[test]
+    fn test_four_score_players_3_and_4() {
+        let mut raw = test_rom(&[], &[]);
+        raw[7] = 0b0000_1000; // NES 2.0
+        raw[15] = 0x02;
+        let mut bus = Bus::new(Cartridge::new(&raw).unwrap());
+        bus.set_buttons(2, ButtonState { a: true, ..Default::default() });
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+
+        let bits: Vec<u8> = (0..24).map(|_| bus.mem_read(0x4016) & 1).collect();
+        assert_eq!(bits[8], 1);
+        assert_eq!(bits[19], 1);
+        assert_eq!(bits.iter().filter(|&&bit| bit == 1).count(), 2);
+    }
+
+    #[test]
+    fn test_zapper_through_the_bus() {
+        let mut bus = Bus::new(Cartridge::new(&test_rom(&[], &[])).unwrap());
+        bus.plug(1, Box::new(Zapper::new()));
+        bus.device_mut::<Zapper>(1).unwrap().trigger = true;
+        assert!(bus.device_mut::<Zapper>(0).is_none());
+
+        // there are only 4 pads and 2 ports
+        bus.set_buttons(4, ButtonState { a: true, ..Default::default() });
+        bus.plug(2, Box::new(Zapper::new()));
+        assert!(bus.device_mut::<Zapper>(3).is_none());
+
+        assert_eq!(bus.mem_read(0x4017) & 0x1F, 0b0001_1000);
+    }
+
+    #[test]
+    fn test_zapper_sees_the_palette_in_use() {
+        let mut bus = Bus::new(Cartridge::new(&test_rom(&[], &[])).unwrap());
+        let mut zapper = Zapper::new();
+        zapper.aim = Some((100, 50));
+        bus.plug(1, Box::new(zapper));
+        bus.ppu.frame.set_pixel(100, 50, 0x30);
+        bus.ppu.scanline = 55;
+        assert_eq!(bus.mem_read(0x4017) & 0b1000, 0);
+
+        // a .pal where everything's dark stays on through a region change
+        bus.set_palette(Some(Palette::from_pal(&[0x20; 64 * 3]).unwrap()));
+        bus.set_region(Region::Pal);
+        bus.ppu.frame.set_pixel(100, 50, 0x30);
+        bus.ppu.scanline = 55;
+        assert_eq!(bus.mem_read(0x4017) & 0b1000, 0b1000);
+        bus.set_palette(None);
+        assert_eq!(bus.mem_read(0x4017) & 0b1000, 0);
+    }
+
+    #[test]
+    fn test_microphone_bit() {
+        let mut bus = Bus::new(Cartridge::new(&test_rom(&[], &[])).unwrap());
+        assert_eq!(bus.mem_read(0x4016) & 0b100, 0);
+        bus.microphone = true;
+        assert_eq!(bus.mem_read(0x4016) & 0b100, 0b100);
+        assert_eq!(bus.mem_read(0x4017) & 0b100, 0);
+    }
+
+    #[test]
+    fn test_vgm_log_sends_dmc_samples_before_playing_them() {
+        let mut bus = Bus::new(Cartridge::new(&test_rom(&[], &[])).unwrap());
+        bus.start_vgm_log();
+        bus.mem_write(0x4013, 0x00);
+        bus.mem_write(0x4015, 0b0001_0000);
+        bus.mem_write(0x0000, 0xFF); // not the APU
+
+        let vgm = bus.stop_vgm_log().unwrap();
+        assert_eq!(
+            &vgm[0x100..],
+            &[0xB4, 0x13, 0x00, 0x67, 0x66, 0xC2, 3, 0, 0, 0, 0x00, 0xC0, 0x00, 0xB4, 0x15, 0x10, 0x66][..]
+        );
+    }
+}
@@ -0,0 +1,321 @@
+use crate::checksum::crc32;
+use crate::mapper::{new_mapper, Mapper, NametableSource};
+use crate::power_on::RamFill;
+use crate::savestate::{Snapshot, StateReader, StateWriter};
+use crate::region::Region;
+
+const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A]; // "NES" followed by MS-DOS EOF
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+const PRG_ROM_PAGE_SIZE: usize = 16 * 1024;
+const CHR_ROM_PAGE_SIZE: usize = 8 * 1024;
+const PRG_RAM_SIZE: usize = 8 * 1024;
+const CHR_RAM_SIZE: usize = 8 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring {
+    Vertical,
+    Horizontal,
+    FourScreen,
+    SingleScreenLower,
+    SingleScreenUpper,
+}
+
+// for boards that switch it, as one byte
+impl Snapshot for Mirroring {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(*self as u8);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        *self = match r.u8()? {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::FourScreen,
+            3 => Mirroring::SingleScreenLower,
+            4 => Mirroring::SingleScreenUpper,
+            n => return Err(format!("save state has mirroring {}, which isn't one", n)),
+        };
+        Ok(())
+    }
+}
+
+pub struct Cartridge {
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>, // CHR RAM when the header says there's no CHR ROM
+    pub mapper: u16,
+    pub battery: bool,
+    pub input_device: u8, // NES 2.0 default expansion device, 0 when not given
+    pub region: Region, // NES 2.0 only, iNES files hardly ever set their TV system bit right
+    chr_is_ram: bool,
+    prg_ram: Vec<u8>,
+    mirroring: Mirroring, // from the header, mappers that switch it override this
+    crc: u32,
+    board: Box<dyn Mapper>,
+}
+
+impl Cartridge {
+    // iNES and NES 2.0 both start with the same 16 bytes
+    // https://www.nesdev.org/wiki/INES https://www.nesdev.org/wiki/NES_2.0
+    pub fn new(raw: &[u8]) -> Result<Cartridge, String> {
+        if raw.len() < HEADER_SIZE || raw[0..4] != NES_TAG {
+            return Err("not an iNES file".to_string());
+        }
+
+        let nes2 = raw[7] & 0b0000_1100 == 0b0000_1000;
+
+        let mut mapper = ((raw[7] & 0xF0) | (raw[6] >> 4)) as u16;
+        let mut prg_pages = raw[4] as usize;
+        let mut chr_pages = raw[5] as usize;
+        if nes2 {
+            mapper |= ((raw[8] & 0x0F) as u16) << 8;
+            prg_pages |= ((raw[9] & 0x0F) as usize) << 8;
+            chr_pages |= ((raw[9] >> 4) as usize) << 8;
+        }
+
+        if prg_pages == 0 {
+            return Err("header says there's no PRG ROM".to_string());
+        }
+
+        let mirroring = match (raw[6] & 0b1000 != 0, raw[6] & 0b1 != 0) {
+            (true, _) => Mirroring::FourScreen,
+            (false, true) => Mirroring::Vertical,
+            (false, false) => Mirroring::Horizontal,
+        };
+
+        let prg_rom_size = prg_pages * PRG_ROM_PAGE_SIZE;
+        let chr_rom_size = chr_pages * CHR_ROM_PAGE_SIZE;
+        let board = new_mapper(mapper, prg_rom_size)?;
+
+        let skip_trainer = raw[6] & 0b100 != 0;
+        let prg_rom_start = HEADER_SIZE + if skip_trainer { TRAINER_SIZE } else { 0 };
+        let chr_rom_start = prg_rom_start + prg_rom_size;
+
+        if raw.len() < chr_rom_start + chr_rom_size {
+            return Err(format!(
+                "file is {} bytes but the header wants {}",
+                raw.len(),
+                chr_rom_start + chr_rom_size
+            ));
+        }
+
+        let chr_is_ram = chr_rom_size == 0;
+        let chr_rom = if chr_is_ram {
+            vec![0; CHR_RAM_SIZE]
+        } else {
+            raw[chr_rom_start..(chr_rom_start + chr_rom_size)].to_vec()
+        };
+
+        Ok(Cartridge {
+            prg_rom: raw[prg_rom_start..(prg_rom_start + prg_rom_size)].to_vec(),
+            chr_rom,
+            mapper,
+            battery: raw[6] & 0b10 != 0,
+            input_device: if nes2 { raw[15] & 0x3F } else { 0 },
+            region: if nes2 { Region::from_header(raw[12]) } else { Region::Ntsc },
+            chr_is_ram,
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            mirroring,
+            // header excluded, that's what the usual ROM databases go by
+            crc: crc32(&raw[prg_rom_start..(chr_rom_start + chr_rom_size)]),
+            board,
+        })
+    }
+
+    pub fn mirroring(&self) -> Mirroring {
+        self.board.mirroring().unwrap_or(self.mirroring)
+    }
+
+    // CRC32 of PRG + CHR
+    pub fn crc32(&self) -> u32 {
+        self.crc
+    }
+
+    // PRG RAM as it is, what a battery would keep
+    pub fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    pub fn load_prg_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.prg_ram.len());
+        self.prg_ram[..len].copy_from_slice(&data[..len]);
+    }
+
+    // power on garbage for the RAM on the board. battery backed PRG RAM keeps its save
+    pub fn init_ram(&mut self, fill: &mut RamFill) {
+        if !self.battery {
+            fill.fill(&mut self.prg_ram);
+        }
+        if self.chr_is_ram {
+            fill.fill(&mut self.chr_rom);
+        }
+    }
+
+    // None when nothing on the cartridge answers, e.g. disabled PRG RAM, and the bus is left floating
+    pub fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        if let Some(data) = self.board.read(addr) {
+            return Some(data);
+        }
+
+        match addr {
+            0x6000..=0x7FFF => self.board.prg_ram_addr(addr).map(|offset| self.prg_ram[offset % self.prg_ram.len()]),
+            0x8000..=0xFFFF => Some(self.prg_rom[self.board.prg_addr(addr) % self.prg_rom.len()]),
+            _ => None,
+        }
+    }
+
+    pub fn cpu_write(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            if let Some(offset) = self.board.prg_ram_addr(addr) {
+                let len = self.prg_ram.len();
+                self.prg_ram[offset % len] = data;
+            }
+        }
+        self.board.write(addr, data);
+    }
+
+    pub fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.ppu_peek(addr)
+    }
+
+    // same as ppu_read but never pokes mapper state, for debuggers
+    pub fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr_rom[self.board.chr_addr(addr) % self.chr_rom.len()]
+    }
+
+    pub fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let offset = self.board.chr_addr(addr) % self.chr_rom.len();
+            self.chr_rom[offset] = data;
+        }
+    }
+
+    // None means console VRAM under mirroring()
+    pub fn nametable(&self, addr: u16) -> Option<NametableSource> {
+        self.board.nametable(addr)
+    }
+
+    // CHR by raw offset, for nametables that live there
+    pub fn chr_byte(&self, offset: usize) -> u8 {
+        self.chr_rom[offset % self.chr_rom.len()]
+    }
+
+    pub fn write_chr(&mut self, offset: usize, data: u8) {
+        if self.chr_is_ram {
+            let len = self.chr_rom.len();
+            self.chr_rom[offset % len] = data;
+        }
+    }
+
+    pub fn set_audio_smoothing(&mut self, smooth: bool) {
+        self.board.set_audio_smoothing(smooth);
+    }
+
+    // once per CPU cycle, for mapper IRQ counters and expansion audio
+    pub fn tick(&mut self) {
+        self.board.tick();
+    }
+
+    pub fn irq(&self) -> bool {
+        self.board.irq()
+    }
+
+    pub fn audio_output(&self) -> f32 {
+        self.board.audio_output()
+    }
+}
+
+// the RAM on the board and the mapper's registers. the ROM is whatever's loaded
+impl Snapshot for Cartridge {
+    fn save(&self, w: &mut StateWriter) {
+        w.vec(&self.prg_ram);
+        w.vec(if self.chr_is_ram { &self.chr_rom } else { &[] });
+        self.board.save_state(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        r.vec_into(&mut self.prg_ram, "PRG RAM")?;
+        if self.chr_is_ram {
+            r.vec_into(&mut self.chr_rom, "CHR RAM")?;
+        } else {
+            r.vec_into(&mut [], "CHR RAM")?;
+        }
+        self.board.load_state(r)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    // a NROM-128 image with the given program at $8000 and vectors pointing at it
+    pub fn test_rom(program: &[u8], chr: &[u8]) -> Vec<u8> {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x00, 0, 0, 0, 0, 0, 0, 0, 0];
+
+        let mut prg = vec![0u8; PRG_ROM_PAGE_SIZE];
+        prg[..program.len()].copy_from_slice(program);
+        // NMI -> $8000 + 0x3F00 (an RTI), RESET -> $8000
+        prg[0x3F00] = 0x40;
+        prg[0x3FFA..0x3FFC].copy_from_slice(&[0x00, 0xBF]);
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        raw.extend(prg);
+
+        let mut chr_rom = vec![0u8; CHR_ROM_PAGE_SIZE];
+        chr_rom[..chr.len()].copy_from_slice(chr);
+        raw.extend(chr_rom);
+
+        raw
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let cart = Cartridge::new(&test_rom(&[0xEA], &[])).unwrap();
+
+        assert_eq!(cart.mapper, 0);
+        assert_eq!(cart.mirroring(), Mirroring::Horizontal);
+        assert_eq!(cart.prg_rom.len(), PRG_ROM_PAGE_SIZE);
+        assert_eq!(cart.chr_rom.len(), CHR_ROM_PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_nrom_128_mirrors_prg() {
+        let mut cart = Cartridge::new(&test_rom(&[0xEA], &[])).unwrap();
+
+        assert_eq!(cart.cpu_read(0x8000), Some(0xEA));
+        assert_eq!(cart.cpu_read(0xC000), Some(0xEA));
+    }
+
+    #[test]
+    fn test_vrc6_banks_and_mirroring() {
+        let mut raw = test_rom(&[], &[]);
+        raw[4] = 4; // 64KB PRG
+        raw[6] = 24 << 4;
+        raw[7] = 24 & 0xF0;
+        let mut prg = vec![0u8; 4 * PRG_ROM_PAGE_SIZE];
+        for (bank, chunk) in prg.chunks_mut(0x2000).enumerate() {
+            chunk[0] = bank as u8;
+        }
+        raw.splice(HEADER_SIZE..HEADER_SIZE + PRG_ROM_PAGE_SIZE, prg);
+
+        let mut cart = Cartridge::new(&raw).unwrap();
+        assert_eq!(cart.mapper, 24);
+        assert_eq!(cart.cpu_read(0xE000), Some(7));
+
+        cart.cpu_write(0x8000, 1);
+        cart.cpu_write(0xC000, 5);
+        assert_eq!(cart.cpu_read(0x8000), Some(2));
+        assert_eq!(cart.cpu_read(0xC000), Some(5));
+
+        cart.cpu_write(0xB003, 0b0000_0100);
+        assert_eq!(cart.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_rejects_garbage() {
+        assert!(Cartridge::new(&[0u8; 32]).is_err());
+
+        let mut raw = test_rom(&[], &[]);
+        raw.truncate(100);
+        assert!(Cartridge::new(&raw).is_err());
+    }
+}
@@ -0,0 +1,90 @@
+use crate::savestate::{Snapshot, StateReader, StateWriter};
+
+/*
+ everything on the board runs off one crystal, the master clock. the CPU and PPU each divide
+ it down, on NTSC that's 21.477272 MHz with a CPU cycle every 12 ticks and a PPU dot every 4.
+ a CPU cycle isn't one instant either, the address goes out partway through and the data is
+ latched at the end. so reads and writes land at slightly different points of the cycle and the
+ PPU gets to run up to the right one before the access
+ https://www.nesdev.org/wiki/Cycle_reference_chart
+*/
+pub const NTSC_MASTER_CLOCK: u64 = 21_477_272;
+pub const NTSC_CPU_DIVIDER: u64 = 12;
+pub const NTSC_PPU_DIVIDER: u64 = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MasterClock {
+    pub ticks: u64, // master clock ticks since power on
+    cpu_divider: u64,
+    ppu_divider: u64,
+    ppu_ticks: u64, // where the PPU has been run up to
+}
+
+impl Default for MasterClock {
+    fn default() -> Self {
+        MasterClock::new(NTSC_CPU_DIVIDER, NTSC_PPU_DIVIDER)
+    }
+}
+
+impl MasterClock {
+    pub fn new(cpu_divider: u64, ppu_divider: u64) -> Self {
+        MasterClock { ticks: 0, cpu_divider, ppu_divider, ppu_ticks: 0 }
+    }
+
+    // the first part of a CPU cycle, up to where the access happens. reads are a tick early
+    // and writes a tick late. returns how many PPU dots are due
+    pub fn begin_cycle(&mut self, read: bool) -> u32 {
+        let half = self.cpu_divider / 2;
+        self.advance(if read { half - 1 } else { half + 1 })
+    }
+
+    // the rest of it
+    pub fn end_cycle(&mut self, read: bool) -> u32 {
+        let half = self.cpu_divider - self.cpu_divider / 2;
+        self.advance(if read { half + 1 } else { half - 1 })
+    }
+
+    fn advance(&mut self, ticks: u64) -> u32 {
+        self.ticks += ticks;
+        let mut dots = 0;
+        while self.ppu_ticks + self.ppu_divider <= self.ticks {
+            self.ppu_ticks += self.ppu_divider;
+            dots += 1;
+        }
+        dots
+    }
+}
+
+// the dividers come from the region
+impl Snapshot for MasterClock {
+    fn save(&self, w: &mut StateWriter) {
+        w.u64(self.ticks);
+        w.u64(self.ppu_ticks);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.ticks = r.u64()?;
+        self.ppu_ticks = r.u64()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_three_dots_per_cycle() {
+        let mut clock = MasterClock::default();
+
+        // one dot of the cycle has gone by when either access happens
+        assert_eq!(clock.begin_cycle(true), 1);
+        assert_eq!(clock.end_cycle(true), 2);
+        assert_eq!(clock.begin_cycle(false), 1);
+        assert_eq!(clock.end_cycle(false), 2);
+
+        let dots: u32 = (0..1000).map(|_| clock.begin_cycle(true) + clock.end_cycle(true)).sum();
+        assert_eq!(dots, 3000);
+        assert_eq!(clock.ticks, 1002 * NTSC_CPU_DIVIDER);
+    }
+}
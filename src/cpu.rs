@@ -1,61 +1,115 @@
-use crate::{OPCODES_MAP, opcode::{OpCode, OpCodeName}};
+use crate::{OPCODES_MAP, opcode::{OpCode, OpCodeName}, bus::{Mem, FlatMemory}};
+use crate::savestate::{Snapshot, StateReader, StateWriter};
 use bitflags::bitflags;
 
 const STACK_ORIGIN: u16 = 0x01FF; // stack grows down and ends at 0x100. overflow will cause it to wrap back
 
-pub struct CPU {
+const NMI_VECTOR: u16 = 0xFFFA;
+const RESET_VECTOR: u16 = 0xFFFC;
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+/*
+ two ways of running an instruction. both give the same results and the same cycle counts, the
+ difference is what the rest of the machine gets to see:
+   Instruction   only the reads and writes the instruction needs, then the leftover cycles are
+                 run at the end of it
+   CycleExact    every cycle is a bus access on the cycle the real 6502 makes it, including the
+                 dummy reads and the old value written back by read-modify-writes. slower, but
+                 mappers and registers that count accesses see what they would on hardware
+ https://www.nesdev.org/6502_cpu.txt
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CpuCore {
+    #[default]
+    Instruction,
+    CycleExact,
+}
+
+pub struct CPU<M: Mem = FlatMemory> {
     pub register_a: u8,
     pub register_x: u8,
     pub register_y: u8,
     pub status: CPUStatus,
     pub stack_pointer: u8,
     pub program_counter: u16,
-    memory: [u8; 0xFFFF]
+    pub cycles: u64, // total cycles since power on
+    pub bus: M,
+    accessed_cycles: u16, // cycles already run by reads and writes, since the last add_cycles
+    irq_masked: bool, // the I flag as the last instruction polled for interrupts
+    core: CpuCore,
 }
 
-impl CPU {
+impl CPU<FlatMemory> {
     pub fn new() -> Self {
-        CPU { 
-            register_a: 0, 
+        CPU::with_bus(FlatMemory::new())
+    }
+}
+
+impl Default for CPU<FlatMemory> {
+    fn default() -> Self {
+        CPU::new()
+    }
+}
+
+impl<M: Mem> CPU<M> {
+    pub fn with_bus(bus: M) -> Self {
+        CPU::with_core(bus, CpuCore::Instruction)
+    }
+
+    pub fn with_core(bus: M, core: CpuCore) -> Self {
+        CPU {
+            register_a: 0,
             register_x: 0,
             register_y: 0,
-            status: CPUStatus::empty(), 
+            status: CPUStatus::empty(),
             stack_pointer: 0,
             program_counter: 0,
-            memory: [0; 0xFFFF],
+            cycles: 0,
+            bus,
+            accessed_cycles: 0,
+            irq_masked: true,
+            core,
         }
     }
 
-    fn get_operand_address(&self, mode: &AddressingMode) -> u16 {
+    pub fn core(&self) -> CpuCore {
+        self.core
+    }
+
+    // the bool says whether an index pushed the address into the next page, which costs a cycle on reads
+    fn get_operand_address(&mut self, mode: &AddressingMode) -> (u16, bool) {
         match mode {
-            AddressingMode::Immediate => self.program_counter,
-            AddressingMode::ZeroPage => self.mem_read(self.program_counter) as u16,
-            AddressingMode::Absolute => self.mem_read_u16(self.program_counter),
-            AddressingMode::ZeroPage_X => self.mem_read(self.program_counter).wrapping_add(self.register_x) as u16,
-            AddressingMode::ZeroPage_Y => self.mem_read(self.program_counter).wrapping_add(self.register_y) as u16,
-            AddressingMode::Absolute_X => self.mem_read_u16(self.program_counter).wrapping_add(self.register_x as u16),
-            AddressingMode::Absolute_Y => self.mem_read_u16(self.program_counter).wrapping_add(self.register_y as u16),
+            AddressingMode::Immediate => (self.program_counter, false),
+            AddressingMode::ZeroPage => (self.read(self.program_counter) as u16, false),
+            AddressingMode::Absolute => (self.read_u16(self.program_counter), false),
+            AddressingMode::ZeroPage_X | AddressingMode::ZeroPage_Y => {
+                let base = self.read(self.program_counter);
+                // the index gets added while the unindexed address is being read
+                self.dummy_read(base as u16);
+                let index = if let AddressingMode::ZeroPage_X = mode { self.register_x } else { self.register_y };
+                (base.wrapping_add(index) as u16, false)
+            },
+            AddressingMode::Absolute_X => {
+                let base = self.read_u16(self.program_counter);
+                let addr = base.wrapping_add(self.register_x as u16);
+                (addr, page_crossed(base, addr))
+            },
+            AddressingMode::Absolute_Y => {
+                let base = self.read_u16(self.program_counter);
+                let addr = base.wrapping_add(self.register_y as u16);
+                (addr, page_crossed(base, addr))
+            },
             AddressingMode::Indirect_X => {
-                let ptr: u8 = self.mem_read(self.program_counter).wrapping_add(self.register_x);
-                (
-                    self.mem_read(
-                        ptr.wrapping_add(1) as u16
-                    ) as u16
-                ) << 8 | 
-                (
-                    self.mem_read(ptr as u16)
-                ) as u16
+                let base: u8 = self.read(self.program_counter);
+                self.dummy_read(base as u16);
+                let ptr = base.wrapping_add(self.register_x);
+                (self.read_zero_page_u16(ptr), false)
             },
-            AddressingMode::Indirect_Y => {
-                let ptr: u8 = self.mem_read(self.program_counter).wrapping_add(self.register_y);
-                (
-                    self.mem_read(
-                        ptr.wrapping_add(1) as u16
-                    ) as u16
-                ) << 8 | 
-                (
-                    self.mem_read(ptr as u16)
-                ) as u16
+            AddressingMode::Indirect_Y => { // the pointer is read first, Y is added to what it points at
+                let ptr: u8 = self.read(self.program_counter);
+                let base = self.read_zero_page_u16(ptr);
+                let addr = base.wrapping_add(self.register_y as u16);
+                (addr, page_crossed(base, addr))
             },
             AddressingMode::NonAddressing => {
                 panic!("Caught you tweaking with {:?}.", mode);
@@ -64,40 +118,112 @@ impl CPU {
         }
     }
 
-    pub fn mem_read(&self, addr: u16) -> u8 {
-        self.memory[addr as usize]
+    pub fn mem_read(&mut self, addr: u16) -> u8 {
+        self.bus.mem_read(addr)
     }
 
     pub fn mem_write(&mut self, addr: u16, data: u8) {
-        self.memory[addr as usize] = data
+        self.bus.mem_write(addr, data)
     }
 
-    fn mem_read_u16(&self, addr: u16) -> u16 { // returns little endian
+    fn mem_read_u16(&mut self, addr: u16) -> u16 { // returns little endian
         u16::from_be_bytes([
-            self.mem_read(addr+1),
+            self.mem_read(addr.wrapping_add(1)),
             self.mem_read(addr)
         ])
     }
 
+    // the CPU's own accesses, each one is a cycle. the rest of the machine is run up to it first
+    fn read(&mut self, addr: u16) -> u8 {
+        self.bus.begin_cycle(true);
+        let data = self.bus.mem_read(addr);
+        self.bus.end_cycle(true);
+        self.accessed_cycles += 1;
+        data
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.bus.begin_cycle(false);
+        self.bus.mem_write(addr, data);
+        self.bus.end_cycle(false);
+        self.accessed_cycles += 1;
+    }
+
+    // low byte first, like the 6502 fetches them
+    fn read_u16(&mut self, addr: u16) -> u16 {
+        let lo = self.read(addr);
+        u16::from_le_bytes([lo, self.read(addr.wrapping_add(1))])
+    }
+
+    // pointers in zero page wrap around inside it
+    fn read_zero_page_u16(&mut self, ptr: u8) -> u16 {
+        let lo = self.read(ptr as u16);
+        u16::from_le_bytes([lo, self.read(ptr.wrapping_add(1) as u16)])
+    }
+
+    // the accesses the 6502 makes while it's busy doing something else. only the cycle exact core
+    // puts them on the bus, the other one runs those cycles at the end of the instruction
+    fn dummy_read(&mut self, addr: u16) {
+        if self.core == CpuCore::CycleExact {
+            self.read(addr);
+        }
+    }
+
+    fn dummy_write(&mut self, addr: u16, data: u8) {
+        if self.core == CpuCore::CycleExact {
+            self.write(addr, data);
+        }
+    }
+
+    // stores and read-modify-writes can't know whether the index carried until they've looked,
+    // so they always spend the cycle reading from the address with the high byte not fixed yet
+    fn store_address(&mut self, mode: &AddressingMode) -> u16 {
+        let (addr, crossed) = self.get_operand_address(mode);
+        if let AddressingMode::Absolute_X | AddressingMode::Absolute_Y | AddressingMode::Indirect_Y = mode {
+            self.dummy_read(uncarried(addr, crossed));
+        }
+        addr
+    }
+
     fn mem_write_u16(&mut self, addr: u16, data: u16) {
         let data_bytes = data.to_be_bytes();
         self.mem_write(addr, data_bytes[1]);
-        self.mem_write(addr+1, data_bytes[0])
+        self.mem_write(addr.wrapping_add(1), data_bytes[0])
     }
 
     pub fn reset(&mut self) {
         self.register_a = 0;
         self.register_x = 0;
         self.register_y = 0;
-        self.status = CPUStatus::empty();
+        self.status = CPUStatus::InterruptDisable;
+        self.irq_masked = true;
         self.stack_pointer = 0;
 
-        self.program_counter = self.mem_read_u16(0xFFFC);
+        self.program_counter = self.mem_read_u16(RESET_VECTOR);
+    }
+
+    // the reset button. it goes through the interrupt sequence with the pushes turned into reads,
+    // so A, X and Y are untouched and S ends up 3 lower. stack_pointer counts down from $FF, so
+    // that's 3 more here
+    // https://www.nesdev.org/wiki/CPU_power_up_state
+    pub fn soft_reset(&mut self) {
+        self.stack_pointer = self.stack_pointer.wrapping_add(3);
+        self.status.insert(CPUStatus::InterruptDisable);
+        self.irq_masked = true;
+
+        self.program_counter = self.mem_read_u16(RESET_VECTOR);
     }
 
     pub fn load(&mut self, program: Vec<u8>) {
-        self.memory[0x8000 .. (0x8000 + program.len())].copy_from_slice(&program);
-        self.mem_write_u16(0xFFFC, 0x8000);
+        self.load_at(0x8000, &program);
+        self.mem_write_u16(RESET_VECTOR, 0x8000);
+    }
+
+    // just copies the bytes in through the bus, nothing points at them
+    pub fn load_at(&mut self, addr: u16, program: &[u8]) {
+        for (i, &byte) in program.iter().enumerate() {
+            self.mem_write(addr.wrapping_add(i as u16), byte);
+        }
     }
 
     pub fn load_and_run(&mut self, program: Vec<u8>) {
@@ -107,86 +233,222 @@ impl CPU {
     }
 
     pub fn run(&mut self) {
+        self.run_with_callback(|_| {});
+    }
+
+    // the callback gets a look at the machine before every instruction
+    pub fn run_with_callback<F>(&mut self, mut callback: F)
+    where
+        F: FnMut(&mut CPU<M>),
+    {
         loop {
-            let opscode = self.mem_read(self.program_counter);
-            self.program_counter += 1;
-
-            // debug print
-            println!("RUNNING OPCODE: {:#02x}", opscode);
-
-            //saving the state for some reason?
-            // answer's below
-            let temp_program_counter = self.program_counter;
-
-            //coerced (i think that's the word): &&OpCode -> &OpCode
-            let entry: &OpCode = OPCODES_MAP.get(&opscode).expect("WHERE IS MY SUPER SUIT?"); 
-
-            match entry.name {
-                /*
-                remember to:
-                1. update OpCodeName
-                2. update the OPCODES hashmap
-                3. double check the corresponding byte
-                 */
-                OpCodeName::LDA => self.lda(entry),
-                OpCodeName::LDX => self.ldx(entry),
-                OpCodeName::STA => self.sta(entry),
-                OpCodeName::TAX => self.tax(),
-                OpCodeName::INX => self.inx(),
-                OpCodeName::PHA => self.pha(),
-                OpCodeName::PHP => self.php(),
-                OpCodeName::JSR => self.jsr(entry),
-                OpCodeName::RTS => self.rts(),
-                OpCodeName::PLA => self.pla(),
-                OpCodeName::PLP => self.plp(),
-                OpCodeName::RTI => self.rti(),
-                OpCodeName::ADC => self.adc(entry),
-                OpCodeName::AND => self.and(entry),
-                OpCodeName::ASL => self.asl(entry),
-                OpCodeName::BCC => self.bcc(entry),
-                OpCodeName::BCS => self.bcs(entry),
-                OpCodeName::BEQ => self.beq(entry),
-                OpCodeName::BIT => self.bit(entry),
-                OpCodeName::BMI => self.bmi(entry),
-                OpCodeName::BNE => self.bne(entry),
-                OpCodeName::BRK => {
-                    // might implement this last
-                    self.brk();
-                    break;
-                } // todo: update this
-                // if there's no warning about unreachable pattern, then you know why
-                _ => todo!("ඞ AMOGUS JUMPSCARE ඞ")
-            }
+            callback(self);
 
-            // idk why we do this. maybe it will be explained later
-            // ANSWER: it's not explained. but JSR is one of the reason
-            if self.program_counter == temp_program_counter {
-                self.program_counter += (entry.len - 1) as u16;
+            if !self.step() {
+                break;
             }
         }
     }
 
-    fn lda(&mut self, op: &OpCode) {
-        self.register_a = self.mem_read(
-            self.get_operand_address(&op.mode)
-        );
+    // runs a single instruction. returns false once we hit a BRK or an opcode that jams the CPU
+    pub fn step(&mut self) -> bool {
+        if self.bus.poll_nmi() {
+            self.interrupt(NMI_VECTOR);
+        } else if !self.irq_masked && self.bus.poll_irq() {
+            self.interrupt(IRQ_VECTOR);
+        }
+
+        self.bus.begin_instruction(self.program_counter, self.cycles);
+        let opscode = self.read(self.program_counter);
+        self.program_counter += 1;
+
+        //saving the state for some reason?
+        // answer's below
+        let temp_program_counter = self.program_counter;
+
+        //coerced (i think that's the word): &&OpCode -> &OpCode
+        let entry: &OpCode = match OPCODES_MAP.get(&opscode) {
+            Some(entry) => entry,
+            None => return self.unofficial(opscode),
+        };
+
+        // one byte instructions still fetch the byte after the opcode, and throw it away
+        if entry.len == 1 {
+            self.dummy_read(self.program_counter);
+        }
+        let masked_before = self.status.contains(CPUStatus::InterruptDisable);
+
+        // anything past the base cycle count (page crosses, taken branches) gets added by the instruction
+        let mut extra_cycles: u8 = 0;
+
+        match entry.name {
+            /*
+            remember to:
+            1. update OpCodeName
+            2. update the OPCODES hashmap
+            3. double check the corresponding byte
+             */
+            OpCodeName::LDA => extra_cycles = self.lda(entry),
+            OpCodeName::LDX => extra_cycles = self.ldx(entry),
+            OpCodeName::LDY => extra_cycles = self.ldy(entry),
+            OpCodeName::STA => self.sta(entry),
+            OpCodeName::STX => self.stx(entry),
+            OpCodeName::STY => self.sty(entry),
+            OpCodeName::TAX => self.tax(),
+            OpCodeName::TAY => self.tay(),
+            OpCodeName::TSX => self.tsx(),
+            OpCodeName::TXA => self.txa(),
+            OpCodeName::TXS => self.txs(),
+            OpCodeName::TYA => self.tya(),
+            OpCodeName::INX => self.inx(),
+            OpCodeName::INY => self.iny(),
+            OpCodeName::DEX => self.dex(),
+            OpCodeName::DEY => self.dey(),
+            OpCodeName::INC => self.inc(entry),
+            OpCodeName::DEC => self.dec(entry),
+            OpCodeName::PHA => self.pha(),
+            OpCodeName::PHP => self.php(),
+            OpCodeName::JSR => self.jsr(entry),
+            OpCodeName::JMP => self.jmp(entry),
+            OpCodeName::RTS => self.rts(),
+            OpCodeName::PLA => self.pla(),
+            OpCodeName::PLP => self.plp(),
+            OpCodeName::RTI => self.rti(),
+            OpCodeName::ADC => extra_cycles = self.adc(entry),
+            OpCodeName::SBC => extra_cycles = self.sbc(entry),
+            OpCodeName::AND => extra_cycles = self.and(entry),
+            OpCodeName::ORA => extra_cycles = self.ora(entry),
+            OpCodeName::EOR => extra_cycles = self.eor(entry),
+            OpCodeName::ASL => self.asl(entry),
+            OpCodeName::LSR => self.lsr(entry),
+            OpCodeName::ROL => self.rol(entry),
+            OpCodeName::ROR => self.ror(entry),
+            OpCodeName::BCC => extra_cycles = self.bcc(entry),
+            OpCodeName::BCS => extra_cycles = self.bcs(entry),
+            OpCodeName::BEQ => extra_cycles = self.beq(entry),
+            OpCodeName::BIT => self.bit(entry),
+            OpCodeName::BMI => extra_cycles = self.bmi(entry),
+            OpCodeName::BNE => extra_cycles = self.bne(entry),
+            OpCodeName::BPL => extra_cycles = self.bpl(entry),
+            OpCodeName::BVC => extra_cycles = self.bvc(entry),
+            OpCodeName::BVS => extra_cycles = self.bvs(entry),
+            OpCodeName::CLC => self.clc(),
+            OpCodeName::CLD => self.cld(),
+            OpCodeName::CLI => self.cli(),
+            OpCodeName::CLV => self.clv(),
+            OpCodeName::SEC => self.sec(),
+            OpCodeName::SED => self.sed(),
+            OpCodeName::SEI => self.sei(),
+            OpCodeName::CMP => extra_cycles = self.cmp(entry),
+            OpCodeName::CPX => self.cpx(entry),
+            OpCodeName::CPY => self.cpy(entry),
+            OpCodeName::NOP => {},
+            OpCodeName::BRK => {
+                // might implement this last
+                self.brk();
+                return false;
+            } // todo: update this
+        }
+
+        // idk why we do this. maybe it will be explained later
+        // ANSWER: it's not explained. but JSR is one of the reason
+        if self.program_counter == temp_program_counter {
+            self.program_counter += (entry.len - 1) as u16;
+        }
+
+        self.add_cycles(entry.cycles as u16 + extra_cycles as u16);
+
+        // CLI, SEI and PLP change I after the poll has already happened, so the old value
+        // holds for one more instruction. RTI changes it in time
+        self.irq_masked = match entry.name {
+            OpCodeName::CLI | OpCodeName::SEI | OpCodeName::PLP => masked_before,
+            _ => self.status.contains(CPUStatus::InterruptDisable),
+        };
+
+        // OAM DMA and friends stall the CPU after the instruction that kicked them off
+        let stall = self.bus.take_stall_cycles();
+        if stall > 0 {
+            self.add_cycles(stall);
+        }
+
+        true
+    }
+
+    fn add_cycles(&mut self, cycles: u16) {
+        self.cycles += cycles as u64;
+        let remaining = cycles.saturating_sub(self.accessed_cycles);
+        self.accessed_cycles = 0;
+        self.bus.tick(remaining);
+    }
+
+    fn interrupt(&mut self, vector: u16) {
+        // it starts out fetching an instruction that never runs
+        self.dummy_read(self.program_counter);
+        self.dummy_read(self.program_counter);
+        let rtn_addr = self.program_counter.to_be_bytes();
+        self.push(rtn_addr[0]);
+        self.push(rtn_addr[1]);
+
+        // B is only ever set when the push comes from BRK/PHP
+        let mut status = self.status;
+        status.remove(CPUStatus::Break);
+        status.insert(CPUStatus::Unused);
+        self.push(status.bits());
+
+        self.status.insert(CPUStatus::InterruptDisable);
+        self.program_counter = self.read_u16(vector);
+        self.add_cycles(7);
+    }
+
+    // loads go through here so page crossings get charged
+    fn read_operand(&mut self, op: &OpCode) -> (u8, u8) {
+        let (addr, crossed) = self.get_operand_address(&op.mode);
+        if crossed {
+            self.dummy_read(uncarried(addr, crossed));
+        }
+        (self.read(addr), crossed as u8)
+    }
+
+    fn lda(&mut self, op: &OpCode) -> u8 {
+        let (data, extra) = self.read_operand(op);
+        self.register_a = data;
 
         self.update_zero_and_negative_flags(self.register_a);
+        extra
     }
 
-    fn ldx(&mut self, op: &OpCode) {
-        let data = self.mem_read(
-            self.get_operand_address(&op.mode)
-        );
+    fn ldx(&mut self, op: &OpCode) -> u8 {
+        let (data, extra) = self.read_operand(op);
         self.register_x = data;
 
         self.update_zero_and_negative_flags(self.register_x);
+        extra
+    }
+
+    fn ldy(&mut self, op: &OpCode) -> u8 {
+        let (data, extra) = self.read_operand(op);
+        self.register_y = data;
+
+        self.update_zero_and_negative_flags(self.register_y);
+        extra
     }
 
     fn sta(&mut self, op: &OpCode) {
-        let addr = self.get_operand_address(&op.mode);
+        let addr = self.store_address(&op.mode);
+
+        self.write(addr, self.register_a);
+    }
+
+    fn stx(&mut self, op: &OpCode) {
+        let addr = self.store_address(&op.mode);
+
+        self.write(addr, self.register_x);
+    }
 
-        self.mem_write(addr, self.register_a);
+    fn sty(&mut self, op: &OpCode) {
+        let addr = self.store_address(&op.mode);
+
+        self.write(addr, self.register_y);
     }
 
     fn tax(&mut self) {
@@ -195,47 +457,145 @@ impl CPU {
         self.update_zero_and_negative_flags(self.register_x);
     }
 
+    fn tay(&mut self) {
+        self.register_y = self.register_a;
+
+        self.update_zero_and_negative_flags(self.register_y);
+    }
+
+    // our stack pointer counts up from 0x1FF, the real one counts down from 0x100 + S
+    fn tsx(&mut self) {
+        self.register_x = 0xFF - self.stack_pointer;
+
+        self.update_zero_and_negative_flags(self.register_x);
+    }
+
+    fn txa(&mut self) {
+        self.register_a = self.register_x;
+
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+
+    fn txs(&mut self) {
+        self.stack_pointer = 0xFF - self.register_x;
+    }
+
+    fn tya(&mut self) {
+        self.register_a = self.register_y;
+
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+
     fn inx(&mut self) {
         (self.register_x, _) = self.register_x.overflowing_add(1);
 
         self.update_zero_and_negative_flags(self.register_x);
     }
 
+    fn iny(&mut self) {
+        self.register_y = self.register_y.wrapping_add(1);
+
+        self.update_zero_and_negative_flags(self.register_y);
+    }
+
+    fn dex(&mut self) {
+        self.register_x = self.register_x.wrapping_sub(1);
+
+        self.update_zero_and_negative_flags(self.register_x);
+    }
+
+    fn dey(&mut self) {
+        self.register_y = self.register_y.wrapping_sub(1);
+
+        self.update_zero_and_negative_flags(self.register_y);
+    }
+
+    fn inc(&mut self, op: &OpCode) {
+        let addr = self.store_address(&op.mode);
+        let old = self.read(addr);
+        self.dummy_write(addr, old);
+        let data = old.wrapping_add(1);
+        self.write(addr, data);
+
+        self.update_zero_and_negative_flags(data);
+    }
+
+    fn dec(&mut self, op: &OpCode) {
+        let addr = self.store_address(&op.mode);
+        let old = self.read(addr);
+        self.dummy_write(addr, old);
+        let data = old.wrapping_sub(1);
+        self.write(addr, data);
+
+        self.update_zero_and_negative_flags(data);
+    }
+
     fn pha(&mut self) {
         self.push(self.register_a);
     }
 
     fn php(&mut self) { // i'm supposed to do something about the break flag? yet the wiki only says to push the status reg. oh well
-        self.push(self.status.bits());
+        // found it: both B and the unused bit are pushed as 1
+        self.push((self.status | CPUStatus::Break | CPUStatus::Unused).bits());
     }
 
+    // the high byte of the target is fetched last, after the return address is pushed
     fn jsr(&mut self, op: &OpCode) {
-        let jmp_addr = self.get_operand_address(&op.mode);
+        let AddressingMode::Absolute = op.mode else { self.unknown_opcode_crash(op) };
+        let lo = self.read(self.program_counter);
+        self.dummy_read(self.get_stack_pointer());
         let rtn_addr = (self.program_counter + 1).to_be_bytes();
         self.push(rtn_addr[0]);
         self.push(rtn_addr[1]);
+        let hi = self.read(self.program_counter.wrapping_add(1));
 
-        self.program_counter = jmp_addr;
+        self.program_counter = u16::from_le_bytes([lo, hi]);
+    }
+
+    fn jmp(&mut self, op: &OpCode) {
+        match &op.mode {
+            AddressingMode::Absolute => {
+                self.program_counter = self.read_u16(self.program_counter);
+            },
+            AddressingMode::NonAddressing => { // JMP ($xxxx)
+                let ptr = self.read_u16(self.program_counter);
+
+                // the 6502 never carries into the high byte here, so $xxFF reads its high byte from $xx00
+                let hi_addr = (ptr & 0xFF00) | (ptr as u8).wrapping_add(1) as u16;
+                self.program_counter = u16::from_le_bytes([
+                    self.read(ptr),
+                    self.read(hi_addr),
+                ]);
+            },
+            _ => self.unknown_opcode_crash(op),
+        }
     }
 
     fn rts(&mut self) {
-        self.program_counter = u16::from_le_bytes([
+        self.pop_dummy_read();
+        let rtn_addr = u16::from_le_bytes([
             self.pop(),
             self.pop()
-        ]).wrapping_add(1);
+        ]);
+        // and one more cycle to step past the JSR's last byte
+        self.dummy_read(rtn_addr);
+        self.program_counter = rtn_addr.wrapping_add(1);
     }
 
     fn pla(&mut self) {
+        self.pop_dummy_read();
         self.register_a = self.pop();
 
         self.update_zero_and_negative_flags(self.register_a);
     }
 
     fn plp(&mut self) {
+        self.pop_dummy_read();
         self.status = CPUStatus::from_bits(self.pop()).expect("FAILED TO COERCE STATUS IN PLP");
     }
 
     fn rti(&mut self) {
+        self.pop_dummy_read();
         self.status = CPUStatus::from_bits(self.pop()).expect("FAILED TO COERCE STATUS IN RTI");
         self.program_counter = u16::from_le_bytes([
             self.pop(),
@@ -245,81 +605,122 @@ impl CPU {
 
     // read :)
     // https://www.righto.com/2012/12/the-6502-overflow-flag-explained.html
-    fn adc(&mut self, op: &OpCode) {
-        let (m, n) = (self.register_a, self.mem_read(self.get_operand_address(&op.mode)));
-        let data: u16 = m as u16 + 
-                        n as u16 + 
+    fn adc(&mut self, op: &OpCode) -> u8 {
+        let (n, extra) = self.read_operand(op);
+        self.add_to_register_a(n);
+        extra
+    }
+
+    // no decimal mode on the NES, so SBC is just ADC with the operand flipped
+    fn sbc(&mut self, op: &OpCode) -> u8 {
+        let (n, extra) = self.read_operand(op);
+        self.add_to_register_a(!n);
+        extra
+    }
+
+    fn add_to_register_a(&mut self, n: u8) {
+        let m = self.register_a;
+        let data: u16 = m as u16 +
+                        n as u16 +
                         if self.status.contains(CPUStatus::Carry) { 1 } else { 0 };
-        
+
         self.register_a = data as u8;
         self.update_zero_and_negative_flags(self.register_a);
         self.status.set(CPUStatus::Carry, data > 0xFF);
-        self.status.set(CPUStatus::Overflow, 
+        self.status.set(CPUStatus::Overflow,
             (m ^ (data as u8)) & (n ^ (data as u8)) & 0x80 != 0x0
         );
     }
 
-    fn and(&mut self, op: &OpCode) {
-        self.register_a &= self.mem_read(self.get_operand_address(&op.mode));
+    fn and(&mut self, op: &OpCode) -> u8 {
+        let (data, extra) = self.read_operand(op);
+        self.register_a &= data;
+
+        self.update_zero_and_negative_flags(self.register_a);
+        extra
+    }
+
+    fn ora(&mut self, op: &OpCode) -> u8 {
+        let (data, extra) = self.read_operand(op);
+        self.register_a |= data;
+
+        self.update_zero_and_negative_flags(self.register_a);
+        extra
+    }
+
+    fn eor(&mut self, op: &OpCode) -> u8 {
+        let (data, extra) = self.read_operand(op);
+        self.register_a ^= data;
 
         self.update_zero_and_negative_flags(self.register_a);
+        extra
     }
 
     fn asl(&mut self, op: &OpCode) {
+        self.shift(op, |data, _| (data << 1, data & 0x80 != 0));
+    }
+
+    fn lsr(&mut self, op: &OpCode) {
+        self.shift(op, |data, _| (data >> 1, data & 0x01 != 0));
+    }
+
+    fn rol(&mut self, op: &OpCode) {
+        self.shift(op, |data, carry| (data << 1 | carry as u8, data & 0x80 != 0));
+    }
+
+    fn ror(&mut self, op: &OpCode) {
+        self.shift(op, |data, carry| (data >> 1 | (carry as u8) << 7, data & 0x01 != 0));
+    }
+
+    // the four shifts only differ in which way the bits go, f gets (data, carry in) and gives back (result, carry out)
+    fn shift<F: Fn(u8, bool) -> (u8, bool)>(&mut self, op: &OpCode, f: F) {
+        let carry_in = self.status.contains(CPUStatus::Carry);
+
         match &op.mode {
             AddressingMode::ZeroPage | AddressingMode::ZeroPage_X |
             AddressingMode::Absolute | AddressingMode::Absolute_X => {
-                let addr = self.get_operand_address(&op.mode);
-                let data = (
-                    self.mem_read(addr) 
-                    as u16
-                ) << 1;
+                let addr = self.store_address(&op.mode);
+                let old = self.read(addr);
+                // the old value goes back out while the ALU works on it
+                self.dummy_write(addr, old);
+                let (data, carry) = f(old, carry_in);
 
-                self.mem_write(addr, data as u8);
+                self.write(addr, data);
 
-                self.status.set(CPUStatus::Carry, data > 0xFF);
-                self.update_zero_and_negative_flags(data as u8);
+                self.status.set(CPUStatus::Carry, carry);
+                self.update_zero_and_negative_flags(data);
             },
-            AddressingMode::NonAddressing => { //this will catch the ASL with accumulator
-                let data = (self.register_a as u16) << 1;
-        
-                self.register_a = data as u8;
+            AddressingMode::NonAddressing => { //this will catch the shifts with accumulator
+                let (data, carry) = f(self.register_a, carry_in);
 
-                self.status.set(CPUStatus::Carry, data > 0xFF);
-                self.update_zero_and_negative_flags(data as u8);
+                self.register_a = data;
+
+                self.status.set(CPUStatus::Carry, carry);
+                self.update_zero_and_negative_flags(data);
             },
             _ => self.unknown_opcode_crash(op),
         }
-        
+
     }
 
-    fn bcc(&mut self, op: &OpCode) {
-        if !self.status.contains(CPUStatus::Carry) {
-            self.branch(op, self.mem_read(self.program_counter) as i8)
-        }
+    fn bcc(&mut self, op: &OpCode) -> u8 {
+        self.branch_if(op, !self.status.contains(CPUStatus::Carry))
     }
 
-    fn bcs(&mut self, op: &OpCode) {
-        if self.status.contains(CPUStatus::Carry) {
-            self.branch(op, self.mem_read(self.program_counter) as i8)
-        }
+    fn bcs(&mut self, op: &OpCode) -> u8 {
+        self.branch_if(op, self.status.contains(CPUStatus::Carry))
     }
 
-    fn beq(&mut self, op: &OpCode) {
-        if self.status.contains(CPUStatus::Zero) {
-            self.branch(op, self.mem_read(self.program_counter) as i8)
-        }
+    fn beq(&mut self, op: &OpCode) -> u8 {
+        self.branch_if(op, self.status.contains(CPUStatus::Zero))
     }
 
     fn bit(&mut self, op: &OpCode) {
-        let m = self.mem_read(
-            self.get_operand_address(&op.mode)
-        );
+        let (addr, _) = self.get_operand_address(&op.mode);
+        let m = self.read(addr);
         let data = self.register_a & m;
 
-        if data == 0 {
-            self.status.insert(CPUStatus::Zero);   
-        }
+        self.status.set(CPUStatus::Zero, data == 0);
 
         if m & 0b1000_0000 > 0 {
             self.status.insert(CPUStatus::Negative);
@@ -334,46 +735,67 @@ impl CPU {
         }
     }
 
-    fn bmi(&mut self, op: &OpCode) {
-        if self.status.contains(CPUStatus::Negative) {
-            self.branch(op, self.mem_read(self.program_counter) as i8)
-        }
+    fn bmi(&mut self, op: &OpCode) -> u8 {
+        self.branch_if(op, self.status.contains(CPUStatus::Negative))
     }
 
-    fn bne(&mut self, op: &OpCode) {
-        if !self.status.contains(CPUStatus::Zero) {
-            self.branch(op, self.mem_read(self.program_counter) as i8)
-        }
+    fn bne(&mut self, op: &OpCode) -> u8 {
+        self.branch_if(op, !self.status.contains(CPUStatus::Zero))
     }
 
-    fn bpl(&mut self, op: &OpCode) {
-        if !self.status.contains(CPUStatus::Negative) {
-            self.branch(op, self.mem_read(self.program_counter) as i8)
-        }
+    fn bpl(&mut self, op: &OpCode) -> u8 {
+        self.branch_if(op, !self.status.contains(CPUStatus::Negative))
     }
 
     // read :) again
     // https://web.archive.org/web/20200129081101/http://users.telenet.be:80/kim1-6502/6502/proman.html#911
+    /*
+     opcodes the table doesn't have. the ones in column 2 (bar the immediate NOPs) jam a real 6502
+     until reset, so they stop us like BRK does. everything else gets skipped as a NOP of its
+     length, which the opcode's place in the matrix gives away. that's right for the unofficial
+     NOPs games use for padding and timing, the combined ones (SLO, LAX, DCP...) do nothing here
+     https://www.nesdev.org/wiki/CPU_unofficial_opcodes
+    */
+    fn unofficial(&mut self, opcode: u8) -> bool {
+        let odd_row = opcode & 0x10 != 0;
+        let len: u16 = match opcode & 0x0F {
+            0x2 if odd_row || opcode < 0x80 => {
+                self.add_cycles(2);
+                return false;
+            },
+            0x8 | 0xA => 1,
+            0x9 | 0xB if odd_row => 3,
+            0xC..=0xF => 3,
+            _ => 2,
+        };
+
+        if len == 1 {
+            self.dummy_read(self.program_counter);
+        }
+        for _ in 1..len {
+            self.read(self.program_counter);
+            self.program_counter = self.program_counter.wrapping_add(1);
+        }
+        self.add_cycles(len + 1);
+        true
+    }
+
     fn brk(&mut self) {
 
     }
 
-    fn bvc(&mut self, op: &OpCode) {
-        if !self.status.contains(CPUStatus::Overflow) {
-            self.branch(op, self.mem_read(self.program_counter) as i8)
-        }
+    fn bvc(&mut self, op: &OpCode) -> u8 {
+        self.branch_if(op, !self.status.contains(CPUStatus::Overflow))
     }
 
-    fn bvs(&mut self, op: &OpCode) {
-        if self.status.contains(CPUStatus::Overflow) {
-            self.branch(op, self.mem_read(self.program_counter) as i8)
-        }
+    fn bvs(&mut self, op: &OpCode) -> u8 {
+        self.branch_if(op, self.status.contains(CPUStatus::Overflow))
     }
 
     fn clc(&mut self) {
         self.status.remove(CPUStatus::Carry)
     }
-    
+
     fn cld(&mut self) {
         self.status.remove(CPUStatus::Decimal)
     }
@@ -386,48 +808,78 @@ impl CPU {
         self.status.remove(CPUStatus::Overflow)
     }
 
-    fn cmp(&mut self, op: &OpCode) {
-        let res = (self.register_a as i16).wrapping_sub(
-            self.mem_read(self.get_operand_address(&op.mode)) as i16
-        );
-        if res == 0 {
-            self.status.set(CPUStatus::Zero, true);
-        }
-        if res & 0b0000_0000_1000_0000 != 0 {
-            self.status.set(CPUStatus::Negative, true);
-        } else {
-            self.status.set(CPUStatus::Carry, true);
-        }
+    fn sec(&mut self) {
+        self.status.insert(CPUStatus::Carry)
+    }
+
+    fn sed(&mut self) {
+        self.status.insert(CPUStatus::Decimal)
+    }
 
+    fn sei(&mut self) {
+        self.status.insert(CPUStatus::InterruptDisable)
+    }
 
+    fn cmp(&mut self, op: &OpCode) -> u8 {
+        let (data, extra) = self.read_operand(op);
+        self.compare(self.register_a, data);
+        extra
     }
 
     fn cpx(&mut self, op: &OpCode) {
-        let res = (self.register_x as i16).wrapping_sub(
-            self.mem_read(self.get_operand_address(&op.mode)) as i16
-        );
-        if res == 0 {
-            self.status.set(CPUStatus::Zero, true);
-        }
-        if res & 0b0000_0000_1000_0000 != 0 {
-            self.status.set(CPUStatus::Negative, true);
-        } else {
-            self.status.set(CPUStatus::Carry, true);
-        }
+        let (data, _) = self.read_operand(op);
+        self.compare(self.register_x, data);
+    }
+
+    fn cpy(&mut self, op: &OpCode) {
+        let (data, _) = self.read_operand(op);
+        self.compare(self.register_y, data);
+    }
+
+    // carry means "no borrow", i.e. register >= data
+    fn compare(&mut self, register: u8, data: u8) {
+        self.status.set(CPUStatus::Carry, register >= data);
+        self.update_zero_and_negative_flags(register.wrapping_sub(data));
     }
 
     // stack
     fn push(&mut self, data: u8) {
-        self.mem_write(self.get_stack_pointer(), data);
+        self.write(self.get_stack_pointer(), data);
         self.stack_pointer = self.stack_pointer.wrapping_add(1);
     }
 
     fn pop(&mut self) -> u8 {
         self.stack_pointer = self.stack_pointer.wrapping_sub(1);
-        self.mem_read(self.get_stack_pointer())
+        self.read(self.get_stack_pointer())
     }
-    
+
+    // pulls spend a cycle reading the stack before the pointer moves
+    fn pop_dummy_read(&mut self) {
+        self.dummy_read(self.get_stack_pointer());
+    }
+
     // branch
+    // a taken branch costs a cycle, and another one if it lands on a different page
+    fn branch_if(&mut self, op: &OpCode, condition: bool) -> u8 {
+        let dist = self.read(self.program_counter) as i8;
+        if !condition {
+            return 0;
+        }
+
+        let next = self.program_counter.wrapping_add((op.len - 1) as u16);
+        self.branch(op, dist);
+
+        // the opcode after the branch gets fetched while the offset's added, then the low byte
+        // of the target is tried before the carry reaches the high one
+        self.dummy_read(next);
+        let crossed = page_crossed(next, self.program_counter);
+        if crossed {
+            self.dummy_read((next & 0xFF00) | (self.program_counter & 0x00FF));
+        }
+
+        1 + crossed as u8
+    }
+
     fn branch(&mut self, op: &OpCode, dist: i8) {
         self.program_counter = self.program_counter.wrapping_add((op.len - 1) as u16).wrapping_add_signed(dist as i16);
     }
@@ -457,6 +909,43 @@ impl CPU {
     }
 }
 
+// just the CPU, the bus gets saved on its own. which core it is stays as it was built
+impl<M: Mem> Snapshot for CPU<M> {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.register_a);
+        w.u8(self.register_x);
+        w.u8(self.register_y);
+        w.u8(self.status.bits());
+        w.u8(self.stack_pointer);
+        w.u16(self.program_counter);
+        w.u64(self.cycles);
+        w.u16(self.accessed_cycles);
+        w.bool(self.irq_masked);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.register_a = r.u8()?;
+        self.register_x = r.u8()?;
+        self.register_y = r.u8()?;
+        self.status = CPUStatus::from_bits_truncate(r.u8()?);
+        self.stack_pointer = r.u8()?;
+        self.program_counter = r.u16()?;
+        self.cycles = r.u64()?;
+        self.accessed_cycles = r.u16()?;
+        self.irq_masked = r.bool()?;
+        Ok(())
+    }
+}
+
+fn page_crossed(a: u16, b: u16) -> bool {
+    a & 0xFF00 != b & 0xFF00
+}
+
+// where an indexed access first lands, before the carry out of the low byte is added in
+fn uncarried(addr: u16, crossed: bool) -> u16 {
+    if crossed { addr.wrapping_sub(0x100) } else { addr }
+}
+
 #[derive(Debug, Clone, Copy)]
 #[allow(non_camel_case_types)]
 pub enum AddressingMode {
@@ -498,4 +987,4 @@ bitflags! {
         const Overflow = 0b0100_0000;
         const Negative = 0b1000_0000;
     }
-}
\ No newline at end of file
+}
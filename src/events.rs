@@ -0,0 +1,190 @@
+use std::fmt::Write;
+
+use crate::ppu::debug::Image;
+use crate::ppu::{DOTS_PER_SCANLINE, SCANLINES_PER_FRAME};
+
+// a record of when the CPU poked at PPU and mapper registers, for chasing raster effect bugs.
+// the PPU position is where the beam was at the access itself, see clock.rs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub frame: u64,
+    pub scanline: u16,
+    pub dot: u16,
+    pub cycle: u64, // CPU cycle the access happened on
+    pub pc: u16,
+    pub addr: u16, // as the game wrote it, mirrors of the PPU registers included
+    pub value: u8,
+    pub access: Access,
+}
+
+impl Event {
+    pub fn is_mapper(&self) -> bool {
+        self.addr >= 0x4020
+    }
+}
+
+pub struct EventLog {
+    pub events: Vec<Event>,
+    pub scanlines: u16, // in a frame, 312 on the 50Hz consoles
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        EventLog::new()
+    }
+}
+
+impl EventLog {
+    pub fn new() -> Self {
+        EventLog { events: Vec::new(), scanlines: SCANLINES_PER_FRAME }
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+
+    pub fn for_frame(&self, frame: u64) -> impl Iterator<Item = &Event> {
+        self.events.iter().filter(move |e| e.frame == frame)
+    }
+
+    pub fn last_frame(&self) -> Option<u64> {
+        self.events.last().map(|e| e.frame)
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from("frame,scanline,dot,cycle,pc,addr,access,value\n");
+        for e in &self.events {
+            let access = match e.access {
+                Access::Read => "R",
+                Access::Write => "W",
+            };
+            let _ = writeln!(
+                out,
+                "{},{},{},{},${:04X},${:04X},{},${:02X}",
+                e.frame, e.scanline, e.dot, e.cycle, e.pc, e.addr, access, e.value
+            );
+        }
+        out
+    }
+
+    // one frame as a 341 dot wide grid with a row per scanline, one marker per access at its scanline and dot
+    pub fn render(&self, frame: u64) -> Image {
+        let (width, height) = (DOTS_PER_SCANLINE as usize, self.scanlines as usize);
+        let mut image = Image::new(width, height);
+
+        // the visible picture is a bit lighter than hblank and vblank
+        for y in 0..height {
+            for x in 0..width {
+                let visible = (1..=256).contains(&x) && y < 240;
+                image.set_pixel(x, y, if visible { (0x30, 0x30, 0x30) } else { (0x18, 0x18, 0x18) });
+            }
+        }
+
+        for e in self.for_frame(frame) {
+            let color = marker_color(e);
+            let (x, y) = (e.dot as usize, e.scanline as usize);
+            image.set_pixel(x, y, color);
+            image.set_pixel(x + 1, y, color);
+            image.set_pixel(x.wrapping_sub(1), y, color);
+            image.set_pixel(x, y + 1, color);
+            image.set_pixel(x, y.wrapping_sub(1), color);
+        }
+        image
+    }
+}
+
+pub fn marker_color(event: &Event) -> (u8, u8, u8) {
+    if event.is_mapper() {
+        return (0xFF, 0xFF, 0xFF);
+    }
+
+    // $2008-$3FFF mirror the 8 registers
+    let addr = match event.addr {
+        0x2000..=0x3FFF => event.addr & 0x2007,
+        addr => addr,
+    };
+    match (addr, event.access) {
+        (0x2000, _) => (0xFF, 0x40, 0x40), // PPUCTRL
+        (0x2001, _) => (0x40, 0xFF, 0x40), // PPUMASK
+        (0x2002, _) => (0xFF, 0xFF, 0x40), // PPUSTATUS
+        (0x2003 | 0x2004 | 0x4014, _) => (0xFF, 0x90, 0x20), // OAM
+        (0x2005, _) => (0x40, 0xC0, 0xFF), // PPUSCROLL
+        (0x2006, _) => (0xC0, 0x60, 0xFF), // PPUADDR
+        (0x2007, Access::Read) => (0x40, 0xFF, 0xC0),
+        _ => (0xFF, 0x60, 0xC0), // PPUDATA writes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn event(frame: u64, scanline: u16, dot: u16, addr: u16) -> Event {
+        Event { frame, scanline, dot, cycle: 0, pc: 0x8000, addr, value: 0x1E, access: Access::Write }
+    }
+
+    #[test]
+    fn test_bus_records_register_accesses() {
+        use crate::bus::Bus;
+        use crate::cartridge::Cartridge;
+        use crate::cartridge::test::test_rom;
+        use crate::cpu::CPU;
+
+        // LDA #$1E; STA $2005; LDA $2002; STA $0200; STA $8000; BRK
+        let program = [0xA9, 0x1E, 0x8D, 0x05, 0x20, 0xAD, 0x02, 0x20, 0x8D, 0x00, 0x02, 0x8D, 0x00, 0x80, 0x00];
+        let mut cpu = CPU::with_bus(Bus::new(Cartridge::new(&test_rom(&program, &[])).unwrap()));
+        cpu.reset();
+        cpu.bus.start_recording();
+        while cpu.step() {}
+
+        let log = cpu.bus.stop_recording().unwrap();
+        let summary: Vec<(u16, u16, Access)> = log.events.iter().map(|e| (e.pc, e.addr, e.access)).collect();
+        assert_eq!(summary, vec![(0x8002, 0x2005, Access::Write), (0x8005, 0x2002, Access::Read), (0x800B, 0x8000, Access::Write)]);
+        // the PPU has run a dot into the cycle by the time the write happens
+        assert_eq!(log.events[0].dot, ((log.events[0].cycle * 3 + 1) % 341) as u16);
+        assert_eq!(log.events[0].cycle, 5);
+    }
+
+    #[test]
+    fn test_csv() {
+        let log = EventLog { events: vec![event(3, 120, 40, 0x2005)], ..Default::default() };
+        assert_eq!(log.to_csv(), "frame,scanline,dot,cycle,pc,addr,access,value\n3,120,40,0,$8000,$2005,W,$1E\n");
+    }
+
+    #[test]
+    fn test_render_only_draws_the_asked_for_frame() {
+        let log = EventLog { events: vec![event(1, 10, 20, 0x2001), event(2, 100, 200, 0x8000)], ..Default::default() };
+        let image = log.render(2);
+
+        assert_eq!((image.width, image.height), (341, 262));
+        assert_eq!(image.get_pixel(200, 100), (0xFF, 0xFF, 0xFF));
+        assert_ne!(image.get_pixel(20, 10), (0x40, 0xFF, 0x40));
+    }
+
+    #[test]
+    fn test_mirrored_registers_get_their_color() {
+        use crate::bus::Bus;
+        use crate::cartridge::Cartridge;
+        use crate::cartridge::test::test_rom;
+        use crate::cpu::CPU;
+
+        // LDA #$1E; STA $3456 (PPUADDR); STA $3FFF (PPUDATA); BRK
+        let program = [0xA9, 0x1E, 0x8D, 0x56, 0x34, 0x8D, 0xFF, 0x3F, 0x00];
+        let mut cpu = CPU::with_bus(Bus::new(Cartridge::new(&test_rom(&program, &[])).unwrap()));
+        cpu.reset();
+        cpu.bus.start_recording();
+        while cpu.step() {}
+
+        let log = cpu.bus.stop_recording().unwrap();
+        assert_eq!(log.events.iter().map(|e| e.addr).collect::<Vec<u16>>(), vec![0x3456, 0x3FFF]);
+        assert_eq!(marker_color(&log.events[0]), (0xC0, 0x60, 0xFF));
+        assert_eq!(marker_color(&log.events[1]), (0xFF, 0x60, 0xC0));
+        assert_eq!(marker_color(&event(0, 0, 0, 0x3FF5)), (0x40, 0xC0, 0xFF));
+    }
+}
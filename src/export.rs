@@ -0,0 +1,367 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::checksum::{adler32, crc32_update};
+use crate::frame::Frame;
+use crate::palette::Palette;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Ppm, // binary (P6)
+}
+
+impl ImageFormat {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<ImageFormat> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        ImageFormat::from_name(&extension)
+    }
+
+    pub fn from_name(name: &str) -> Option<ImageFormat> {
+        match name {
+            "png" => Some(ImageFormat::Png),
+            "ppm" => Some(ImageFormat::Ppm),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Ppm => "ppm",
+        }
+    }
+}
+
+// stuff that gets written into the image next to the pixels
+#[derive(Debug, Clone, Default)]
+pub struct FrameMetadata {
+    pub frame: u64,
+    pub rom_crc32: Option<u32>,
+    pub frame_count: Option<u64>, // how many frames the whole run was
+}
+
+impl FrameMetadata {
+    fn entries(&self) -> Vec<(&'static str, String)> {
+        let mut entries = vec![
+            ("Software", "nes-emulator".to_string()),
+            ("Frame", self.frame.to_string()),
+        ];
+        if let Some(crc) = self.rom_crc32 {
+            entries.push(("ROM-CRC32", format!("{:08X}", crc)));
+        }
+        if let Some(count) = self.frame_count {
+            entries.push(("Frame-Count", count.to_string()));
+        }
+        entries
+    }
+}
+
+pub fn encode_frame(format: ImageFormat, frame: &Frame, palette: &Palette, meta: &FrameMetadata) -> Vec<u8> {
+    let rgb = frame.to_rgb(palette);
+    encode_rgb(format, Frame::WIDTH, Frame::HEIGHT, &rgb, &meta.entries())
+}
+
+// the format is picked from the extension, anything unknown ends up as PNG
+pub fn save_frame<P: AsRef<Path>>(path: P, frame: &Frame, palette: &Palette, meta: &FrameMetadata) -> io::Result<()> {
+    let format = ImageFormat::from_path(&path).unwrap_or(ImageFormat::Png);
+    fs::write(path, encode_frame(format, frame, palette, meta))
+}
+
+pub fn encode_rgb(format: ImageFormat, width: usize, height: usize, rgb: &[u8], text: &[(&str, String)]) -> Vec<u8> {
+    match format {
+        ImageFormat::Png => encode_png(width, height, rgb, text),
+        ImageFormat::Ppm => encode_ppm(width, height, rgb, text),
+    }
+}
+
+pub fn encode_ppm(width: usize, height: usize, rgb: &[u8], text: &[(&str, String)]) -> Vec<u8> {
+    let mut out = b"P6\n".to_vec();
+    for (key, value) in text {
+        out.extend(format!("# {}: {}\n", key, value).bytes());
+    }
+    out.extend(format!("{} {}\n255\n", width, height).bytes());
+    out.extend_from_slice(rgb);
+    out
+}
+
+// 8 bit RGB, no interlacing, every row unfiltered
+pub fn encode_png(width: usize, height: usize, rgb: &[u8], text: &[(&str, String)]) -> Vec<u8> {
+    let mut out = PNG_SIGNATURE.to_vec();
+
+    let mut header = Vec::with_capacity(13);
+    header.extend((width as u32).to_be_bytes());
+    header.extend((height as u32).to_be_bytes());
+    header.extend([8, 2, 0, 0, 0]); // bit depth, color type RGB, compression, filter, interlace
+    write_chunk(&mut out, b"IHDR", &header);
+
+    for (key, value) in text {
+        let mut chunk = key.as_bytes().to_vec();
+        chunk.push(0);
+        chunk.extend(value.bytes());
+        write_chunk(&mut out, b"tEXt", &chunk);
+    }
+
+    let mut raw = Vec::with_capacity((width * 3 + 1) * height);
+    for row in rgb.chunks(width * 3) {
+        raw.push(0); // filter: none
+        raw.extend_from_slice(row);
+    }
+    write_chunk(&mut out, b"IDAT", &zlib_compress(&raw));
+    write_chunk(&mut out, b"IEND", &[]);
+
+    out
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend((data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32_update(crc32_update(0, kind), data);
+    out.extend(crc.to_be_bytes());
+}
+
+// writes every Nth frame into a directory as frame_000042.png and so on
+pub struct FrameDumper {
+    pub dir: PathBuf,
+    pub format: ImageFormat,
+    pub every: u64,
+    pub palette: Palette,
+    pub rom_crc32: Option<u32>,
+    pub frame_count: Option<u64>,
+}
+
+impl FrameDumper {
+    pub fn new<P: Into<PathBuf>>(dir: P, format: ImageFormat, every: u64) -> Self {
+        FrameDumper {
+            dir: dir.into(),
+            format,
+            every: every.max(1),
+            palette: Palette::default(),
+            rom_crc32: None,
+            frame_count: None,
+        }
+    }
+
+    pub fn path_for(&self, frame_number: u64) -> PathBuf {
+        self.dir.join(format!("frame_{:06}.{}", frame_number, self.format.extension()))
+    }
+
+    // returns where the frame went, or None if this one isn't due
+    pub fn dump(&self, frame_number: u64, frame: &Frame) -> io::Result<Option<PathBuf>> {
+        if !frame_number.is_multiple_of(self.every) {
+            return Ok(None);
+        }
+
+        fs::create_dir_all(&self.dir)?;
+
+        let meta = FrameMetadata {
+            frame: frame_number,
+            rom_crc32: self.rom_crc32,
+            frame_count: self.frame_count,
+        };
+        let path = self.path_for(frame_number);
+        fs::write(&path, encode_frame(self.format, frame, &self.palette, &meta))?;
+
+        Ok(Some(path))
+    }
+}
+
+/*
+ deflate with the fixed huffman tables and a plain hash chain matcher.
+ not zopfli, but NES frames are mostly long runs so it does fine
+ https://www.rfc-editor.org/rfc/rfc1951
+*/
+const WINDOW_SIZE: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: usize = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097,
+    6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u32,
+    count: u8,
+}
+
+impl BitWriter {
+    fn write_bits(&mut self, value: u32, count: u8) {
+        self.bits |= value << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    // huffman codes go in most significant bit first
+    fn write_code(&mut self, code: u32, len: u8) {
+        let reversed = code.reverse_bits() >> (32 - len);
+        self.write_bits(reversed, len);
+    }
+
+    fn write_literal(&mut self, symbol: u16) {
+        match symbol {
+            0..=143 => self.write_code(0x30 + symbol as u32, 8),
+            144..=255 => self.write_code(0x190 + (symbol - 144) as u32, 9),
+            256..=279 => self.write_code((symbol - 256) as u32, 7),
+            _ => self.write_code(0xC0 + (symbol - 280) as u32, 8),
+        }
+    }
+
+    fn write_match(&mut self, length: usize, distance: usize) {
+        let code = LENGTH_BASE.iter().rposition(|&base| base as usize <= length).unwrap();
+        self.write_literal(257 + code as u16);
+        self.write_bits((length - LENGTH_BASE[code] as usize) as u32, LENGTH_EXTRA[code]);
+
+        let code = DIST_BASE.iter().rposition(|&base| base as usize <= distance).unwrap();
+        self.write_code(code as u32, 5);
+        self.write_bits((distance - DIST_BASE[code] as usize) as u32, DIST_EXTRA[code]);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.bits as u8);
+        }
+        self.out
+    }
+}
+
+fn hash(data: &[u8]) -> usize {
+    let value = (data[0] as usize) << 16 | (data[1] as usize) << 8 | data[2] as usize;
+    (value.wrapping_mul(2654435761) >> 8) & ((1 << HASH_BITS) - 1)
+}
+
+pub fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter { out: vec![0x78, 0x01], bits: 0, count: 0 };
+
+    // one final block, fixed huffman
+    writer.write_bits(1, 1);
+    writer.write_bits(1, 2);
+
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; data.len()];
+
+    let mut i = 0;
+    while i < data.len() {
+        let mut best_len = 0;
+        let mut best_dist = 0;
+
+        if i + MIN_MATCH <= data.len() {
+            let h = hash(&data[i..]);
+            let mut candidate = head[h];
+            let mut chain = 0;
+
+            while candidate != usize::MAX && i - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+                let max = (data.len() - i).min(MAX_MATCH);
+                let len = (0..max).take_while(|&k| data[candidate + k] == data[i + k]).count();
+                if len > best_len {
+                    best_len = len;
+                    best_dist = i - candidate;
+                    if len == max {
+                        break;
+                    }
+                }
+                candidate = prev[candidate];
+                chain += 1;
+            }
+
+            prev[i] = head[h];
+            head[h] = i;
+        }
+
+        if best_len >= MIN_MATCH {
+            writer.write_match(best_len, best_dist);
+            // the skipped bytes still need to be findable later
+            for k in i + 1..i + best_len {
+                if k + MIN_MATCH <= data.len() {
+                    let h = hash(&data[k..]);
+                    prev[k] = head[h];
+                    head[h] = k;
+                }
+            }
+            i += best_len;
+        } else {
+            writer.write_literal(data[i] as u16);
+            i += 1;
+        }
+    }
+
+    writer.write_literal(256); // end of block
+
+    let mut out = writer.finish();
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::checksum::crc32;
+
+    #[test]
+    fn test_png_chunks() {
+        let frame = Frame::new();
+        let meta = FrameMetadata { frame: 42, rom_crc32: Some(0xDEADBEEF), frame_count: None };
+        let png = encode_frame(ImageFormat::Png, &frame, &Palette::default(), &meta);
+
+        assert_eq!(png[..8], PNG_SIGNATURE);
+
+        // walk the chunks and check every CRC
+        let mut kinds = vec![];
+        let mut pos = 8;
+        while pos < png.len() {
+            let len = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+            let kind = &png[pos + 4..pos + 8];
+            let crc = u32::from_be_bytes(png[pos + 8 + len..pos + 12 + len].try_into().unwrap());
+            assert_eq!(crc, crc32(&png[pos + 4..pos + 8 + len]));
+
+            if kind == b"tEXt" {
+                kinds.push(String::from_utf8_lossy(&png[pos + 8..pos + 8 + len]).to_string());
+            }
+            pos += 12 + len;
+        }
+
+        assert!(kinds.contains(&"Frame\x0042".to_string()));
+        assert!(kinds.contains(&"ROM-CRC32\x00DEADBEEF".to_string()));
+    }
+
+    #[test]
+    fn test_ppm_header() {
+        let frame = Frame::new();
+        let meta = FrameMetadata { frame: 7, rom_crc32: None, frame_count: Some(60) };
+        let ppm = encode_frame(ImageFormat::Ppm, &frame, &Palette::default(), &meta);
+
+        let header = "P6\n# Software: nes-emulator\n# Frame: 7\n# Frame-Count: 60\n256 240\n255\n";
+        assert_eq!(&ppm[..header.len()], header.as_bytes());
+        assert_eq!(ppm.len(), header.len() + 256 * 240 * 3);
+    }
+
+    #[test]
+    fn test_runs_compress() {
+        let data = vec![0x0Fu8; 256 * 240 * 3];
+        assert!(zlib_compress(&data).len() < 2048);
+    }
+
+    #[test]
+    fn test_dumper_skips_frames() {
+        let dumper = FrameDumper::new("unused", ImageFormat::Ppm, 10);
+
+        assert!(dumper.dump(3, &Frame::new()).unwrap().is_none());
+        assert_eq!(dumper.path_for(20), PathBuf::from("unused/frame_000020.ppm"));
+    }
+}
@@ -0,0 +1,50 @@
+use crate::palette::Palette;
+
+// what the PPU draws into. each pixel is a 9 bit palette index (color + emphasis), see palette.rs
+#[derive(Clone)]
+pub struct Frame {
+    pub pixels: Vec<u16>,
+}
+
+impl Frame {
+    pub const WIDTH: usize = 256;
+    pub const HEIGHT: usize = 240;
+
+    pub fn new() -> Self {
+        Frame {
+            pixels: vec![0; Frame::WIDTH * Frame::HEIGHT],
+        }
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, index: u16) {
+        self.pixels[y * Frame::WIDTH + x] = index;
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> u16 {
+        self.pixels[y * Frame::WIDTH + x]
+    }
+
+    pub fn to_rgb(&self, palette: &Palette) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|&p| {
+                let (r, g, b) = palette.rgb(p);
+                [r, g, b]
+            })
+            .collect()
+    }
+
+    pub fn to_rgba8888(&self, palette: &Palette) -> Vec<u8> {
+        palette.to_rgba8888(&self.pixels)
+    }
+
+    pub fn to_rgb565(&self, palette: &Palette) -> Vec<u16> {
+        palette.to_rgb565(&self.pixels)
+    }
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Frame::new()
+    }
+}
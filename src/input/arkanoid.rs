@@ -0,0 +1,98 @@
+use super::{InputContext, InputDevice, ShiftRegister};
+use crate::savestate::{Snapshot, StateReader, StateWriter};
+
+// the Vaus controller from Arkanoid. a strobe latches the knob's position, which then
+// comes out MSB first and inverted, with the fire button on a separate line
+// https://www.nesdev.org/wiki/Arkanoid_controller
+pub struct ArkanoidPaddle {
+    pub position: u8, // the games expect roughly 98-242
+    pub button: bool,
+    famicom: bool, // the Famicom one plugs into the expansion port and uses D1 of both registers
+    shift: ShiftRegister,
+}
+
+impl ArkanoidPaddle {
+    pub fn new(famicom: bool) -> Self {
+        ArkanoidPaddle { position: 98, button: false, famicom, shift: ShiftRegister::default() }
+    }
+
+    // the serial bits in the order they come out
+    fn stream(&self) -> u32 {
+        (!self.position).reverse_bits() as u32
+    }
+}
+
+impl InputDevice for ArkanoidPaddle {
+    fn write(&mut self, data: u8) {
+        let stream = self.stream();
+        self.shift.write(data, stream, 8);
+    }
+
+    fn read(&mut self, port: usize, _ctx: &InputContext) -> u8 {
+        let button = self.button as u8;
+        if !self.famicom {
+            // NES: D3 the button, D4 the position
+            let stream = self.stream();
+            return button << 3 | self.shift.read(stream) << 4;
+        }
+
+        // Famicom: $4016 D1 is the button, $4017 D1 the position
+        match port {
+            0 => button << 1,
+            _ => {
+                let stream = self.stream();
+                self.shift.read(stream) << 1
+            },
+        }
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.position);
+        w.bool(self.button);
+        self.shift.save(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.position = r.u8()?;
+        self.button = r.bool()?;
+        self.shift.load(r)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frame::Frame;
+    use crate::input::test::blank_context;
+
+    #[test]
+    fn test_position_is_inverted_msb_first() {
+        let frame = Frame::new();
+        let ctx = blank_context(&frame);
+
+        let mut paddle = ArkanoidPaddle::new(false);
+        paddle.position = 0b1100_1010;
+        paddle.button = true;
+        paddle.write(1);
+        paddle.write(0);
+
+        let bits: Vec<u8> = (0..8).map(|_| paddle.read(1, &ctx) >> 4 & 1).collect();
+        assert_eq!(bits, vec![0, 0, 1, 1, 0, 1, 0, 1]);
+        assert_eq!(paddle.read(1, &ctx) & 0b1000, 0b1000);
+    }
+
+    #[test]
+    fn test_famicom_version_splits_across_registers() {
+        let frame = Frame::new();
+        let ctx = blank_context(&frame);
+
+        let mut paddle = ArkanoidPaddle::new(true);
+        paddle.position = 0x00;
+        paddle.button = true;
+        paddle.write(1);
+        paddle.write(0);
+
+        assert_eq!(paddle.read(0, &ctx), 0b10);
+        assert_eq!(paddle.read(1, &ctx), 0b10);
+    }
+}
@@ -0,0 +1,235 @@
+use super::{InputContext, InputDevice};
+use crate::savestate::{StateReader, StateWriter};
+
+/*
+ the HVC-007 keyboard that came with Family BASIC, on the expansion port. 72 keys in 9 rows of
+ 8, each row read as two halves of 4 keys on $4017 D1-D4, 0 for a key that's down.
+ $4016 writes: bit 0 goes back to row 0, bit 1 picks the half and going from 1 to 0 moves on
+ a row, bit 2 enables the keyboard
+ https://www.nesdev.org/wiki/Family_BASIC_Keyboard
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FamilyKey {
+    F1, F2, F3, F4, F5, F6, F7, F8,
+    Num0, Num1, Num2, Num3, Num4, Num5, Num6, Num7, Num8, Num9,
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    Return, LeftBracket, RightBracket, Kana, LeftShift, RightShift, Yen, Stop,
+    Semicolon, Colon, At, Caret, Minus, Slash, Underscore, Comma, Period,
+    Ctr, Esc, Grph, ClrHome, Ins, Del, Space, Up, Down, Left, Right,
+}
+
+use FamilyKey::*;
+
+const LETTERS: [FamilyKey; 26] = [A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z];
+const DIGITS: [FamilyKey; 10] = [Num0, Num1, Num2, Num3, Num4, Num5, Num6, Num7, Num8, Num9];
+
+// each row is D4 to D1 of the first half, then D4 to D1 of the second
+const MATRIX: [[FamilyKey; 8]; 9] = [
+    [RightBracket, LeftBracket, Return, F8, Stop, Yen, RightShift, Kana],
+    [Semicolon, Colon, At, F7, Caret, Minus, Slash, Underscore],
+    [K, L, O, F6, Num0, P, Comma, Period],
+    [J, U, I, F5, Num8, Num9, N, M],
+    [H, G, Y, F4, Num6, Num7, V, B],
+    [D, R, T, F3, Num4, Num5, C, F],
+    [A, S, W, F2, Num3, E, Z, X],
+    [Ctr, Q, Esc, F1, Num2, Num1, Grph, LeftShift],
+    [ClrHome, Up, Right, Left, Down, Space, Del, Ins],
+];
+
+impl FamilyKey {
+    // (row, position in the row)
+    fn position(self) -> (usize, usize) {
+        for (row, keys) in MATRIX.iter().enumerate() {
+            if let Some(index) = keys.iter().position(|&key| key == self) {
+                return (row, index);
+            }
+        }
+        unreachable!("every key is in the matrix")
+    }
+
+    /*
+     a host key by name, as most windowing libraries spell them. letters and digits go by
+     their own character, and the keys the Famicom has that a PC doesn't land nearby:
+       Backspace/Delete -> DEL      Home -> CLR HOME    End/Pause -> STOP
+       Left Ctrl -> CTR             Left Alt -> GRPH    Right Alt -> KANA
+       \ -> ¥   ' -> :   ` -> @   = -> ^   Right Ctrl -> _
+    */
+    pub fn from_host_key(name: &str) -> Option<FamilyKey> {
+        let name = name.to_ascii_lowercase();
+        let mut chars = name.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            let key = match c {
+                'a'..='z' => Some(LETTERS[c as usize - 'a' as usize]),
+                '0'..='9' => Some(DIGITS[c as usize - '0' as usize]),
+                '[' => Some(LeftBracket),
+                ']' => Some(RightBracket),
+                ';' => Some(Semicolon),
+                '\'' => Some(Colon),
+                '`' => Some(At),
+                '=' => Some(Caret),
+                '-' => Some(Minus),
+                '/' => Some(Slash),
+                ',' => Some(Comma),
+                '.' => Some(Period),
+                '\\' => Some(Yen),
+                ' ' => Some(Space),
+                _ => None,
+            };
+            return key;
+        }
+
+        Some(match name.as_str() {
+            "f1" => F1,
+            "f2" => F2,
+            "f3" => F3,
+            "f4" => F4,
+            "f5" => F5,
+            "f6" => F6,
+            "f7" => F7,
+            "f8" => F8,
+            "return" | "enter" => Return,
+            "escape" | "esc" => Esc,
+            "space" => Space,
+            "backspace" | "delete" => Del,
+            "insert" => Ins,
+            "home" => ClrHome,
+            "end" | "pause" => Stop,
+            "up" => Up,
+            "down" => Down,
+            "left" => Left,
+            "right" => Right,
+            "left shift" | "lshift" | "shift" => LeftShift,
+            "right shift" | "rshift" => RightShift,
+            "left ctrl" | "lctrl" | "ctrl" => Ctr,
+            "right ctrl" | "rctrl" => Underscore,
+            "left alt" | "lalt" | "alt" => Grph,
+            "right alt" | "ralt" => Kana,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct FamilyKeyboard {
+    keys: [u8; 9], // a bit per key, in MATRIX order
+    row: usize,
+    second_half: bool,
+    enabled: bool,
+}
+
+impl FamilyKeyboard {
+    pub fn new() -> Self {
+        FamilyKeyboard::default()
+    }
+
+    pub fn set_key(&mut self, key: FamilyKey, pressed: bool) {
+        let (row, index) = key.position();
+        if pressed {
+            self.keys[row] |= 1 << index;
+        } else {
+            self.keys[row] &= !(1 << index);
+        }
+    }
+
+    pub fn release_all(&mut self) {
+        self.keys = [0; 9];
+    }
+}
+
+impl InputDevice for FamilyKeyboard {
+    fn write(&mut self, data: u8) {
+        self.enabled = data & 0b100 != 0;
+        let second_half = data & 0b010 != 0;
+
+        if data & 0b001 != 0 {
+            self.row = 0;
+        } else if self.second_half && !second_half {
+            self.row += 1;
+        }
+        self.second_half = second_half;
+    }
+
+    fn read(&mut self, port: usize, _ctx: &InputContext) -> u8 {
+        if port == 0 || !self.enabled {
+            return 0;
+        }
+        // past the last row nothing is down
+        let Some(&row) = self.keys.get(self.row) else {
+            return 0b1_1110;
+        };
+
+        let half = if self.second_half { row >> 4 } else { row & 0x0F };
+        (0..4).fold(0, |data, i| if half & (1 << i) == 0 { data | 1 << (4 - i) } else { data })
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.keys);
+        w.usize(self.row);
+        w.bool(self.second_half);
+        w.bool(self.enabled);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), String> {
+        r.bytes(&mut self.keys)?;
+        self.row = r.usize()?;
+        self.second_half = r.bool()?;
+        self.enabled = r.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frame::Frame;
+    use crate::input::test::blank_context;
+
+    // the way Family BASIC scans, both halves of each row
+    fn scan(keyboard: &mut FamilyKeyboard) -> Vec<u8> {
+        let frame = Frame::new();
+        let ctx = blank_context(&frame);
+        keyboard.write(0b101);
+        let mut reads = Vec::new();
+        for _ in 0..9 {
+            keyboard.write(0b100);
+            reads.push(keyboard.read(1, &ctx));
+            keyboard.write(0b110);
+            reads.push(keyboard.read(1, &ctx));
+        }
+        reads
+    }
+
+    #[test]
+    fn test_matrix_scan() {
+        let mut keyboard = FamilyKeyboard::new();
+        assert!(scan(&mut keyboard).iter().all(|&read| read == 0b1_1110));
+
+        keyboard.set_key(FamilyKey::Return, true);
+        keyboard.set_key(FamilyKey::Ins, true);
+        let reads = scan(&mut keyboard);
+        assert_eq!(reads[0], 0b1_1010); // RETURN is D2 of row 0's first half
+        assert_eq!(reads[17], 0b1_1100); // INS is D1 of row 8's second half
+        assert_eq!(reads.iter().filter(|&&read| read != 0b1_1110).count(), 2);
+
+        keyboard.set_key(FamilyKey::Return, false);
+        assert_eq!(scan(&mut keyboard)[0], 0b1_1110);
+    }
+
+    #[test]
+    fn test_disabled_reads_nothing() {
+        let frame = Frame::new();
+        let mut keyboard = FamilyKeyboard::new();
+        keyboard.write(0b001);
+        assert_eq!(keyboard.read(1, &blank_context(&frame)), 0);
+    }
+
+    #[test]
+    fn test_host_keys() {
+        assert_eq!(FamilyKey::from_host_key("q"), Some(FamilyKey::Q));
+        assert_eq!(FamilyKey::from_host_key("7"), Some(FamilyKey::Num7));
+        assert_eq!(FamilyKey::from_host_key("Backspace"), Some(FamilyKey::Del));
+        assert_eq!(FamilyKey::from_host_key("Left Alt"), Some(FamilyKey::Grph));
+        assert_eq!(FamilyKey::from_host_key("F9"), None);
+    }
+}
@@ -0,0 +1,160 @@
+use super::{InputContext, InputDevice, ShiftRegister};
+use crate::joypad::{ButtonState, JoypadButton};
+use crate::savestate::{Snapshot, StateReader, StateWriter};
+
+// pads come out one after the other, then 8 bits telling the game an adapter is there
+// https://www.nesdev.org/wiki/Four_player_adapters
+const STREAM_BITS: u8 = 24;
+
+// in read order, LSB first
+const FOUR_SCORE_SIGNATURES: [u8; 2] = [0b0000_1000, 0b0000_0100];
+const HORI_SIGNATURES: [u8; 2] = [0b0000_0100, 0b0000_1000];
+
+// the NES Four Score, one of these per port. port 0 has players 1 and 3, port 1 players 2 and 4
+pub struct FourScore {
+    pads: [JoypadButton; 2],
+    signature: u8,
+    shift: ShiftRegister,
+}
+
+impl FourScore {
+    pub fn new(port: usize) -> Self {
+        FourScore { pads: [JoypadButton::empty(); 2], signature: FOUR_SCORE_SIGNATURES[port], shift: ShiftRegister::default() }
+    }
+
+    fn stream(&self) -> u32 {
+        stream(self.pads, self.signature)
+    }
+}
+
+fn stream(pads: [JoypadButton; 2], signature: u8) -> u32 {
+    pads[0].bits() as u32 | (pads[1].bits() as u32) << 8 | (signature as u32) << 16
+}
+
+impl InputDevice for FourScore {
+    fn write(&mut self, data: u8) {
+        let stream = self.stream();
+        self.shift.write(data, stream, STREAM_BITS);
+    }
+
+    fn read(&mut self, _port: usize, _ctx: &InputContext) -> u8 {
+        let stream = self.stream();
+        self.shift.read(stream)
+    }
+
+    fn set_buttons(&mut self, index: usize, buttons: ButtonState) {
+        self.pads[index] = buttons.into();
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.pads[0].bits());
+        w.u8(self.pads[1].bits());
+        w.u8(self.signature);
+        self.shift.save(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), String> {
+        for pad in self.pads.iter_mut() {
+            *pad = JoypadButton::from_bits_truncate(r.u8()?);
+        }
+        self.signature = r.u8()?;
+        self.shift.load(r)
+    }
+}
+
+// the Famicom side: players 3 and 4 come in through the expansion port on D1, with the
+// signature the other way round from the Four Score
+pub struct FamicomFourPlayer {
+    pads: [JoypadButton; 2],
+    shifts: [ShiftRegister; 2],
+}
+
+impl Default for FamicomFourPlayer {
+    fn default() -> Self {
+        FamicomFourPlayer::new()
+    }
+}
+
+impl FamicomFourPlayer {
+    pub fn new() -> Self {
+        FamicomFourPlayer { pads: [JoypadButton::empty(); 2], shifts: Default::default() }
+    }
+
+    // the adapter's stream for a port carries its pad, then 8 blank bits, then the signature
+    fn stream(&self, port: usize) -> u32 {
+        stream([self.pads[port], JoypadButton::empty()], HORI_SIGNATURES[port])
+    }
+}
+
+impl InputDevice for FamicomFourPlayer {
+    fn write(&mut self, data: u8) {
+        for port in 0..2 {
+            let stream = self.stream(port);
+            self.shifts[port].write(data, stream, STREAM_BITS);
+        }
+    }
+
+    fn read(&mut self, port: usize, _ctx: &InputContext) -> u8 {
+        let stream = self.stream(port);
+        self.shifts[port].read(stream) << 1
+    }
+
+    // 0 is player 3, 1 player 4
+    fn set_buttons(&mut self, index: usize, buttons: ButtonState) {
+        self.pads[index] = buttons.into();
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        for (pad, shift) in self.pads.iter().zip(&self.shifts) {
+            w.u8(pad.bits());
+            shift.save(w);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), String> {
+        for (pad, shift) in self.pads.iter_mut().zip(self.shifts.iter_mut()) {
+            *pad = JoypadButton::from_bits_truncate(r.u8()?);
+            shift.load(r)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frame::Frame;
+    use crate::input::test::blank_context;
+
+    fn read_stream(device: &mut dyn InputDevice, port: usize) -> Vec<u8> {
+        let frame = Frame::new();
+        let ctx = blank_context(&frame);
+        device.write(1);
+        device.write(0);
+        (0..25).map(|_| device.read(port, &ctx)).collect()
+    }
+
+    #[test]
+    fn test_four_score_stream() {
+        let mut four_score = FourScore::new(1);
+        four_score.set_buttons(0, ButtonState { a: true, ..Default::default() });
+        four_score.set_buttons(1, ButtonState { start: true, ..Default::default() });
+
+        let reads = read_stream(&mut four_score, 1);
+        let stream = reads[..24].iter().enumerate().fold(0u32, |stream, (bit, &read)| stream | (read as u32) << bit);
+        assert_eq!(stream, 0x01 | 0x08 << 8 | 0x04 << 16);
+        assert_eq!(reads[24], 1);
+    }
+
+    #[test]
+    fn test_famicom_adapter_uses_d1() {
+        let mut adapter = FamicomFourPlayer::new();
+        adapter.set_buttons(0, ButtonState { b: true, ..Default::default() });
+
+        let reads = read_stream(&mut adapter, 0);
+        assert!(reads.iter().all(|&read| read & !0b10 == 0));
+        assert_eq!(reads[1], 0b10);
+        assert_eq!(reads[16 + 2], 0b10); // the signature
+        assert_eq!(reads.iter().filter(|&&read| read != 0).count(), 3); // and the 1 after it all
+    }
+}
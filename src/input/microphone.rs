@@ -0,0 +1,26 @@
+// the microphone on the Famicom's second controller shows up as $4016 D2. it goes through a
+// comparator, so all a game sees is whether something loud enough is coming in
+
+// RMS level of host audio input (samples -1 to 1) that counts as blowing into it
+pub const DEFAULT_THRESHOLD: f32 = 0.1;
+
+// for driving the mic bit from recorded or live audio, a frame's worth at a time
+pub fn mic_active(samples: &[f32], threshold: f32) -> bool {
+    if samples.is_empty() {
+        return false;
+    }
+    let power = samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32;
+    power.sqrt() >= threshold
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_threshold() {
+        assert!(!mic_active(&[], DEFAULT_THRESHOLD));
+        assert!(!mic_active(&[0.01, -0.02, 0.01], DEFAULT_THRESHOLD));
+        assert!(mic_active(&[0.5, -0.5, 0.4, -0.3], DEFAULT_THRESHOLD));
+    }
+}
@@ -0,0 +1,186 @@
+use std::any::Any;
+
+use crate::frame::Frame;
+use crate::joypad::{ButtonState, Joypad};
+use crate::palette::Palette;
+use crate::savestate::{Snapshot, StateReader, StateWriter};
+
+pub mod arkanoid;
+pub mod family_keyboard;
+pub mod four_score;
+pub mod microphone;
+pub mod power_pad;
+pub mod zapper;
+
+use arkanoid::ArkanoidPaddle;
+use family_keyboard::FamilyKeyboard;
+use four_score::{FamicomFourPlayer, FourScore};
+use power_pad::PowerPad;
+use zapper::Zapper;
+
+/*
+ anything plugged into the two controller ports or the Famicom expansion port. all of them
+ see every $4016 write, and a read of $4016 (port 0) or $4017 (port 1) is whatever the port's
+ device and the expansion device drive on D0-D4 ORed together. D5-D7 are open bus.
+ frontends get at a particular device's state with Bus::device_mut
+*/
+pub trait InputDevice: Any {
+    // OUT0-OUT2 in the low 3 bits
+    fn write(&mut self, data: u8);
+
+    // port is which register is being read, 0 for $4016 and 1 for $4017
+    fn read(&mut self, port: usize, ctx: &InputContext) -> u8;
+
+    // for devices made of standard pads, index counts pads within the device
+    fn set_buttons(&mut self, _index: usize, _buttons: ButtonState) {}
+
+    // for save states. loading only works into the same kind of device that was saved
+    fn save_state(&self, _w: &mut StateWriter) {}
+
+    fn load_state(&mut self, _r: &mut StateReader) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// what a device can see of the console when it's read, the Zapper looks at the screen
+pub struct InputContext<'a> {
+    pub frame: &'a Frame,
+    pub palette: &'a Palette, // the colors the frame comes out in, see Bus::set_palette
+    pub scanline: u16,
+    pub dot: u16,
+}
+
+// an empty port
+pub struct Unplugged;
+
+impl InputDevice for Unplugged {
+    fn write(&mut self, _data: u8) {}
+
+    fn read(&mut self, _port: usize, _ctx: &InputContext) -> u8 {
+        0
+    }
+}
+
+// what's plugged in where
+pub struct InputSetup {
+    pub ports: [Box<dyn InputDevice>; 2],
+    pub expansion: Option<Box<dyn InputDevice>>,
+}
+
+impl Default for InputSetup {
+    fn default() -> Self {
+        InputSetup { ports: [Box::new(Joypad::new()), Box::new(Joypad::new())], expansion: None }
+    }
+}
+
+impl InputSetup {
+    // from the NES 2.0 default expansion device byte. anything not emulated gets two pads
+    // https://www.nesdev.org/wiki/NES_2.0#Default_Expansion_Device
+    pub fn from_header(device: u8) -> Self {
+        let joypad = || -> Box<dyn InputDevice> { Box::new(Joypad::new()) };
+        match device {
+            0x02 => InputSetup { ports: [Box::new(FourScore::new(0)), Box::new(FourScore::new(1))], expansion: None },
+            0x03 => InputSetup { ports: [joypad(), joypad()], expansion: Some(Box::new(FamicomFourPlayer::new())) },
+            0x08 => InputSetup { ports: [joypad(), Box::new(Zapper::new())], expansion: None },
+            0x09 => InputSetup { ports: [Box::new(Zapper::new()), Box::new(Zapper::new())], expansion: None },
+            0x0B | 0x0C => InputSetup { ports: [joypad(), Box::new(PowerPad::new())], expansion: None },
+            0x0F => InputSetup { ports: [joypad(), Box::new(ArkanoidPaddle::new(false))], expansion: None },
+            0x10 => InputSetup { ports: [joypad(), joypad()], expansion: Some(Box::new(ArkanoidPaddle::new(true))) },
+            0x23 => InputSetup { ports: [joypad(), joypad()], expansion: Some(Box::new(FamilyKeyboard::new())) },
+            _ => InputSetup::default(),
+        }
+    }
+}
+
+// shifts out a value LSB first while strobe is low, reloading it while strobe is high.
+// once everything's out it keeps returning 1 like the official pads do
+#[derive(Default, Clone)]
+pub struct ShiftRegister {
+    strobe: bool,
+    bits: u32,
+    remaining: u8,
+}
+
+impl ShiftRegister {
+    // value is what gets latched, length how many bits of it there are
+    pub fn write(&mut self, data: u8, value: u32, length: u8) {
+        self.strobe = data & 1 != 0;
+        if self.strobe {
+            self.bits = value;
+            self.remaining = length;
+        }
+    }
+
+    // value as of now, while strobe is high the first bit follows it
+    pub fn read(&mut self, value: u32) -> u8 {
+        if self.strobe {
+            return (value & 1) as u8;
+        }
+        if self.remaining == 0 {
+            return 1;
+        }
+        let bit = (self.bits & 1) as u8;
+        self.bits >>= 1;
+        self.remaining -= 1;
+        bit
+    }
+}
+
+impl Snapshot for ShiftRegister {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.strobe);
+        w.u32(self.bits);
+        w.u8(self.remaining);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.strobe = r.bool()?;
+        self.bits = r.u32()?;
+        self.remaining = r.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use lazy_static::lazy_static;
+
+    lazy_static! {
+        static ref PALETTE: Palette = Palette::default();
+    }
+
+    pub fn blank_context(frame: &Frame) -> InputContext<'_> {
+        InputContext { frame, palette: &PALETTE, scanline: 0, dot: 0 }
+    }
+
+    #[test]
+    fn test_shift_register() {
+        let mut shift = ShiftRegister::default();
+        shift.write(1, 0b101, 3);
+        assert_eq!(shift.read(0b101), 1);
+        assert_eq!(shift.read(0b100), 0);
+        shift.write(0, 0b101, 3);
+
+        let bits: Vec<u8> = (0..5).map(|_| shift.read(0)).collect();
+        assert_eq!(bits, vec![1, 0, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_header_defaults() {
+        let frame = Frame::new();
+        let ctx = blank_context(&frame);
+
+        let mut setup = InputSetup::from_header(0x08);
+        let zapper = (setup.ports[1].as_mut() as &mut dyn Any).downcast_mut::<Zapper>();
+        assert!(zapper.is_some());
+        // no light, trigger up
+        assert_eq!(setup.ports[1].read(1, &ctx), 0b0000_1000);
+
+        let setup = InputSetup::from_header(0x10);
+        assert!(setup.expansion.is_some());
+
+        let setup = InputSetup::from_header(0x00);
+        assert!((setup.ports[0].as_ref() as &dyn Any).is::<Joypad>());
+    }
+}
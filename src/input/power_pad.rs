@@ -0,0 +1,91 @@
+use super::{InputContext, InputDevice, ShiftRegister};
+use crate::savestate::{Snapshot, StateReader, StateWriter};
+
+// the Power Pad mat (and the Family Trainer, same thing). 12 buttons numbered as on side B,
+// read out over two lines at once
+// https://www.nesdev.org/wiki/Power_Pad
+
+// which button each bit of the two streams is, 1 based
+const D3_ORDER: [usize; 8] = [2, 1, 5, 9, 6, 10, 11, 7];
+const D4_ORDER: [usize; 4] = [4, 3, 12, 8];
+
+pub struct PowerPad {
+    pub buttons: [bool; 12], // button 1 is index 0
+    shifts: [ShiftRegister; 2],
+}
+
+impl Default for PowerPad {
+    fn default() -> Self {
+        PowerPad::new()
+    }
+}
+
+impl PowerPad {
+    pub fn new() -> Self {
+        PowerPad { buttons: [false; 12], shifts: Default::default() }
+    }
+
+    fn stream(&self, order: &[usize]) -> u32 {
+        order.iter().enumerate().fold(0, |stream, (bit, &button)| stream | (self.buttons[button - 1] as u32) << bit)
+    }
+}
+
+impl InputDevice for PowerPad {
+    fn write(&mut self, data: u8) {
+        let d3 = self.stream(&D3_ORDER);
+        let d4 = self.stream(&D4_ORDER);
+        self.shifts[0].write(data, d3, 8);
+        // only 4 buttons on this one, the rest of the 8 read as 1
+        self.shifts[1].write(data, d4 | 0xF0, 8);
+    }
+
+    fn read(&mut self, _port: usize, _ctx: &InputContext) -> u8 {
+        let d3 = self.stream(&D3_ORDER);
+        let d4 = self.stream(&D4_ORDER);
+        self.shifts[0].read(d3) << 3 | self.shifts[1].read(d4) << 4
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        for &button in &self.buttons {
+            w.bool(button);
+        }
+        for shift in &self.shifts {
+            shift.save(w);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), String> {
+        for button in self.buttons.iter_mut() {
+            *button = r.bool()?;
+        }
+        for shift in self.shifts.iter_mut() {
+            shift.load(r)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frame::Frame;
+    use crate::input::test::blank_context;
+
+    #[test]
+    fn test_button_order() {
+        let frame = Frame::new();
+        let ctx = blank_context(&frame);
+
+        let mut pad = PowerPad::new();
+        pad.buttons[0] = true; // 1
+        pad.buttons[11] = true; // 12
+        pad.write(1);
+        pad.write(0);
+
+        let reads: Vec<u8> = (0..8).map(|_| pad.read(1, &ctx)).collect();
+        let d3: Vec<u8> = reads.iter().map(|r| r >> 3 & 1).collect();
+        let d4: Vec<u8> = reads.iter().map(|r| r >> 4 & 1).collect();
+        assert_eq!(d3, vec![0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(d4, vec![0, 0, 1, 0, 1, 1, 1, 1]);
+    }
+}
@@ -0,0 +1,106 @@
+use super::{InputContext, InputDevice};
+use crate::frame::Frame;
+use crate::savestate::{StateReader, StateWriter};
+
+// the photodiode only stays lit for a couple of dozen scanlines after the beam goes past
+// https://www.nesdev.org/wiki/Zapper
+const LIGHT_SCANLINES: usize = 20;
+
+// average of r, g and b in the palette the console's showing, anything at least this bright is
+// seen. on the standard palette white, light greys and the pastel row pass, the darker rows don't
+const BRIGHTNESS_THRESHOLD: u16 = 0x80;
+
+// the NES Zapper. D3 is the light sensor, low while it sees light, and D4 is the trigger
+pub struct Zapper {
+    pub aim: Option<(usize, usize)>, // None is pointing off screen
+    pub trigger: bool,
+}
+
+impl Default for Zapper {
+    fn default() -> Self {
+        Zapper::new()
+    }
+}
+
+impl Zapper {
+    pub fn new() -> Self {
+        Zapper { aim: None, trigger: false }
+    }
+
+    fn sees_light(&self, ctx: &InputContext) -> bool {
+        let Some((x, y)) = self.aim else {
+            return false;
+        };
+        if x >= Frame::WIDTH || y >= Frame::HEIGHT {
+            return false;
+        }
+
+        // lines get drawn at the start of the scanline, so the aimed one has to be this
+        // one or a recent one in the current frame
+        let scanline = ctx.scanline as usize;
+        if y > scanline || scanline - y > LIGHT_SCANLINES {
+            return false;
+        }
+
+        let (r, g, b) = ctx.palette.rgb(ctx.frame.get_pixel(x, y));
+        (r as u16 + g as u16 + b as u16) / 3 >= BRIGHTNESS_THRESHOLD
+    }
+}
+
+impl InputDevice for Zapper {
+    fn write(&mut self, _data: u8) {}
+
+    fn read(&mut self, _port: usize, ctx: &InputContext) -> u8 {
+        let light = if self.sees_light(ctx) { 0 } else { 0b0000_1000 };
+        let trigger = if self.trigger { 0b0001_0000 } else { 0 };
+        light | trigger
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.option(&self.aim, |w, &(x, y)| {
+            w.usize(x);
+            w.usize(y);
+        });
+        w.bool(self.trigger);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.aim = r.option(|r| Ok((r.usize()?, r.usize()?)))?;
+        self.trigger = r.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::palette::Palette;
+
+    #[test]
+    fn test_light_sense() {
+        let mut frame = Frame::new();
+        frame.set_pixel(100, 50, 0x30); // white
+        frame.set_pixel(101, 50, 0x0F); // black
+
+        let mut zapper = Zapper::new();
+        zapper.aim = Some((100, 50));
+        zapper.trigger = true;
+
+        let palette = Palette::default();
+        let read_at = |zapper: &mut Zapper, scanline| zapper.read(1, &InputContext { frame: &frame, palette: &palette, scanline, dot: 0 });
+
+        assert_eq!(read_at(&mut zapper, 55), 0b0001_0000);
+        // not drawn yet, and long since faded
+        assert_eq!(read_at(&mut zapper, 49), 0b0001_1000);
+        assert_eq!(read_at(&mut zapper, 80), 0b0001_1000);
+
+        zapper.aim = Some((101, 50));
+        zapper.trigger = false;
+        assert_eq!(read_at(&mut zapper, 55), 0b0000_1000);
+
+        // white is only white if the palette says so
+        zapper.aim = Some((100, 50));
+        let dark = Palette::from_pal(&[0x20; 64 * 3]).unwrap();
+        assert_eq!(zapper.read(1, &InputContext { frame: &frame, palette: &dark, scanline: 55, dot: 0 }), 0b0000_1000);
+    }
+}
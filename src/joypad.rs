@@ -0,0 +1,158 @@
+use bitflags::bitflags;
+
+use crate::input::{InputContext, InputDevice};
+use crate::savestate::{StateReader, StateWriter};
+
+bitflags! {
+    // the order they come out of the shift register, A first
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct JoypadButton: u8 {
+        const A = 0b0000_0001;
+        const B = 0b0000_0010;
+        const Select = 0b0000_0100;
+        const Start = 0b0000_1000;
+        const Up = 0b0001_0000;
+        const Down = 0b0010_0000;
+        const Left = 0b0100_0000;
+        const Right = 0b1000_0000;
+    }
+}
+
+// what a frontend holds down on one controller
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ButtonState {
+    pub a: bool,
+    pub b: bool,
+    pub select: bool,
+    pub start: bool,
+    pub up: bool,
+    pub down: bool,
+    pub left: bool,
+    pub right: bool,
+}
+
+impl From<ButtonState> for JoypadButton {
+    fn from(state: ButtonState) -> Self {
+        let mut buttons = JoypadButton::empty();
+        buttons.set(JoypadButton::A, state.a);
+        buttons.set(JoypadButton::B, state.b);
+        buttons.set(JoypadButton::Select, state.select);
+        buttons.set(JoypadButton::Start, state.start);
+        buttons.set(JoypadButton::Up, state.up);
+        buttons.set(JoypadButton::Down, state.down);
+        buttons.set(JoypadButton::Left, state.left);
+        buttons.set(JoypadButton::Right, state.right);
+        buttons
+    }
+}
+
+impl From<JoypadButton> for ButtonState {
+    fn from(buttons: JoypadButton) -> Self {
+        ButtonState {
+            a: buttons.contains(JoypadButton::A),
+            b: buttons.contains(JoypadButton::B),
+            select: buttons.contains(JoypadButton::Select),
+            start: buttons.contains(JoypadButton::Start),
+            up: buttons.contains(JoypadButton::Up),
+            down: buttons.contains(JoypadButton::Down),
+            left: buttons.contains(JoypadButton::Left),
+            right: buttons.contains(JoypadButton::Right),
+        }
+    }
+}
+
+// the standard controller. writing 1 to $4016 keeps reloading the shift register,
+// writing 0 freezes it so the buttons can be read out one bit at a time
+#[derive(Default, Clone)]
+pub struct Joypad {
+    pub buttons: JoypadButton,
+    strobe: bool,
+    index: u8,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad::default()
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.index = 0;
+        }
+    }
+
+    // only bit 0, the rest of the byte is up to the bus
+    pub fn read(&mut self) -> u8 {
+        // all 8 read out, official pads send 1s from here on
+        if self.index > 7 {
+            return 1;
+        }
+
+        let bit = (self.buttons.bits() >> self.index) & 1;
+        if !self.strobe {
+            self.index += 1;
+        }
+        bit
+    }
+}
+
+impl InputDevice for Joypad {
+    fn write(&mut self, data: u8) {
+        Joypad::write(self, data);
+    }
+
+    fn read(&mut self, _port: usize, _ctx: &InputContext) -> u8 {
+        Joypad::read(self)
+    }
+
+    fn set_buttons(&mut self, _index: usize, buttons: ButtonState) {
+        self.buttons = buttons.into();
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u8(self.buttons.bits());
+        w.bool(self.strobe);
+        w.u8(self.index);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.buttons = JoypadButton::from_bits_truncate(r.u8()?);
+        self.strobe = r.bool()?;
+        self.index = r.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reads_buttons_in_order() {
+        let mut joypad = Joypad::new();
+        joypad.buttons = JoypadButton::A | JoypadButton::Start | JoypadButton::Right;
+        joypad.write(1);
+        joypad.write(0);
+
+        let bits: Vec<u8> = (0..10).map(|_| joypad.read()).collect();
+        assert_eq!(bits, vec![1, 0, 0, 1, 0, 0, 0, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_button_state_round_trips() {
+        let state = ButtonState { b: true, up: true, ..Default::default() };
+        let buttons = JoypadButton::from(state);
+        assert_eq!(buttons, JoypadButton::B | JoypadButton::Up);
+        assert_eq!(ButtonState::from(buttons), state);
+    }
+
+    #[test]
+    fn test_strobe_high_keeps_returning_a() {
+        let mut joypad = Joypad::new();
+        joypad.buttons = JoypadButton::A;
+        joypad.write(1);
+        assert_eq!(joypad.read(), 1);
+        assert_eq!(joypad.read(), 1);
+    }
+}
@@ -1,740 +1,410 @@
-use std::collections::HashMap;
-
-use lazy_static::lazy_static;
-use opcode::OpCode;
-use cpu::AddressingMode;
-
-use crate::opcode::OpCodeName;
-
-pub mod cpu;
-pub mod opcode;
-
-// pub static OPCODES: &'static Vec<OpCode> = &vec![
-//     OpCode{
-//         byte: 0xA9,
-//         name: "LDA",
-//         len: 2,
-//         cycles: 2,
-//         mode: cpu::AddressingMode::Immediate,
-//     },
-    
-// ];
-
-lazy_static!{
-    pub static ref OPCODES: Vec<OpCode> = vec![
-        OpCode { byte:0xA9, name:OpCodeName::LDA, len:2, cycles:2, mode:AddressingMode::Immediate },
-        OpCode { byte:0xA5, name:OpCodeName::LDA, len:2, cycles:3, mode:AddressingMode::ZeroPage },
-        OpCode { byte:0xB5, name:OpCodeName::LDA, len:2, cycles:4, mode:AddressingMode::ZeroPage_X },
-        OpCode { byte:0xAD, name:OpCodeName::LDA, len:3, cycles:4, mode:AddressingMode::Absolute },
-        OpCode { byte:0xBD, name:OpCodeName::LDA, len:3, cycles:4, mode:AddressingMode::Absolute_X },
-        OpCode { byte:0xB9, name:OpCodeName::LDA, len:3, cycles:4, mode:AddressingMode::Absolute_Y },
-        OpCode { byte:0xA1, name:OpCodeName::LDA, len:2, cycles:6, mode:AddressingMode::Indirect_X },
-        OpCode { byte:0xB1, name:OpCodeName::LDA, len:2, cycles:5, mode:AddressingMode::Indirect_Y },
-        OpCode { byte:0xA2, name:OpCodeName::LDX, len:2, cycles:2, mode:AddressingMode::Immediate },
-        OpCode { byte:0xA6, name:OpCodeName::LDX, len:2, cycles:3, mode:AddressingMode::ZeroPage },
-        OpCode { byte:0xB6, name:OpCodeName::LDX, len:2, cycles:4, mode:AddressingMode::ZeroPage_Y },
-        OpCode { byte:0xAE, name:OpCodeName::LDX, len:3, cycles:4, mode:AddressingMode::Absolute },
-        OpCode { byte:0xBE, name:OpCodeName::LDX, len:3, cycles:4, mode:AddressingMode::Absolute_Y },
-        OpCode { byte:0x85, name:OpCodeName::STA, len:2, cycles:3, mode:AddressingMode::ZeroPage },
-        OpCode { byte:0x95, name:OpCodeName::STA, len:2, cycles:4, mode:AddressingMode::ZeroPage_X },
-        OpCode { byte:0x8D, name:OpCodeName::STA, len:3, cycles:4, mode:AddressingMode::Absolute },
-        OpCode { byte:0x9D, name:OpCodeName::STA, len:3, cycles:5, mode:AddressingMode::Absolute_X },
-        OpCode { byte:0x99, name:OpCodeName::STA, len:3, cycles:5, mode:AddressingMode::Absolute_Y },
-        OpCode { byte:0x81, name:OpCodeName::STA, len:2, cycles:6, mode:AddressingMode::Indirect_X },
-        OpCode { byte:0x91, name:OpCodeName::STA, len:2, cycles:6, mode:AddressingMode::Indirect_Y },
-        OpCode { byte:0xAA, name:OpCodeName::TAX, len:1, cycles:2, mode:AddressingMode::NonAddressing },
-        OpCode { byte:0xE8, name:OpCodeName::INX, len:1, cycles:2, mode:AddressingMode::NonAddressing },
-        OpCode { byte:0x00, name:OpCodeName::BRK, len:1, cycles:7, mode:AddressingMode::NonAddressing },
-        OpCode { byte:0x48, name:OpCodeName::PHA, len:1, cycles:3, mode:AddressingMode::NonAddressing },
-        OpCode { byte:0x08, name:OpCodeName::PHP, len:1, cycles:3, mode:AddressingMode::NonAddressing },
-        OpCode { byte:0x20, name:OpCodeName::JSR, len:3, cycles:6, mode:AddressingMode::Absolute },
-        OpCode { byte:0x60, name:OpCodeName::RTS, len:1, cycles:6, mode:AddressingMode::NonAddressing },
-        OpCode { byte:0x68, name:OpCodeName::PLA, len:1, cycles:4, mode:AddressingMode::NonAddressing },
-        OpCode { byte:0x28, name:OpCodeName::PLP, len:1, cycles:4, mode:AddressingMode::NonAddressing },
-        OpCode { byte:0x40, name:OpCodeName::RTI, len:1, cycles:6, mode:AddressingMode::NonAddressing },
-        OpCode { byte:0x69, name:OpCodeName::ADC, len:2, cycles:2, mode:AddressingMode::Immediate },
-        OpCode { byte:0x65, name:OpCodeName::ADC, len:2, cycles:3, mode:AddressingMode::ZeroPage },
-        OpCode { byte:0x75, name:OpCodeName::ADC, len:2, cycles:4, mode:AddressingMode::ZeroPage_X },
-        OpCode { byte:0x6D, name:OpCodeName::ADC, len:3, cycles:4, mode:AddressingMode::Absolute },
-        OpCode { byte:0x7D, name:OpCodeName::ADC, len:3, cycles:4, mode:AddressingMode::Absolute_X },
-        OpCode { byte:0x79, name:OpCodeName::ADC, len:3, cycles:4, mode:AddressingMode::Absolute_Y },
-        OpCode { byte:0x61, name:OpCodeName::ADC, len:2, cycles:6, mode:AddressingMode::Indirect_X },
-        OpCode { byte:0x71, name:OpCodeName::ADC, len:2, cycles:5, mode:AddressingMode::Indirect_Y },
-        OpCode { byte:0x29, name:OpCodeName::AND, len:2, cycles:2, mode:AddressingMode::Immediate },
-        OpCode { byte:0x25, name:OpCodeName::AND, len:2, cycles:3, mode:AddressingMode::ZeroPage },
-        OpCode { byte:0x35, name:OpCodeName::AND, len:2, cycles:4, mode:AddressingMode::ZeroPage_X },
-        OpCode { byte:0x2D, name:OpCodeName::AND, len:3, cycles:4, mode:AddressingMode::Absolute },
-        OpCode { byte:0x3D, name:OpCodeName::AND, len:3, cycles:4, mode:AddressingMode::Absolute_X },
-        OpCode { byte:0x39, name:OpCodeName::AND, len:3, cycles:4, mode:AddressingMode::Absolute_Y },
-        OpCode { byte:0x21, name:OpCodeName::AND, len:2, cycles:6, mode:AddressingMode::Indirect_X },
-        OpCode { byte:0x31, name:OpCodeName::AND, len:2, cycles:5, mode:AddressingMode::Indirect_Y },
-        OpCode { byte:0x0A, name:OpCodeName::ASL, len:1, cycles:2, mode:AddressingMode::NonAddressing },
-        OpCode { byte:0x06, name:OpCodeName::ASL, len:2, cycles:5, mode:AddressingMode::ZeroPage },
-        OpCode { byte:0x16, name:OpCodeName::ASL, len:2, cycles:6, mode:AddressingMode::ZeroPage_X },
-        OpCode { byte:0x0E, name:OpCodeName::ASL, len:3, cycles:6, mode:AddressingMode::Absolute },
-        OpCode { byte:0x1E, name:OpCodeName::ASL, len:3, cycles:7, mode:AddressingMode::Absolute_X },
-        OpCode { byte:0x90, name:OpCodeName::BCC, len:2, cycles:2, mode:AddressingMode::NonAddressing },
-        OpCode { byte:0xB0, name:OpCodeName::BCS, len:2, cycles:2, mode:AddressingMode::NonAddressing },
-        OpCode { byte:0xF0, name:OpCodeName::BEQ, len:2, cycles:2, mode:AddressingMode::NonAddressing },
-        OpCode { byte:0x2C, name:OpCodeName::BIT, len:3, cycles:4, mode:AddressingMode::Absolute },
-        OpCode { byte:0x89, name:OpCodeName::BIT, len:2, cycles:3, mode:AddressingMode::Immediate },
-        OpCode { byte:0x24, name:OpCodeName::BIT, len:2, cycles:3, mode:AddressingMode::ZeroPage },
-        OpCode { byte:0x30, name:OpCodeName::BMI, len:2, cycles:2, mode:AddressingMode::NonAddressing },
-        OpCode { byte:0xD0, name:OpCodeName::BNE, len:2, cycles:2, mode:AddressingMode::NonAddressing },
-        OpCode { byte:0x10, name:OpCodeName::BPL, len:2, cycles:2, mode:AddressingMode::NonAddressing },
-        OpCode { byte:0x50, name:OpCodeName::BVC, len:2, cycles:2, mode:AddressingMode::NonAddressing },
-        OpCode { byte:0x70, name:OpCodeName::BVS, len:2, cycles:2, mode:AddressingMode::NonAddressing },
-        OpCode { byte:0x18, name:OpCodeName::CLC, len:1, cycles:2, mode:AddressingMode::NonAddressing },
-        OpCode { byte:0xD8, name:OpCodeName::CLD, len:1, cycles:2, mode:AddressingMode::NonAddressing },
-        OpCode { byte:0x58, name:OpCodeName::CLI, len:1, cycles:2, mode:AddressingMode::NonAddressing },
-        OpCode { byte:0xB8, name:OpCodeName::CLV, len:1, cycles:2, mode:AddressingMode::NonAddressing },
-        OpCode { byte:0xC9, name:OpCodeName::CMP, len:2, cycles:2, mode:AddressingMode::Immediate },
-        OpCode { byte:0xC5, name:OpCodeName::CMP, len:2, cycles:3, mode:AddressingMode::ZeroPage },
-        OpCode { byte:0xD5, name:OpCodeName::CMP, len:2, cycles:4, mode:AddressingMode::ZeroPage_X },
-        OpCode { byte:0xCD, name:OpCodeName::CMP, len:3, cycles:4, mode:AddressingMode::Absolute },
-        OpCode { byte:0xDD, name:OpCodeName::CMP, len:3, cycles:4, mode:AddressingMode::Absolute_X },
-        OpCode { byte:0xD9, name:OpCodeName::CMP, len:3, cycles:4, mode:AddressingMode::Absolute_Y },
-        OpCode { byte:0xC1, name:OpCodeName::CMP, len:2, cycles:6, mode:AddressingMode::Indirect_X },
-        OpCode { byte:0xD1, name:OpCodeName::CMP, len:2, cycles:5, mode:AddressingMode::Indirect_Y },
-        OpCode { byte:0xE0, name:OpCodeName::CPX, len:2, cycles:2, mode:AddressingMode::Immediate },
-        OpCode { byte:0xE4, name:OpCodeName::CPX, len:2, cycles:3, mode:AddressingMode::ZeroPage },
-        OpCode { byte:0xEC, name:OpCodeName::CPX, len:3, cycles:4, mode:AddressingMode::Absolute }
-    ];
-    pub static ref OPCODES_MAP: HashMap<u8, &'static OpCode> = {
-        let mut map: HashMap<u8, &OpCode> = HashMap::new();
-        OPCODES.iter().for_each(|x| {
-            map.insert(x.byte, x);
-        });
-        map
-    };
-}
-
-fn main() {
-    // let num: u16 = 0x1234;
-    // let le_num = num.to_le_bytes();
-
-    // println!("{:X} {:X}", le_num[0], le_num[1]);
-    // println!("{}", u16::from_be_bytes([0x01, 0x00]));
-    
-    let num: u8 = 0b1000_0000;
-    // let new_num: u16 = (num as u16) + 1;
-    println!("{:X}: {:#018b}", num as i16, num as i16);
-    // println!("{:X} {:#}", new_num, new_num);
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use nes_emulator::apu;
+use nes_emulator::cpu::CpuCore;
+use nes_emulator::export::{self, FrameDumper, FrameMetadata, ImageFormat};
+use nes_emulator::nes::{Nes, RunAheadMode};
+use nes_emulator::nsf::{Nsf, NsfPlayer};
+use nes_emulator::palette::Palette;
+use nes_emulator::power_on::RamInit;
+use nes_emulator::ppu::debug::Image;
+use nes_emulator::region::Region;
+use nes_emulator::script::InputScript;
+use nes_emulator::wav::AudioCapture;
+
+const USAGE: &str = "usage: nes-emulator <rom.nes | music.nsf | music.nsfe> [options]
+
+  --frames N          how many frames to run (default 60)
+  --seconds N         run for this long instead of a number of frames
+  --region NAME       ntsc, pal or dendy, instead of what the header says
+  --cycle-exact       put every CPU cycle on the bus, slower but more accurate
+  --ram-init NAME     power on RAM: zeros (default), ones, pattern, random or random:SEED
+  --input FILE        scripted button presses, see script.rs for the format
+  --run-ahead N       show frames from 1-4 frames ahead, to hide the game's input lag
+  --second-instance   run ahead on a second machine, so the logs and stems stay clean
+  --load-state FILE   start from a save state instead of power on
+  --save-state FILE   write a save state after the last frame
+  --screenshot FILE   save the last frame, .png or .ppm
+  --dump-every N      save every Nth frame into --dump-dir
+  --dump-dir DIR      where dumped frames go (default frames)
+  --format png|ppm    format for dumped frames (default png)
+  --palette FILE      use a .pal file instead of the built in palette
+  --no-metadata       leave the ROM hash and frame count out of the images
+  --wav FILE          write the mixed audio as a 16 bit WAV
+  --stems DIR         write each channel as its own WAV into DIR
+  --sample-rate N     audio sample rate (default 44100)
+  --vgm FILE          log APU writes into a VGM file
+  --vgm-loop N        put the VGM loop point at the start of frame N
+  --smooth-n163       mix Namco 163 channels together instead of switching between them
+
+NSF files play one track into --wav / --stems:
+  --track N           which track, starting at 1 (default the file's own pick)
+  --length N          seconds before the fade (default the file's time or 150)
+  --fade N            seconds of fade out (default the file's fade or 8)
+  --list              print the track list and stop
+
+PPU state after the last frame, .txt gives text and anything else an image:
+  --patterns FILE          both pattern tables
+  --pattern-palette N      palette 0-7 for --patterns (default greys)
+  --nametables FILE        all four nametables with the scroll window outlined
+  --sprites FILE           the OAM sprite list, as a table or sprite sheet
+  --palette-ram FILE       the 32 bytes of palette RAM
+  --events FILE            PPU and mapper register accesses, .csv for all of them
+                           or an image of one frame
+  --events-frame N         which frame the image shows (default the last one)";
+
+struct Options {
+    rom: PathBuf,
+    frames: u64,
+    seconds: Option<f64>, // turned into frames once the region is known
+    region: Option<Region>,
+    cycle_exact: bool,
+    ram_init: Option<RamInit>,
+    input: Option<PathBuf>,
+    run_ahead: u8,
+    second_instance: bool,
+    load_state: Option<PathBuf>,
+    save_state: Option<PathBuf>,
+    screenshot: Option<PathBuf>,
+    dump_every: Option<u64>,
+    dump_dir: PathBuf,
+    format: ImageFormat,
+    palette: Option<PathBuf>,
+    metadata: bool,
+    wav: Option<PathBuf>,
+    stems: Option<PathBuf>,
+    sample_rate: u32,
+    vgm: Option<PathBuf>,
+    vgm_loop: Option<u64>,
+    smooth_n163: bool,
+    track: Option<u8>,
+    length: Option<f64>,
+    fade: Option<f64>,
+    list: bool,
+    patterns: Option<PathBuf>,
+    pattern_palette: Option<u8>,
+    nametables: Option<PathBuf>,
+    sprites: Option<PathBuf>,
+    palette_ram: Option<PathBuf>,
+    events: Option<PathBuf>,
+    events_frame: Option<u64>,
 }
 
-#[cfg(test)]
-mod test {
-    use std::vec;
-
-    use crate::cpu::{CPU, CPUStatus};
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut rom = None;
+    let mut options = Options {
+        rom: PathBuf::new(),
+        frames: 60,
+        seconds: None,
+        region: None,
+        cycle_exact: false,
+        ram_init: None,
+        input: None,
+        run_ahead: 0,
+        second_instance: false,
+        load_state: None,
+        save_state: None,
+        screenshot: None,
+        dump_every: None,
+        dump_dir: PathBuf::from("frames"),
+        format: ImageFormat::Png,
+        palette: None,
+        metadata: true,
+        wav: None,
+        stems: None,
+        sample_rate: apu::DEFAULT_SAMPLE_RATE,
+        vgm: None,
+        vgm_loop: None,
+        smooth_n163: false,
+        track: None,
+        length: None,
+        fade: None,
+        list: false,
+        patterns: None,
+        pattern_palette: None,
+        nametables: None,
+        sprites: None,
+        palette_ram: None,
+        events: None,
+        events_frame: None,
+    };
 
-    #[test]
-    fn test_0xa9_lda_immediate_load_data() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9, 0x05, 0x00]);
-        assert_eq!(cpu.register_a, 0x05);
-        assert!(!cpu.status.contains(CPUStatus::Zero));
-        assert!(!cpu.status.contains(CPUStatus::Negative));
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{} needs a value", name));
+
+        match arg.as_str() {
+            "--frames" => {
+                options.frames = parse_number(&value("--frames")?)?;
+                options.seconds = None;
+            },
+            "--seconds" => options.seconds = Some(parse_seconds(&value("--seconds")?)?),
+            "--region" => {
+                let name = value("--region")?;
+                options.region = Some(Region::from_name(&name).ok_or(format!("unknown region {}", name))?);
+            },
+            "--cycle-exact" => options.cycle_exact = true,
+            "--ram-init" => {
+                let name = value("--ram-init")?;
+                options.ram_init = Some(RamInit::from_name(&name).ok_or(format!("unknown RAM fill {}", name))?);
+            },
+            "--input" => options.input = Some(PathBuf::from(value("--input")?)),
+            "--run-ahead" => match parse_number(&value("--run-ahead")?)? {
+                frames @ 0..=4 => options.run_ahead = frames as u8,
+                frames => return Err(format!("can't run {} frames ahead, 4 at most", frames)),
+            },
+            "--second-instance" => options.second_instance = true,
+            "--load-state" => options.load_state = Some(PathBuf::from(value("--load-state")?)),
+            "--save-state" => options.save_state = Some(PathBuf::from(value("--save-state")?)),
+            "--screenshot" => options.screenshot = Some(PathBuf::from(value("--screenshot")?)),
+            "--dump-every" => options.dump_every = Some(parse_number(&value("--dump-every")?)?),
+            "--dump-dir" => options.dump_dir = PathBuf::from(value("--dump-dir")?),
+            "--format" => {
+                let name = value("--format")?;
+                options.format = ImageFormat::from_name(&name).ok_or(format!("unknown format {}", name))?;
+            },
+            "--palette" => options.palette = Some(PathBuf::from(value("--palette")?)),
+            "--no-metadata" => options.metadata = false,
+            "--wav" => options.wav = Some(PathBuf::from(value("--wav")?)),
+            "--stems" => options.stems = Some(PathBuf::from(value("--stems")?)),
+            "--vgm" => options.vgm = Some(PathBuf::from(value("--vgm")?)),
+            "--vgm-loop" => options.vgm_loop = Some(parse_number(&value("--vgm-loop")?)?),
+            "--track" => match parse_number(&value("--track")?)? {
+                track @ 1..=255 => options.track = Some(track as u8),
+                track => return Err(format!("track {} is out of range", track)),
+            },
+            "--length" => options.length = Some(parse_seconds(&value("--length")?)?),
+            "--fade" => options.fade = Some(parse_seconds(&value("--fade")?)?),
+            "--list" => options.list = true,
+            "--smooth-n163" => options.smooth_n163 = true,
+            "--sample-rate" => match parse_number(&value("--sample-rate")?)? {
+                rate @ 8_000..=192_000 => options.sample_rate = rate as u32,
+                rate => return Err(format!("sample rate {} is out of range", rate)),
+            },
+            "--patterns" => options.patterns = Some(PathBuf::from(value("--patterns")?)),
+            "--pattern-palette" => match parse_number(&value("--pattern-palette")?)? {
+                n @ 0..=7 => options.pattern_palette = Some(n as u8),
+                n => return Err(format!("pattern palette {} isn't 0-7", n)),
+            },
+            "--nametables" => options.nametables = Some(PathBuf::from(value("--nametables")?)),
+            "--sprites" => options.sprites = Some(PathBuf::from(value("--sprites")?)),
+            "--palette-ram" => options.palette_ram = Some(PathBuf::from(value("--palette-ram")?)),
+            "--events" => options.events = Some(PathBuf::from(value("--events")?)),
+            "--events-frame" => options.events_frame = Some(parse_number(&value("--events-frame")?)?),
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}\n\n{}", arg, USAGE)),
+            _ => rom = Some(PathBuf::from(arg)),
+        }
     }
 
-    #[test]
-    fn test_0xa9_lda_zero_flag() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9, 0x00, 0x00]);
-        assert!(cpu.status.contains(CPUStatus::Zero));
-    }
+    options.rom = rom.ok_or(USAGE.to_string())?;
+    Ok(options)
+}
 
-    #[test]
-    fn test_0xaa_tax_move_a_to_x() { // set reg a value
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9, 0x0a, 0xaa, 0x00]);
+fn parse_number(value: &str) -> Result<u64, String> {
+    value.parse().map_err(|_| format!("{} isn't a number", value))
+}
 
-        assert_eq!(cpu.register_x, 10)
+fn parse_seconds(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(seconds) if seconds >= 0.0 && seconds.is_finite() => Ok(seconds),
+        _ => Err(format!("{} isn't a number of seconds", value)),
     }
+}
 
-    #[test]
-    fn test_5_ops_working_together() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa9, 0xc0, 0xaa, 0xe8, 0x00]);
-
-        assert_eq!(cpu.register_x, 0xc1)
+fn run(options: Options) -> Result<(), String> {
+    let raw = fs::read(&options.rom).map_err(|e| format!("{}: {}", options.rom.display(), e))?;
+    if raw.starts_with(b"NESM\x1A") || raw.starts_with(b"NSFE") {
+        return play_nsf(&options, &raw);
     }
 
-    #[test]
-    fn test_inx_overflow() { // set reg x val
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xa2, 0xff, 0xe8, 0xe8, 0x00]);
-
-        assert_eq!(cpu.register_x, 1)
+    let core = if options.cycle_exact { CpuCore::CycleExact } else { CpuCore::Instruction };
+    let mut nes = Nes::with_core(&raw, core)?;
+    if let Some(region) = options.region {
+        nes.set_region(region);
     }
-
-    #[test]
-    fn test_lda_from_memory() {
-        let mut cpu = CPU::new();
-        cpu.mem_write(0x10, 0x55);
-
-        cpu.load_and_run(vec![0xa5, 0x10, 0x00]);
-
-        assert_eq!(cpu.register_a, 0x55);
+    if let Some(init) = options.ram_init {
+        nes.set_ram_init(init);
     }
+    nes.set_audio_smoothing(options.smooth_n163);
+    let mode = if options.second_instance { RunAheadMode::SecondInstance } else { RunAheadMode::SingleInstance };
+    nes.set_run_ahead(options.run_ahead, mode)?;
+    let rom_crc32 = nes.cpu.bus.cartridge.crc32();
+    let frames_wanted = match options.seconds {
+        Some(seconds) => nes.region().frames_for_seconds(seconds),
+        None => options.frames,
+    };
 
-    #[test]
-    fn test_sta_zeropage() {
-        let mut cpu = CPU::new();
-
-        cpu.load_and_run(vec![
-            0xa9, 0x05, //loads 0x05 into reg a
-            0x85, 0x10, // loads reg a into address 0x10
-        ]);
-
-        let data = cpu.mem_read(0x10);
-        assert_eq!(data, 0x05);
+    let palette = match &options.palette {
+        Some(path) => Palette::load(path)?,
+        None => Palette::new(nes.region().ppu_model()),
+    };
+    if options.palette.is_some() {
+        nes.set_palette(Some(palette.clone()));
     }
 
-    //test pha
-    #[test]
-    fn test_pha() {
-        let mut cpu = CPU::new();
-
-        cpu.load_and_run(vec![
-            0xa9, 0x05, // set 0x05 in reg a
-            0x48, // push a to stack
-            0x00, // break
-        ]);
-
-        // real stack pointer should be at 0x01FF - 0x001
-        assert_eq!(cpu.get_stack_pointer(), 0x1FE);
-
-        // there should be 0x05 at 0x01FF
-        assert_eq!(cpu.mem_read(0x1FF), 0x05);
+    let dumper = options.dump_every.map(|every| {
+        let mut dumper = FrameDumper::new(&options.dump_dir, options.format, every);
+        dumper.palette = palette.clone();
+        if options.metadata {
+            dumper.rom_crc32 = Some(rom_crc32);
+            dumper.frame_count = Some(frames_wanted);
+        }
+        dumper
+    });
+
+    let script = match &options.input {
+        Some(path) => Some(InputScript::load(path)?),
+        None => None,
+    };
 
-        // stack pointer should have the value 0x001
-        assert_eq!(cpu.stack_pointer, 0x01);
+    let mut audio = (options.wav.is_some() || options.stems.is_some())
+        .then(|| AudioCapture::new(&mut nes.cpu.bus.apu, options.sample_rate, options.stems.is_some()));
+    if options.events.is_some() {
+        nes.cpu.bus.start_recording();
     }
-
-    //test php
-    #[test]
-    fn test_php() {
-        let mut cpu = CPU::new();
-
-        cpu.load_and_run(vec![
-            0xa9, 0x00, // set 0x00 in reg a, this should set the status reg
-            0x08, // push status to stack
-            0x00, // break
-        ]);
-
-        // real stack pointer should be at 0x01FF - 0x001
-        assert_eq!(cpu.get_stack_pointer(), 0x1FE);
-
-        // there should be status at 0x01FF
-        assert_eq!(cpu.mem_read(0x1FF) & 0b0000_0010, 0b0000_0010);
-
-        // stack pointer should have the value 0x001
-        assert_eq!(cpu.stack_pointer, 0x01);
+    if options.vgm.is_some() {
+        nes.cpu.bus.start_vgm_log();
     }
-
-    //test jsr
-    #[test]
-    fn test_jsr() {
-        let mut cpu = CPU::new();
-
-        cpu.load_and_run(vec![
-            0x20, 0x05, 0x80, // jump pc to 0x8050, push 0x8002 to stack as [0x1FF: 0x80, 0x1FE: 0x05]
-            0xa9, 0x05, // set 0x05 on reg a, this shouldn't run
-            0xa2, 0x04, //set 0x04 on reg x, this should run
-            0x00, // break
-        ]);
-
-        // check reg x
-        assert_eq!(cpu.register_x, 0x04);
-
-        // check reg a
-        assert_eq!(cpu.register_a, 0x00);
-
-        // check stack
-        assert_eq!(cpu.mem_read(0x01FF), 0x80);
-        assert_eq!(cpu.mem_read(0x01FE), 0x02);
-        
-        // check pc
-        assert_eq!(cpu.program_counter, 0x8008);
-
+    if let Some(path) = &options.load_state {
+        let state = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        nes.load_state(&state).map_err(|e| format!("{}: {}", path.display(), e))?;
     }
 
-    // deprecated test rts
-    // this test isn't deleted because it may trigger my future self's neuron
-    // yes, the error is on purpose
-    #[test]
-    fn deprecated_test_rts() {
-        let mut cpu = CPU::new();
-
-        cpu.load_and_run(vec![
-            0x20, 0x05, 0x80, // jump pc to 0x8050, push 0x8002 to stack as [0x1FF: 0x80, 0x1FE: 0x05]
-            0xa9, 0x05, // set 0x05 on reg a, this should run after RTS
-            0xa2, 0x04, //set 0x04 on reg x, this should run
-            0x60, // jumps to 0x8003
-            0x00, // break
-        ]);
-
-        
-        // check reg x
-        assert_eq!(cpu.register_x, 0x04);
-
-        // check reg a
-        assert_eq!(cpu.register_a, 0x05);
-
-        // check stack
-        assert_eq!(cpu.mem_read(0x01FF), 0x80); // if you check our pop func, we don't zeroize it
-        assert_eq!(cpu.mem_read(0x01FE), 0x02); // why? as of now, i don't know why we should do so
-        
-        // check pc
-        assert_eq!(cpu.program_counter, 0x8009);
+    let mut frames = 0;
+    if options.vgm_loop == Some(0) {
+        nes.cpu.bus.mark_vgm_loop();
     }
-
-    // test rts
-    #[test]
-    fn test_rts() {
-        let mut cpu = CPU::new();
-
-        cpu.load_and_run(vec![
-            0x20, 0x06, 0x80, // jump pc to 0x8050, push 0x8002 to stack as [0x1FF: 0x80, 0x1FE: 0x05]
-            0x00, // break
-            0xa9, 0x05, // set 0x05 on reg a, this shouldn't run
-            0xa2, 0x04, //set 0x04 on reg x, this should run
-            0x60, // jumps to 0x8003
-            0xa9, 0x03, // set reg a = 0x03, shouldn't run
-            0xa2, 0x02, // set reg x = 0x02, shouldn't run
-            0x00, // break
-        ]);
-
-        
-        // check reg x
-        assert_eq!(cpu.register_x, 0x04);
-
-        // check reg a
-        assert_eq!(cpu.register_a, 0x00);
-
-        // check stack
-        assert_eq!(cpu.mem_read(0x01FF), 0x80); // if you check our pop func, we don't zeroize it
-        assert_eq!(cpu.mem_read(0x01FE), 0x02); // why? as of now, i don't know why we should do so
-        
-        // check pc
-        assert_eq!(cpu.program_counter, 0x8004);
+    while frames < frames_wanted {
+        if let Some(script) = &script {
+            script.apply(frames, &mut nes.cpu.bus);
+        }
+
+        let output = nes.run_frame();
+        if let Some(audio) = &mut audio {
+            audio.extend(output.samples, nes.cpu.bus.apu.take_stems());
+        }
+        if let Some(pc) = nes.halted_at() {
+            eprintln!("the CPU stopped on a BRK or jam at {:04X} on frame {}", pc, frames);
+            break;
+        }
+
+        if let Some(dumper) = &dumper {
+            dumper.dump(frames, &output.frame).map_err(|e| e.to_string())?;
+        }
+        frames += 1;
+        // so the loop gets marked as the frame starts, not a whole frame late
+        if options.vgm_loop == Some(frames) {
+            nes.cpu.bus.mark_vgm_loop();
+        }
     }
 
-    // test pla
-    #[test]
-    fn test_pla() {
-        let mut cpu = CPU::new();
-
-        cpu.load_and_run(vec![
-            0xa9, 0x0, //set reg a = 0x0
-            0x48, //push reg a
-            0xa9, 0x5, //set reg a = 0x5
-            0x68, //pop stack, set reg a
-            0x0, //break
-        ]);
-
-        // check stack, should be 0x01FF
-        assert_eq!(cpu.get_stack_pointer(), 0x01FF);
-
-        // check flag, flag Z should be up
-        assert!(cpu.status.contains(CPUStatus::Zero));
-
-        // check reg a, should be 0x0
-        assert_eq!(cpu.register_a, 0x0);
+    if let Some(path) = &options.save_state {
+        fs::write(path, nes.save_state()).map_err(|e| format!("{}: {}", path.display(), e))?;
     }
 
-    // test plp
-    #[test]
-    fn test_plp() {
-        let mut cpu = CPU::new();
-
-        cpu.load_and_run(vec![
-            0xa9, 0x0, //set reg a = 0x0
-            0x08, //push status
-            0xa9, 0x5, //set reg a = 0x5
-            0x28, // pull into status
-            0x0, // break
-        ]);
-
-        //check stack
-        assert_eq!(cpu.get_stack_pointer(), 0x1FF);
-
-        //check flag
-        assert!(cpu.status.contains(CPUStatus::Zero));
+    if let Some(path) = &options.screenshot {
+        let meta = FrameMetadata {
+            frame: frames.saturating_sub(1),
+            rom_crc32: options.metadata.then_some(rom_crc32),
+            frame_count: options.metadata.then_some(frames),
+        };
+        export::save_frame(path, &nes.cpu.bus.ppu.frame, &palette, &meta).map_err(|e| format!("{}: {}", path.display(), e))?;
     }
 
-    // test rti
-    #[test]
-    fn test_rti() {
-        let mut cpu = CPU::new();
-
-        cpu.load_and_run(vec![
-            0xa9, 0x80, // reg a = 0x80
-            0x48, // push a
-            0xa9, 0xD, // reg a = 0x22
-            0x48, // push a; [0x01FF: 0x80, 0x01FE: 0x0D]
-            0xa9, 0x48, // reg a = 0x48 [0x01FD: 0x48]
-            0x48, // push a; this will be status
-            0x40, // rti; status = 0x48 and pc = 0x800D // this is 0x8009
-            0x0, // 0x800A
-            0x0, // 0x800B
-            0x0, // 0x800C
-            0x0, // 0x800D
-        ]);
-
-        // check stack
-        assert_eq!(cpu.get_stack_pointer(), 0x1FF);
-
-        // check flag
-        assert_eq!(cpu.status.bits(), 0x48);
-
-        // check pc
-        assert_eq!(cpu.program_counter, 0x800E);
+    if let (Some(path), Some(vgm)) = (&options.vgm, nes.cpu.bus.stop_vgm_log()) {
+        fs::write(path, vgm).map_err(|e| format!("{}: {}", path.display(), e))?;
     }
 
-    // test adc
-    #[test]
-    fn test_adc() {
-        // test normal addition
-        let mut cpu = CPU::new();
-
-        cpu.load_and_run(vec![
-            0xA9, 0x01, // reg a = 0x01
-            0x69, 0x01, // reg a += 0x01
-            0x00,
-        ]);
-        assert_eq!(cpu.register_a, 0x02);
-
-
-        // test overflow addition
-        // no overflow
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![
-            0xa9, 0xFF,
-            0x69, 0xFF,
-        ]); 
-        assert!(!cpu.status.contains(CPUStatus::Overflow));
-        assert!(cpu.status.contains(CPUStatus::Carry));
-        assert_eq!(cpu.register_a, 0b1111_1110);
-        // overflow
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![
-            0xa9, 0b0111_0000,
-            0x69, 0b0100_0000,
-            0x00,
-        ]);
-        assert!(cpu.status.contains(CPUStatus::Overflow));
-        assert!(!cpu.status.contains(CPUStatus::Carry));
-        assert_eq!(cpu.register_a, 0b1011_0000);
-
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![
-            0xa9, 0b1100_0000,
-            0x69, 0b1100_0000,
-            0x00,
-        ]);
-        assert_eq!(cpu.register_a, 0b1000_0000);
-        assert!(!cpu.status.contains(CPUStatus::Overflow));
-        assert!(cpu.status.contains(CPUStatus::Carry));
-        assert!(cpu.status.contains(CPUStatus::Negative));
+    if let Some(audio) = &audio {
+        if let Some(path) = &options.wav {
+            audio.save(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+        if let Some(dir) = &options.stems {
+            audio.save_stems(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        }
     }
 
-    // test and
-    #[test]
-    fn test_and() {
-        let mut cpu = CPU::new();
-
-        cpu.load_and_run(vec![
-            0xa9, 0b1000_0000,
-            0x29, 0x00,
-            0x00
-        ]);
-        assert!(cpu.status.contains(CPUStatus::Zero));
-        assert!(!cpu.status.contains(CPUStatus::Negative));
-        
-        cpu.load_and_run(vec![
-            0xa9, 0b1000_0000,
-            0x29, 0b1000_0000,
-            0x00,
-        ]);
-        assert!(!cpu.status.contains(CPUStatus::Zero));
-        assert!(cpu.status.contains(CPUStatus::Negative));
-
-        cpu.load_and_run(vec![
-            0xa9, 0b0011_0000,
-            0x29, 0b0011_1100,
-            0x00,
-        ]);
-        assert_eq!(cpu.register_a, 0b0011_0000);
+    let (ppu, cart) = (&nes.cpu.bus.ppu, &nes.cpu.bus.cartridge);
+    if let Some(path) = &options.patterns {
+        save_view(path, || ppu.pattern_tables_text(cart), || ppu.render_pattern_tables(cart, options.pattern_palette, &palette))?;
     }
-
-    // test asl
-    #[test]
-    fn test_asl() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![
-            0xa9, 0b1100_0000, //reg a = 0b1100_0000
-            0x48, // push reg a
-            0x0e, 0xff, 0x01, // shift 0x01ff by 1
-            0x68, //pop into reg a 
-            0x00, 
-        ]);
-
-        assert!(cpu.status.contains(CPUStatus::Carry));
-        assert!(cpu.status.contains(CPUStatus::Negative));
-        assert_eq!(cpu.register_a, 0b1000_0000);
+    if let Some(path) = &options.nametables {
+        save_view(path, || ppu.nametables_text(cart), || ppu.render_nametables(cart, &palette))?;
+    }
+    if let Some(path) = &options.sprites {
+        save_view(path, || ppu.sprite_table(), || ppu.render_sprite_sheet(cart, &palette))?;
+    }
+    if let Some(path) = &options.palette_ram {
+        save_view(path, || ppu.palette_ram_table(), || ppu.render_palette_ram(&palette))?;
     }
 
-    // test asl_a
-    #[test]
-    fn test_asl_a() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![
-            0xa9, 0b1100_0000,
-            0x0a,
-            0x00,
-        ]);
-
-        assert!(cpu.status.contains(CPUStatus::Carry));
-        assert!(cpu.status.contains(CPUStatus::Negative));
-        assert_eq!(cpu.register_a, 0b1000_0000);
+    if let (Some(path), Some(log)) = (&options.events, nes.cpu.bus.stop_recording()) {
+        let frame = options.events_frame.or(log.last_frame()).unwrap_or(0);
+        let csv = path.extension().is_some_and(|e| e == "csv");
+        let data = if csv {
+            log.to_csv().into_bytes()
+        } else {
+            let format = ImageFormat::from_path(path).ok_or(format!("{}: use .png, .ppm or .csv", path.display()))?;
+            log.render(frame).encode(format)
+        };
+        fs::write(path, data).map_err(|e| format!("{}: {}", path.display(), e))?;
     }
 
-    #[test]
-    fn test_bcc() {
-        let mut cpu = CPU::new();
-
-        cpu.load_and_run(vec![
-            0xa9, 0xff, //reg a = 255
-            0x0a, //reg a shift left
-            0x90, 0x03, //if carry, branch
-            0xa9, 0x02, // if carry is set, go here
-            0x0,
-            0xa9, 0x03, // if carry is set, reg a = 3
-            0x0,
-        ]);
-
-        assert_eq!(cpu.register_a, 0x02);
-        assert!(cpu.status.contains(CPUStatus::Carry));
-
-        cpu.load_and_run(vec![
-            0xa9, 0x01, //reg a = 1
-            0x90, 0x03, //if carry, branch
-            0xa9, 0x02, // if carry is set, go here
-            0x0,
-            0xa9, 0x03, // if carry is set, reg a = 3
-            0x0,
-        ]);
-
-        assert_eq!(cpu.register_a, 0x03);
-        assert!(!cpu.status.contains(CPUStatus::Carry));
-        assert!(!cpu.status.contains(CPUStatus::Negative));
+    Ok(())
+}
 
-    }
+fn play_nsf(options: &Options, raw: &[u8]) -> Result<(), String> {
+    let nsf = Nsf::parse(raw)?;
 
-    #[test]
-    fn test_bcs() {
-        let mut cpu = CPU::new();
-
-        cpu.load_and_run(vec![
-            0xa9, 0xff, //reg a = 255
-            0x0a, //reg a shift left
-            0xB0, 0x03, //if carry, branch
-            0xa9, 0x02, // if carry is set, go here
-            0x0,
-            0xa9, 0x03, // if carry is set, reg a = 3
-            0x0,
-        ]);
-
-        assert_eq!(cpu.register_a, 0x03);
-        assert!(cpu.status.contains(CPUStatus::Carry));
-
-        cpu.load_and_run(vec![
-            0xa9, 0x01, //reg a = 1
-            0xB0, 0x03, //if carry, branch
-            0xa9, 0x02, // if carry is set, go here
-            0x0,
-            0xa9, 0x03, // if carry is set, reg a = 3
-            0x0,
-        ]);
-
-        assert_eq!(cpu.register_a, 0x02);
-        assert!(!cpu.status.contains(CPUStatus::Carry));
-        assert!(!cpu.status.contains(CPUStatus::Negative));
+    if options.list {
+        println!("{} - {} ({})", nsf.title, nsf.artist, nsf.copyright);
+        print!("{}", nsf.track_listing());
+        return Ok(());
     }
-
-    #[test]
-    fn test_beq() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![
-            0xa9, 0,
-            0xF0, 0x03, //if zero, branch
-            0xa9, 0x02, // if zero is set, go here
-            0x0,
-            0xa9, 0x03, // if zero is set, reg a = 3
-            0x0,
-        ]);
-
-        assert_eq!(cpu.register_a, 0x03);
-
-        
-        cpu.load_and_run(vec![
-            0xa9, 1,
-            0xF0, 0x03, //if zero, branch
-            0xa9, 0x02, // if zero is set, go here
-            0x0,
-            0xa9, 0x03, // if zero is set, reg a = 3
-            0x0,
-        ]);
-
-        assert_eq!(cpu.register_a, 0x02);
+    if !nsf.unsupported_chips().is_empty() {
+        eprintln!("warning: no emulation for {:?}, those parts will be missing", nsf.unsupported_chips());
     }
 
-    #[test]
-    fn test_bit() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![
-            0xa9, 0,
-            0x89, 0b1100_0000,
-            0x0,
-        ]);
-
-        assert!(cpu.status.contains(CPUStatus::Zero));
-        assert!(cpu.status.contains(CPUStatus::Negative));
-        assert!(cpu.status.contains(CPUStatus::Overflow));
-        
-        cpu.load_and_run(vec![
-            0xa9, 0b1000_0000,
-            0x89, 0b1100_0000,
-            0x0,
-        ]);
-
-        assert!(!cpu.status.contains(CPUStatus::Zero));
-        assert!(cpu.status.contains(CPUStatus::Negative));
-        assert!(cpu.status.contains(CPUStatus::Overflow));
-        
-        cpu.load_and_run(vec![
-            0xa9, 0b1000_0000,
-            0x89, 0b1100_0000,
-            0x0,
-        ]);
-
-        assert!(!cpu.status.contains(CPUStatus::Zero));
-        assert!(cpu.status.contains(CPUStatus::Negative));
-        assert!(cpu.status.contains(CPUStatus::Overflow));
-        
-        cpu.load_and_run(vec![
-            0xa9, 0b1000_0000,
-            0x89, 0b0010_0000,
-            0x0,
-        ]);
-
-        assert!(cpu.status.contains(CPUStatus::Zero));
-        assert!(!cpu.status.contains(CPUStatus::Negative));
-        assert!(!cpu.status.contains(CPUStatus::Overflow));
-    }
+    let track = options.track.unwrap_or(nsf.starting_song);
+    let info = nsf.tracks.get(track as usize - 1).cloned().unwrap_or_default();
+    let length = options.length.or(info.length_ms.map(|ms| ms as f64 / 1000.0)).unwrap_or(150.0);
+    let fade = options.fade.or(info.fade_ms.map(|ms| ms as f64 / 1000.0)).unwrap_or(8.0);
 
-    #[test]
-    fn test_bmi() {
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![ // branch
-            0xa9, 0b1000_0000,
-            0x30, 0x03,
-            0xa9, 0x02,
-            0x0,
-            0xa9, 0x03,
-            0x0,
-        ]);
-
-        assert_eq!(cpu.register_a, 0x03);
-
-        
-        cpu.load_and_run(vec![ // no branch
-            0xa9, 1,
-            0x30, 0x03,
-            0xa9, 0x02,
-            0x0,
-            0xa9, 0x03,
-            0x0,
-        ]);
-
-        assert_eq!(cpu.register_a, 0x02);
+    let mut player = NsfPlayer::new(nsf);
+    player.smooth_n163 = options.smooth_n163;
+    let audio = player.render(track, length, fade, options.sample_rate, options.stems.is_some())?;
 
+    if let Some(path) = &options.wav {
+        audio.save(path).map_err(|e| format!("{}: {}", path.display(), e))?;
     }
-
-    #[test]
-    fn test_bne() {
-        
-        let mut cpu = CPU::new();
-        cpu.load_and_run(vec![ // branch
-            0xa9, 0b1000_0000,
-            0xD0, 0x03,
-            0xa9, 0x02,
-            0x0,
-            0xa9, 0x03,
-            0x0,
-        ]);
-
-        assert_eq!(cpu.register_a, 0x03);
-
-        
-        cpu.load_and_run(vec![ // no branch
-            0xa9, 0,
-            0xD0, 0x03,
-            0xa9, 0x02,
-            0x0,
-            0xa9, 0x03,
-            0x0,
-        ]);
-
-        assert_eq!(cpu.register_a, 0x02);
+    if let Some(dir) = &options.stems {
+        audio.save_stems(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    }
+    if options.wav.is_none() && options.stems.is_none() {
+        eprintln!("nothing to write the track to, give --wav or --stems");
     }
+    Ok(())
+}
+
+fn save_view(path: &Path, text: impl FnOnce() -> String, image: impl FnOnce() -> Image) -> Result<(), String> {
+    let data = match path.extension().and_then(|e| e.to_str()) {
+        Some("txt") => text().into_bytes(),
+        _ => {
+            let format = ImageFormat::from_path(path).ok_or(format!("{}: use .png, .ppm or .txt", path.display()))?;
+            image().encode(format)
+        },
+    };
+    fs::write(path, data).map_err(|e| format!("{}: {}", path.display(), e))
+}
 
+fn main() {
+    let result = parse_args(env::args().skip(1)).and_then(run);
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
 }
-// #[cfg(test)]
-// mod test {
-//     use crate::cpu::CPU;
-
-//     #[test]
-//     fn test_0xa9_lda_immediate_load_data() {
-//         let mut cpu = CPU::new();
-//         cpu.interpret(vec![0xa9, 0x05, 0x00]);
-//         assert_eq!(cpu.register_a, 0x05);
-//         assert!(cpu.status & 0b0000_0010 == 0b00);
-//         assert!(cpu.status & 0b1000_0000 == 0);
-//     }
-
-//     #[test]
-//     fn test_0xa9_lda_zero_flag() {
-//         let mut cpu = CPU::new();
-//         cpu.interpret(vec![0xa9, 0x00, 0x00]);
-//         assert!(cpu.status & 0b0000_0010 == 0b10);
-//     }
-
-//     #[test]
-//     fn test_0xaa_tax_move_a_to_x() {
-//         let mut cpu = CPU::new();
-//         cpu.register_a = 10;
-//         cpu.interpret(vec![0xaa, 0x00]);
-
-//         assert_eq!(cpu.register_x, 10)
-//     }
-
-//     #[test]
-//     fn test_5_ops_working_together() {
-//         let mut cpu = CPU::new();
-//         cpu.interpret(vec![0xa9, 0xc0, 0xaa, 0xe8, 0x00]);
-
-//         assert_eq!(cpu.register_x, 0xc1)
-//     }
-
-//     #[test]
-//     fn test_inx_overflow() {
-//         let mut cpu = CPU::new();
-//         cpu.register_x = 0xff;
-//         cpu.interpret(vec![0xe8, 0xe8, 0x00]);
-
-//         assert_eq!(cpu.register_x, 1)
-//     }
-// }
\ No newline at end of file
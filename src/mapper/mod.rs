@@ -0,0 +1,85 @@
+use crate::cartridge::Mirroring;
+use crate::savestate::{StateReader, StateWriter};
+
+pub mod namco163;
+pub mod nrom;
+pub mod vrc6;
+pub mod vrc7;
+pub mod vrc_irq;
+
+// where a nametable access ends up when the board takes over from the header's mirroring
+pub enum NametableSource {
+    Ciram(usize), // which 1KB page of console VRAM
+    Chr(usize),   // offset into CHR
+}
+
+/*
+ what a board does between the console and the chips on it. the cartridge owns the actual
+ ROM and RAM, a mapper just says where an address ends up and keeps its own registers.
+ offsets it hands back get wrapped to the size of the memory by the cartridge
+*/
+pub trait Mapper {
+    // $8000-$FFFF -> offset into PRG ROM
+    fn prg_addr(&self, addr: u16) -> usize;
+
+    // $6000-$7FFF -> offset into PRG RAM, None when it's disabled or missing
+    fn prg_ram_addr(&self, addr: u16) -> Option<usize> {
+        Some(addr as usize & 0x1FFF)
+    }
+
+    // $0000-$1FFF on the PPU side -> offset into CHR ROM/RAM
+    fn chr_addr(&self, addr: u16) -> usize {
+        addr as usize & 0x1FFF
+    }
+
+    // None leaves $2000-$2FFF to console VRAM and mirroring()
+    fn nametable(&self, _addr: u16) -> Option<NametableSource> {
+        None
+    }
+
+    // every CPU write from $4020 up comes through here, RAM writes included
+    fn write(&mut self, _addr: u16, _data: u8) {}
+
+    // for boards with registers or memory the CPU can read back
+    fn read(&mut self, _addr: u16) -> Option<u8> {
+        None
+    }
+
+    // None leaves it to the header
+    fn mirroring(&self) -> Option<Mirroring> {
+        None
+    }
+
+    // once per CPU cycle
+    fn tick(&mut self) {}
+
+    fn irq(&self) -> bool {
+        false
+    }
+
+    // expansion audio, scaled to sit alongside the APU mix
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+
+    // for chips whose authentic output has artifacts people may not want
+    fn set_audio_smoothing(&mut self, _smooth: bool) {}
+
+    // the board's registers for save states, boards without any leave these be
+    fn save_state(&self, _w: &mut StateWriter) {}
+
+    fn load_state(&mut self, _r: &mut StateReader) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+pub fn new_mapper(id: u16, prg_rom_size: usize) -> Result<Box<dyn Mapper>, String> {
+    match id {
+        0 => Ok(Box::new(nrom::Nrom)),
+        19 => Ok(Box::new(namco163::Namco163::new(prg_rom_size))),
+        24 => Ok(Box::new(vrc6::Vrc6::new(prg_rom_size, false))),
+        26 => Ok(Box::new(vrc6::Vrc6::new(prg_rom_size, true))),
+        85 => Ok(Box::new(vrc7::Vrc7::new(prg_rom_size))),
+        _ => Err(format!("mapper {} isn't supported", id)),
+    }
+}
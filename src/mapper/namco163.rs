@@ -0,0 +1,412 @@
+use super::{Mapper, NametableSource};
+use crate::savestate::{Snapshot, StateReader, StateWriter};
+
+// mapper 19, Namco 163. 8KB PRG banks, 1KB CHR banks, nametables that can come out of
+// CHR ROM, a 15 bit IRQ counter and up to 8 wavetable channels
+// https://www.nesdev.org/wiki/INES_Mapper_019 https://www.nesdev.org/wiki/Namco_163_audio
+
+// wavetable sample (4 bits, centred) times volume, so a single channel at full volume comes out
+// about half again as loud as an APU pulse at 15. boards vary a lot, this is a middle value
+const LEVEL: f32 = 0.14937 * 1.5 / (8.0 * 15.0);
+
+// one channel gets updated every 15 CPU cycles
+const CYCLES_PER_CHANNEL: u8 = 15;
+
+// bank values from here up point nametables at the console's own VRAM instead of CHR ROM
+const CIRAM_BANKS: u8 = 0xE0;
+
+pub struct Namco163 {
+    prg_rom_size: usize,
+    prg_banks: [usize; 3], // $8000, $A000, $C000. $E000 is the last bank
+    chr_banks: [usize; 8],
+    nametable_banks: [u8; 4],
+    irq_counter: u16, // 15 bits
+    irq_enabled: bool,
+    irq_pending: bool,
+    audio: Namco163Audio,
+}
+
+impl Namco163 {
+    pub fn new(prg_rom_size: usize) -> Self {
+        Namco163 {
+            prg_rom_size,
+            prg_banks: [0, 1, 2],
+            chr_banks: [0; 8],
+            nametable_banks: [CIRAM_BANKS; 4],
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            audio: Namco163Audio::new(),
+        }
+    }
+}
+
+impl Mapper for Namco163 {
+    fn prg_addr(&self, addr: u16) -> usize {
+        let offset = (addr & 0x1FFF) as usize;
+        match addr {
+            0x8000..=0xDFFF => self.prg_banks[(addr as usize - 0x8000) / 0x2000] * 0x2000 + offset,
+            _ => self.prg_rom_size - 0x2000 + offset,
+        }
+    }
+
+    // bank values of $E0 and up can also put console VRAM in the pattern tables. nothing is
+    // known to use that, so here they always index CHR
+    fn chr_addr(&self, addr: u16) -> usize {
+        let addr = addr as usize & 0x1FFF;
+        self.chr_banks[addr / 0x400] * 0x400 + addr % 0x400
+    }
+
+    fn nametable(&self, addr: u16) -> Option<NametableSource> {
+        let bank = self.nametable_banks[(addr as usize & 0x0FFF) / 0x400];
+        Some(if bank >= CIRAM_BANKS {
+            NametableSource::Ciram((bank & 1) as usize)
+        } else {
+            NametableSource::Chr(bank as usize * 0x400 + (addr & 0x3FF) as usize)
+        })
+    }
+
+    fn read(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4800..=0x4FFF => Some(self.audio.read_data()),
+            0x5000..=0x57FF => Some(self.irq_counter as u8),
+            0x5800..=0x5FFF => Some((self.irq_counter >> 8) as u8 | (self.irq_enabled as u8) << 7),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4800..=0x4FFF => self.audio.write_data(data),
+            0x5000..=0x57FF => {
+                self.irq_counter = self.irq_counter & 0x7F00 | data as u16;
+                self.irq_pending = false;
+            },
+            0x5800..=0x5FFF => {
+                self.irq_counter = self.irq_counter & 0x00FF | ((data & 0x7F) as u16) << 8;
+                self.irq_enabled = data & 0b1000_0000 != 0;
+                self.irq_pending = false;
+            },
+            0x8000..=0xBFFF => self.chr_banks[(addr as usize - 0x8000) / 0x800] = data as usize,
+            0xC000..=0xDFFF => self.nametable_banks[(addr as usize - 0xC000) / 0x800] = data,
+            0xE000..=0xE7FF => {
+                self.prg_banks[0] = (data & 0x3F) as usize;
+                self.audio.enabled = data & 0b0100_0000 == 0;
+            },
+            0xE800..=0xEFFF => self.prg_banks[1] = (data & 0x3F) as usize,
+            0xF000..=0xF7FF => self.prg_banks[2] = (data & 0x3F) as usize,
+            0xF800..=0xFFFF => self.audio.write_address(data),
+            _ => {},
+        }
+    }
+
+    fn tick(&mut self) {
+        if self.irq_enabled && self.irq_counter < 0x7FFF {
+            self.irq_counter += 1;
+            if self.irq_counter == 0x7FFF {
+                self.irq_pending = true;
+            }
+        }
+        self.audio.clock();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn set_audio_smoothing(&mut self, smooth: bool) {
+        self.audio.smooth = smooth;
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        for &bank in &self.prg_banks {
+            w.usize(bank);
+        }
+        for &bank in &self.chr_banks {
+            w.usize(bank);
+        }
+        w.bytes(&self.nametable_banks);
+        w.u16(self.irq_counter);
+        w.bool(self.irq_enabled);
+        w.bool(self.irq_pending);
+        self.audio.save(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), String> {
+        for bank in self.prg_banks.iter_mut() {
+            *bank = r.usize()?;
+        }
+        for bank in self.chr_banks.iter_mut() {
+            *bank = r.usize()?;
+        }
+        r.bytes(&mut self.nametable_banks)?;
+        self.irq_counter = r.u16()?;
+        self.irq_enabled = r.bool()?;
+        self.irq_pending = r.bool()?;
+        self.audio.load(r)
+    }
+}
+
+/*
+ the sound side, shared with the NSF player. the channels' registers live at the top of the
+ 128 bytes of RAM along with the waveforms, and the chip only has one DAC, so it visits the
+ enabled channels one after the other. with more channels enabled each gets a smaller slice
+ of the time and the switching itself gets audible. smooth averages the channels instead
+*/
+#[derive(Clone)]
+pub struct Namco163Audio {
+    pub smooth: bool,
+    enabled: bool,
+    ram: [u8; 128],
+    address: u8,
+    auto_increment: bool,
+    divider: u8,
+    current: u8, // the channel the DAC is on right now, 7 counting down
+    outputs: [i16; 8], // the last value each channel produced
+}
+
+impl Default for Namco163Audio {
+    fn default() -> Self {
+        Namco163Audio::new()
+    }
+}
+
+impl Namco163Audio {
+    pub fn new() -> Self {
+        Namco163Audio {
+            smooth: false,
+            enabled: true,
+            ram: [0; 128],
+            address: 0,
+            auto_increment: false,
+            divider: 0,
+            current: 7,
+            outputs: [0; 8],
+        }
+    }
+
+    // $F800
+    pub fn write_address(&mut self, data: u8) {
+        self.address = data & 0x7F;
+        self.auto_increment = data & 0b1000_0000 != 0;
+    }
+
+    // $4800
+    pub fn write_data(&mut self, data: u8) {
+        self.ram[self.address as usize] = data;
+        self.step_address();
+    }
+
+    pub fn read_data(&mut self) -> u8 {
+        let data = self.ram[self.address as usize];
+        self.step_address();
+        data
+    }
+
+    fn step_address(&mut self) {
+        if self.auto_increment {
+            self.address = (self.address + 1) & 0x7F;
+        }
+    }
+
+    // 1-8, from the top nibble of the last channel's volume byte
+    fn channel_count(&self) -> u8 {
+        (self.ram[0x7F] >> 4 & 0b111) + 1
+    }
+
+    // once per CPU cycle
+    pub fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+
+        self.divider += 1;
+        if self.divider < CYCLES_PER_CHANNEL {
+            return;
+        }
+        self.divider = 0;
+
+        self.update_channel(self.current);
+        let lowest = 8 - self.channel_count();
+        self.current = if self.current <= lowest { 7 } else { self.current - 1 };
+    }
+
+    fn update_channel(&mut self, channel: u8) {
+        let base = 0x40 + channel as usize * 8;
+        let regs = &self.ram[base..base + 8];
+
+        let frequency = regs[0] as u32 | (regs[2] as u32) << 8 | ((regs[4] & 0b11) as u32) << 16;
+        let length = 256 - (regs[4] & 0xFC) as u32;
+        let mut phase = regs[1] as u32 | (regs[3] as u32) << 8 | (regs[5] as u32) << 16;
+        let offset = regs[6] as u32;
+        let volume = (regs[7] & 0x0F) as i16;
+
+        phase = (phase + frequency) % (length << 16);
+        self.ram[base + 1] = phase as u8;
+        self.ram[base + 3] = (phase >> 8) as u8;
+        self.ram[base + 5] = (phase >> 16) as u8;
+
+        // samples are nibbles, low one first
+        let sample_addr = ((phase >> 16) + offset) & 0xFF;
+        let byte = self.ram[(sample_addr / 2) as usize & 0x7F];
+        let sample = if sample_addr & 1 == 0 { byte & 0x0F } else { byte >> 4 };
+
+        self.outputs[channel as usize] = (sample as i16 - 8) * volume;
+    }
+
+    pub fn output(&self) -> f32 {
+        if !self.enabled {
+            return 0.0;
+        }
+
+        let level = if self.smooth {
+            let count = self.channel_count();
+            let sum: i16 = self.outputs[(8 - count) as usize..].iter().sum();
+            sum as f32 / count as f32
+        } else {
+            self.outputs[self.current as usize] as f32
+        };
+        level * LEVEL
+    }
+}
+
+// smoothing is a listening preference, it stays as it is
+impl Snapshot for Namco163Audio {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.enabled);
+        w.bytes(&self.ram);
+        w.u8(self.address);
+        w.bool(self.auto_increment);
+        w.u8(self.divider);
+        w.u8(self.current);
+        for &output in &self.outputs {
+            w.i16(output);
+        }
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.enabled = r.bool()?;
+        r.bytes(&mut self.ram)?;
+        self.address = r.u8()?;
+        self.auto_increment = r.bool()?;
+        self.divider = r.u8()?;
+        self.current = r.u8()?;
+        for output in self.outputs.iter_mut() {
+            *output = r.i16()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // a square wave of length 16 samples in the first 8 bytes, on channel 7
+    fn square_on_channel_7(audio: &mut Namco163Audio, channels: u8) {
+        audio.write_address(0x80);
+        for _ in 0..4 {
+            audio.write_data(0x00);
+        }
+        for _ in 0..4 {
+            audio.write_data(0xFF);
+        }
+
+        audio.write_address(0x80 | 0x78);
+        // frequency $10000 is one sample per update, length 16
+        for data in [0, 0, 0, 0, (256 - 16) as u8 | 0b01, 0, 0, ((channels - 1) << 4) | 0x0F] {
+            audio.write_data(data);
+        }
+    }
+
+    #[test]
+    fn test_ram_port_auto_increments() {
+        let mut audio = Namco163Audio::new();
+        audio.write_address(0x80 | 0x7E);
+        audio.write_data(1);
+        audio.write_data(2);
+        audio.write_data(3); // wraps to 0
+
+        audio.write_address(0x7E);
+        assert_eq!(audio.read_data(), 1);
+        assert_eq!(audio.read_data(), 1);
+        assert_eq!(audio.ram[0x7F], 2);
+        assert_eq!(audio.ram[0x00], 3);
+    }
+
+    #[test]
+    fn test_channel_plays_its_waveform() {
+        let mut audio = Namco163Audio::new();
+        square_on_channel_7(&mut audio, 1);
+
+        let mut levels = Vec::new();
+        for _ in 0..16 * CYCLES_PER_CHANNEL as usize {
+            audio.clock();
+            levels.push(audio.outputs[7]);
+        }
+        assert!(levels.contains(&(-8 * 15)));
+        assert!(levels.contains(&(7 * 15)));
+    }
+
+    #[test]
+    fn test_more_channels_means_slower_updates() {
+        let mut audio = Namco163Audio::new();
+        square_on_channel_7(&mut audio, 4);
+
+        // channel 7 only comes round every 4 * 15 cycles
+        let mut visits = 0;
+        for _ in 0..4 * 15 * 10 {
+            let phase = audio.ram[0x7D];
+            audio.clock();
+            if audio.ram[0x7D] != phase {
+                visits += 1;
+            }
+        }
+        assert_eq!(visits, 10);
+    }
+
+    #[test]
+    fn test_smoothing_averages_the_channels() {
+        let mut audio = Namco163Audio::new();
+        square_on_channel_7(&mut audio, 2);
+        audio.outputs = [0, 0, 0, 0, 0, 0, 40, 80];
+
+        audio.current = 6;
+        let authentic = audio.output();
+        audio.smooth = true;
+        assert_eq!(audio.output(), 60.0 * LEVEL);
+        assert_eq!(authentic, 40.0 * LEVEL);
+    }
+
+    #[test]
+    fn test_irq_counts_up_to_7fff() {
+        let mut mapper = Namco163::new(0x20000);
+        mapper.write(0x5000, 0xFD);
+        mapper.write(0x5800, 0x80 | 0x7F);
+        mapper.tick();
+        assert!(!mapper.irq());
+        mapper.tick();
+        assert!(mapper.irq());
+        assert_eq!(mapper.read(0x5000), Some(0xFF));
+
+        // sits there until it's rewritten
+        mapper.tick();
+        assert_eq!(mapper.read(0x5000), Some(0xFF));
+        mapper.write(0x5800, 0);
+        assert!(!mapper.irq());
+    }
+
+    #[test]
+    fn test_nametables_through_chr() {
+        let mut mapper = Namco163::new(0x20000);
+        mapper.write(0xC000, 0xE1);
+        mapper.write(0xC800, 0x05);
+
+        assert!(matches!(mapper.nametable(0x2010), Some(NametableSource::Ciram(1))));
+        assert!(matches!(mapper.nametable(0x2410), Some(NametableSource::Chr(offset)) if offset == 5 * 0x400 + 0x10));
+    }
+}
@@ -0,0 +1,10 @@
+use super::Mapper;
+
+// mapper 0, no banking at all. a 16KB NROM shows up twice through the cartridge's wrapping
+pub struct Nrom;
+
+impl Mapper for Nrom {
+    fn prg_addr(&self, addr: u16) -> usize {
+        (addr - 0x8000) as usize
+    }
+}
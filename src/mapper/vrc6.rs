@@ -0,0 +1,425 @@
+use super::vrc_irq::VrcIrq;
+use super::Mapper;
+use crate::cartridge::Mirroring;
+use crate::savestate::{Snapshot, StateReader, StateWriter};
+
+// mappers 24 and 26, Konami VRC6. 26 is the same chip with A0 and A1 wired the other way round
+// https://www.nesdev.org/wiki/VRC6
+
+// one step of VRC6 output, picked so a VRC6 pulse at volume 15 is as loud as an APU pulse at 15
+const LEVEL: f32 = 0.14937 / 15.0;
+
+pub struct Vrc6 {
+    swapped_lines: bool,
+    prg_rom_size: usize,
+    prg_16k: usize,
+    prg_8k: usize,
+    chr_banks: [usize; 8],
+    mirroring: Mirroring,
+    prg_ram_enabled: bool,
+    irq: VrcIrq,
+    audio: Vrc6Audio,
+}
+
+impl Vrc6 {
+    pub fn new(prg_rom_size: usize, swapped_lines: bool) -> Self {
+        Vrc6 {
+            swapped_lines,
+            prg_rom_size,
+            prg_16k: 0,
+            prg_8k: 0,
+            chr_banks: [0; 8],
+            mirroring: Mirroring::Vertical,
+            prg_ram_enabled: false,
+            irq: VrcIrq::default(),
+            audio: Vrc6Audio::new(),
+        }
+    }
+
+    // everything in terms of mapper 24's register numbers
+    fn register(&self, addr: u16) -> u16 {
+        let low = if self.swapped_lines { (addr & 1) << 1 | (addr & 2) >> 1 } else { addr & 3 };
+        addr & 0xF000 | low
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn prg_addr(&self, addr: u16) -> usize {
+        match addr {
+            0x8000..=0xBFFF => self.prg_16k * 0x4000 + (addr & 0x3FFF) as usize,
+            0xC000..=0xDFFF => self.prg_8k * 0x2000 + (addr & 0x1FFF) as usize,
+            _ => self.prg_rom_size - 0x2000 + (addr & 0x1FFF) as usize,
+        }
+    }
+
+    fn prg_ram_addr(&self, addr: u16) -> Option<usize> {
+        self.prg_ram_enabled.then_some(addr as usize & 0x1FFF)
+    }
+
+    // only the plain 1KB banking mode ($B003 mode 0), which is what both games use
+    fn chr_addr(&self, addr: u16) -> usize {
+        let addr = addr as usize & 0x1FFF;
+        self.chr_banks[addr / 0x400] * 0x400 + addr % 0x400
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            return;
+        }
+
+        match self.register(addr) {
+            0x8000..=0x8003 => self.prg_16k = (data & 0x0F) as usize,
+            register @ (0x9000..=0x9003 | 0xA000..=0xA002 | 0xB000..=0xB002) => self.audio.write(register, data),
+            0xB003 => {
+                self.mirroring = match (data >> 2) & 0b11 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                };
+                self.prg_ram_enabled = data & 0b1000_0000 != 0;
+            },
+            0xC000..=0xC003 => self.prg_8k = (data & 0x1F) as usize,
+            register @ 0xD000..=0xD003 => self.chr_banks[(register & 3) as usize] = data as usize,
+            register @ 0xE000..=0xE003 => self.chr_banks[4 + (register & 3) as usize] = data as usize,
+            0xF000 => self.irq.write_latch(data),
+            0xF001 => self.irq.write_control(data),
+            0xF002 => self.irq.acknowledge(),
+            _ => {},
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    fn tick(&mut self) {
+        self.irq.clock();
+        self.audio.clock();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.usize(self.prg_16k);
+        w.usize(self.prg_8k);
+        for &bank in &self.chr_banks {
+            w.usize(bank);
+        }
+        self.mirroring.save(w);
+        w.bool(self.prg_ram_enabled);
+        self.irq.save(w);
+        self.audio.save(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.prg_16k = r.usize()?;
+        self.prg_8k = r.usize()?;
+        for bank in self.chr_banks.iter_mut() {
+            *bank = r.usize()?;
+        }
+        self.mirroring.load(r)?;
+        self.prg_ram_enabled = r.bool()?;
+        self.irq.load(r)?;
+        self.audio.load(r)
+    }
+}
+
+/*
+ the sound half of the chip, on its own so NSF playback can use it too. two pulses with
+ 8 duty settings over a 16 step sequence and a sawtooth, all clocked straight off M2.
+ registers are $9000-$9003, $A000-$A002 and $B000-$B002 (mapper 24 numbering)
+*/
+#[derive(Default, Clone)]
+pub struct Vrc6Audio {
+    pulses: [Vrc6Pulse; 2],
+    saw: Vrc6Saw,
+    halt: bool,
+    shift: u8, // the frequency multiplier in $9003
+}
+
+impl Vrc6Audio {
+    pub fn new() -> Self {
+        Vrc6Audio::default()
+    }
+
+    pub fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x9003 => {
+                self.halt = data & 0b001 != 0;
+                // ×256 wins over ×16 when both are set
+                self.shift = if data & 0b100 != 0 {
+                    8
+                } else if data & 0b010 != 0 {
+                    4
+                } else {
+                    0
+                };
+            },
+            0x9000..=0x9002 => self.pulses[0].write(addr & 3, data),
+            0xA000..=0xA002 => self.pulses[1].write(addr & 3, data),
+            0xB000..=0xB002 => self.saw.write(addr & 3, data),
+            _ => {},
+        }
+    }
+
+    // once per CPU cycle
+    pub fn clock(&mut self) {
+        if self.halt {
+            return;
+        }
+        for pulse in &mut self.pulses {
+            pulse.clock(self.shift);
+        }
+        self.saw.clock(self.shift);
+    }
+
+    // the chip just sums the three, pulses are 4 bit and the saw 5
+    pub fn output(&self) -> f32 {
+        let sum = self.pulses[0].output() as u16 + self.pulses[1].output() as u16 + self.saw.output() as u16;
+        sum as f32 * LEVEL
+    }
+}
+
+#[derive(Default, Clone)]
+struct Vrc6Pulse {
+    volume: u8,
+    duty: u8,
+    constant: bool, // the mode bit, ignores the duty and outputs the volume
+    enabled: bool,
+    period: u16, // 12 bits
+    timer: u16,
+    step: u8, // counts down from 15
+}
+
+impl Vrc6Pulse {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.volume = data & 0x0F;
+                self.duty = (data >> 4) & 0b111;
+                self.constant = data & 0b1000_0000 != 0;
+            },
+            1 => self.period = self.period & 0x0F00 | data as u16,
+            _ => {
+                self.period = self.period & 0x00FF | ((data & 0x0F) as u16) << 8;
+                self.enabled = data & 0b1000_0000 != 0;
+                if !self.enabled {
+                    self.step = 15;
+                }
+            },
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period >> shift;
+        self.step = if self.step == 0 { 15 } else { self.step - 1 };
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.constant || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+struct Vrc6Saw {
+    rate: u8, // 6 bits
+    enabled: bool,
+    period: u16,
+    timer: u16,
+    step: u8, // 0-13, the accumulator takes the rate on every other one
+    accumulator: u8,
+}
+
+impl Vrc6Saw {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => self.rate = data & 0b0011_1111,
+            1 => self.period = self.period & 0x0F00 | data as u16,
+            _ => {
+                self.period = self.period & 0x00FF | ((data & 0x0F) as u16) << 8;
+                self.enabled = data & 0b1000_0000 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            },
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period >> shift;
+
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if self.step.is_multiple_of(2) {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+impl Snapshot for Vrc6Audio {
+    fn save(&self, w: &mut StateWriter) {
+        for pulse in &self.pulses {
+            w.u8(pulse.volume);
+            w.u8(pulse.duty);
+            w.bool(pulse.constant);
+            w.bool(pulse.enabled);
+            w.u16(pulse.period);
+            w.u16(pulse.timer);
+            w.u8(pulse.step);
+        }
+        w.u8(self.saw.rate);
+        w.bool(self.saw.enabled);
+        w.u16(self.saw.period);
+        w.u16(self.saw.timer);
+        w.u8(self.saw.step);
+        w.u8(self.saw.accumulator);
+        w.bool(self.halt);
+        w.u8(self.shift);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        for pulse in self.pulses.iter_mut() {
+            pulse.volume = r.u8()?;
+            pulse.duty = r.u8()?;
+            pulse.constant = r.bool()?;
+            pulse.enabled = r.bool()?;
+            pulse.period = r.u16()?;
+            pulse.timer = r.u16()?;
+            pulse.step = r.u8()?;
+        }
+        self.saw.rate = r.u8()?;
+        self.saw.enabled = r.bool()?;
+        self.saw.period = r.u16()?;
+        self.saw.timer = r.u16()?;
+        self.saw.step = r.u8()?;
+        self.saw.accumulator = r.u8()?;
+        self.halt = r.bool()?;
+        self.shift = r.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pulse_duty() {
+        let mut audio = Vrc6Audio::new();
+        audio.write(0x9000, 0b0011_1111); // duty 3 -> 4/16, volume 15
+        audio.write(0x9001, 0);
+        audio.write(0x9002, 0b1000_0000); // period 0, steps every cycle
+
+        let mut high = 0;
+        for _ in 0..16 {
+            audio.clock();
+            if audio.pulses[0].output() == 15 {
+                high += 1;
+            }
+        }
+        assert_eq!(high, 4);
+
+        audio.write(0x9000, 0b1000_0111);
+        audio.clock();
+        assert_eq!(audio.pulses[0].output(), 7);
+    }
+
+    #[test]
+    fn test_saw_ramps_and_resets() {
+        let mut audio = Vrc6Audio::new();
+        audio.write(0xB000, 42);
+        audio.write(0xB002, 0b1000_0000);
+
+        let mut levels = Vec::new();
+        for _ in 0..14 {
+            audio.clock();
+            levels.push(audio.saw.output());
+        }
+        // 6 adds of 42 peak at 252 >> 3 = 31, then back to 0
+        assert_eq!(levels[11], 31);
+        assert_eq!(levels[13], 0);
+    }
+
+    #[test]
+    fn test_halt_and_frequency_shift() {
+        let mut audio = Vrc6Audio::new();
+        audio.write(0xA000, 0b0111_1111);
+        audio.write(0xA001, 0xFF);
+        audio.write(0xA002, 0b1000_0000); // period 255
+        audio.write(0x9003, 0b100); // period >> 8 = 0
+
+        let before = audio.pulses[1].step;
+        audio.clock();
+        assert_ne!(audio.pulses[1].step, before);
+
+        audio.write(0x9003, 0b001);
+        let before = audio.pulses[1].step;
+        audio.clock();
+        assert_eq!(audio.pulses[1].step, before);
+    }
+
+    #[test]
+    fn test_matches_apu_pulse_volume() {
+        let mut audio = Vrc6Audio::new();
+        audio.write(0x9000, 0b1000_1111);
+        audio.write(0x9002, 0b1000_0000);
+        let apu = crate::apu::mix(15, 0, 0, 0, 0);
+        assert!((audio.output() - apu).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_mapper_26_swaps_address_lines() {
+        let mut vrc6 = Vrc6::new(0x40000, true);
+        vrc6.write(0xB003, 0b1000_1100); // both lines set, same either way
+        assert_eq!(vrc6.mirroring(), Some(Mirroring::SingleScreenUpper));
+        assert_eq!(vrc6.prg_ram_addr(0x6001), Some(1));
+
+        vrc6.write(0xD001, 5); // $D002 on mapper 24
+        vrc6.write(0xD002, 6); // $D001
+        assert_eq!(vrc6.chr_addr(0x0800), 5 * 0x400);
+        assert_eq!(vrc6.chr_addr(0x0400), 6 * 0x400);
+    }
+
+    #[test]
+    fn test_prg_banks() {
+        let mut vrc6 = Vrc6::new(0x40000, false);
+        vrc6.write(0x8000, 3);
+        vrc6.write(0xC000, 9);
+
+        assert_eq!(vrc6.prg_addr(0x8001), 3 * 0x4000 + 1);
+        assert_eq!(vrc6.prg_addr(0xC000), 9 * 0x2000);
+        assert_eq!(vrc6.prg_addr(0xFFFF), 0x3FFFF);
+    }
+}
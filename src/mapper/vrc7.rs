@@ -0,0 +1,274 @@
+use super::vrc_irq::VrcIrq;
+use super::Mapper;
+use crate::cartridge::Mirroring;
+use crate::opll::{Opll, CLOCKS_PER_SAMPLE, VRC7_PATCHES};
+use crate::savestate::{Snapshot, StateReader, StateWriter};
+
+// mapper 85, Konami VRC7. the two board revisions use A4 (VRC7a, Lagrange Point) or A3
+// (VRC7b, Tiny Toon Adventures 2) as the second register line, both are taken here
+// https://www.nesdev.org/wiki/VRC7
+
+// the OPLL runs off twice the CPU clock
+const CPU_CYCLES_PER_SAMPLE: u8 = (CLOCKS_PER_SAMPLE / 2) as u8;
+
+// a channel at full level swings as far as an APU pulse at 15
+const LEVEL: f32 = 0.14937 / 2.0;
+
+pub struct Vrc7 {
+    prg_rom_size: usize,
+    prg_banks: [usize; 3],
+    chr_banks: [usize; 8],
+    mirroring: Mirroring,
+    prg_ram_enabled: bool,
+    irq: VrcIrq,
+    audio: Vrc7Audio,
+}
+
+impl Vrc7 {
+    pub fn new(prg_rom_size: usize) -> Self {
+        Vrc7 {
+            prg_rom_size,
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            mirroring: Mirroring::Vertical,
+            prg_ram_enabled: false,
+            irq: VrcIrq::default(),
+            audio: Vrc7Audio::new(),
+        }
+    }
+}
+
+impl Mapper for Vrc7 {
+    fn prg_addr(&self, addr: u16) -> usize {
+        let offset = (addr & 0x1FFF) as usize;
+        match addr {
+            0x8000..=0xDFFF => self.prg_banks[(addr as usize - 0x8000) / 0x2000] * 0x2000 + offset,
+            _ => self.prg_rom_size - 0x2000 + offset,
+        }
+    }
+
+    fn prg_ram_addr(&self, addr: u16) -> Option<usize> {
+        self.prg_ram_enabled.then_some(addr as usize & 0x1FFF)
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        let addr = addr as usize & 0x1FFF;
+        self.chr_banks[addr / 0x400] * 0x400 + addr % 0x400
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            return;
+        }
+
+        // the sound registers need A5 as well, they'd look the same as $9001 otherwise
+        match addr & 0xF030 {
+            0x9010 => return self.audio.select(data),
+            0x9030 => return self.audio.write(data),
+            _ => {},
+        }
+
+        // $x000 or $x001, whichever line the board uses
+        let register = addr & 0xF000 | (addr & 0x18 != 0) as u16;
+        match register {
+            0x8000 | 0x8001 | 0x9000 => {
+                let bank = ((register - 0x8000) >> 12) * 2 + (register & 1);
+                self.prg_banks[bank as usize] = (data & 0x3F) as usize;
+            },
+            0xA000..=0xD001 => {
+                let bank = ((register - 0xA000) >> 12) * 2 + (register & 1);
+                self.chr_banks[bank as usize] = data as usize;
+            },
+            0xE000 => {
+                self.mirroring = match data & 0b11 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                };
+                self.audio.set_reset(data & 0b0100_0000 != 0);
+                self.prg_ram_enabled = data & 0b1000_0000 != 0;
+            },
+            0xE001 => self.irq.write_latch(data),
+            0xF000 => self.irq.write_control(data),
+            0xF001 => self.irq.acknowledge(),
+            _ => {},
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    fn tick(&mut self) {
+        self.irq.clock();
+        self.audio.clock();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        for &bank in &self.prg_banks {
+            w.usize(bank);
+        }
+        for &bank in &self.chr_banks {
+            w.usize(bank);
+        }
+        self.mirroring.save(w);
+        w.bool(self.prg_ram_enabled);
+        self.irq.save(w);
+        self.audio.save(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), String> {
+        for bank in self.prg_banks.iter_mut() {
+            *bank = r.usize()?;
+        }
+        for bank in self.chr_banks.iter_mut() {
+            *bank = r.usize()?;
+        }
+        self.mirroring.load(r)?;
+        self.prg_ram_enabled = r.bool()?;
+        self.irq.load(r)?;
+        self.audio.load(r)
+    }
+}
+
+// the OPLL with the VRC7's patches and its register port, shared with the NSF player
+#[derive(Clone)]
+pub struct Vrc7Audio {
+    opll: Opll,
+    register: u8,
+    divider: u8,
+    reset: bool, // $E000 bit 6 holds the chip in reset and silences it
+}
+
+impl Default for Vrc7Audio {
+    fn default() -> Self {
+        Vrc7Audio::new()
+    }
+}
+
+impl Vrc7Audio {
+    pub fn new() -> Self {
+        Vrc7Audio { opll: Opll::new(VRC7_PATCHES), register: 0, divider: 0, reset: false }
+    }
+
+    // $9010
+    pub fn select(&mut self, register: u8) {
+        self.register = register;
+    }
+
+    // $9030
+    pub fn write(&mut self, data: u8) {
+        if !self.reset {
+            self.opll.write(self.register, data);
+        }
+    }
+
+    pub fn set_reset(&mut self, reset: bool) {
+        if reset && !self.reset {
+            self.opll = Opll::new(VRC7_PATCHES);
+        }
+        self.reset = reset;
+    }
+
+    // once per CPU cycle
+    pub fn clock(&mut self) {
+        if self.reset {
+            return;
+        }
+        self.divider += 1;
+        if self.divider == CPU_CYCLES_PER_SAMPLE {
+            self.divider = 0;
+            self.opll.clock();
+        }
+    }
+
+    pub fn output(&self) -> f32 {
+        if self.reset {
+            0.0
+        } else {
+            self.opll.output() * LEVEL
+        }
+    }
+}
+
+impl Snapshot for Vrc7Audio {
+    fn save(&self, w: &mut StateWriter) {
+        self.opll.save(w);
+        w.u8(self.register);
+        w.u8(self.divider);
+        w.bool(self.reset);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.opll.load(r)?;
+        self.register = r.u8()?;
+        self.divider = r.u8()?;
+        self.reset = r.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_both_board_revisions() {
+        // VRC7a puts the second register on A4, VRC7b on A3
+        for second in [0x8010, 0x8008] {
+            let mut vrc7 = Vrc7::new(0x40000);
+            vrc7.write(0x8000, 3);
+            vrc7.write(second, 4);
+            vrc7.write(0x9000, 5);
+            assert_eq!(vrc7.prg_addr(0x8000), 3 * 0x2000);
+            assert_eq!(vrc7.prg_addr(0xA000), 4 * 0x2000);
+            assert_eq!(vrc7.prg_addr(0xC000), 5 * 0x2000);
+            assert_eq!(vrc7.prg_addr(0xE000), 0x40000 - 0x2000);
+
+            vrc7.write(0xD000 | (second & 0xFF), 9);
+            assert_eq!(vrc7.chr_addr(0x1C00), 9 * 0x400);
+        }
+    }
+
+    #[test]
+    fn test_sound_port_and_reset() {
+        let mut vrc7 = Vrc7::new(0x40000);
+        vrc7.write(0x9010, 0x30);
+        vrc7.write(0x9030, 0x10); // instrument 1, full volume
+        vrc7.write(0x9010, 0x10);
+        vrc7.write(0x9030, 0x80);
+        vrc7.write(0x9010, 0x20);
+        vrc7.write(0x9030, 0b0001_1000);
+
+        let mut peak: f32 = 0.0;
+        for _ in 0..36 * 2000 {
+            vrc7.tick();
+            peak = peak.max(vrc7.audio_output().abs());
+        }
+        assert!(peak > 0.01);
+
+        vrc7.write(0xE000, 0b0100_0000);
+        vrc7.tick();
+        assert_eq!(vrc7.audio_output(), 0.0);
+    }
+
+    #[test]
+    fn test_irq_registers() {
+        let mut vrc7 = Vrc7::new(0x40000);
+        vrc7.write(0xE010, 0xFF);
+        vrc7.write(0xF000, 0b110); // cycle mode
+        vrc7.tick();
+        assert!(vrc7.irq());
+        vrc7.write(0xF010, 0);
+        assert!(!vrc7.irq());
+    }
+}
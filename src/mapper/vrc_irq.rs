@@ -0,0 +1,127 @@
+use crate::savestate::{Snapshot, StateReader, StateWriter};
+
+// the IRQ counter Konami put in the VRC4, VRC6 and VRC7. counts up from the latch and fires
+// on overflow, either every CPU cycle or every scanline using a prescaler of 341 / 3
+#[derive(Default, Clone)]
+pub struct VrcIrq {
+    pub pending: bool,
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+    enabled: bool,
+    enable_after_ack: bool,
+    cycle_mode: bool,
+}
+
+impl VrcIrq {
+    pub fn write_latch(&mut self, data: u8) {
+        self.latch = data;
+    }
+
+    pub fn write_control(&mut self, data: u8) {
+        self.enable_after_ack = data & 0b001 != 0;
+        self.enabled = data & 0b010 != 0;
+        self.cycle_mode = data & 0b100 != 0;
+        self.pending = false;
+
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = 341;
+        }
+    }
+
+    pub fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    // once per CPU cycle
+    pub fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.cycle_mode {
+            self.clock_counter();
+            return;
+        }
+
+        self.prescaler -= 3;
+        if self.prescaler <= 0 {
+            self.prescaler += 341;
+            self.clock_counter();
+        }
+    }
+
+    fn clock_counter(&mut self) {
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
+
+impl Snapshot for VrcIrq {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.pending);
+        w.u8(self.latch);
+        w.u8(self.counter);
+        w.i16(self.prescaler);
+        w.bool(self.enabled);
+        w.bool(self.enable_after_ack);
+        w.bool(self.cycle_mode);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.pending = r.bool()?;
+        self.latch = r.u8()?;
+        self.counter = r.u8()?;
+        self.prescaler = r.i16()?;
+        self.enabled = r.bool()?;
+        self.enable_after_ack = r.bool()?;
+        self.cycle_mode = r.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_scanline_mode_counts_every_341_dots() {
+        let mut irq = VrcIrq::default();
+        irq.write_latch(0xFE);
+        irq.write_control(0b010);
+
+        // two scanlines is 2 * 341 / 3 cycles, give or take one
+        for _ in 0..227 {
+            irq.clock();
+        }
+        assert!(!irq.pending);
+        irq.clock();
+        assert!(irq.pending);
+
+        irq.acknowledge();
+        assert!(!irq.pending);
+        // enable after ack was 0
+        for _ in 0..10_000 {
+            irq.clock();
+        }
+        assert!(!irq.pending);
+    }
+
+    #[test]
+    fn test_cycle_mode() {
+        let mut irq = VrcIrq::default();
+        irq.write_latch(0xFD);
+        irq.write_control(0b111);
+        irq.clock();
+        irq.clock();
+        assert!(!irq.pending);
+        irq.clock();
+        assert!(irq.pending);
+    }
+}
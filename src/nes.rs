@@ -0,0 +1,745 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::bus::Bus;
+use crate::cartridge::Cartridge;
+use crate::cpu::{CpuCore, CPU};
+use crate::frame::Frame;
+use crate::joypad::ButtonState;
+use crate::palette::Palette;
+use crate::power_on::RamInit;
+use crate::region::Region;
+use crate::rewind::{Rewind, RewindConfig};
+use crate::savestate::{Snapshot, StateChunks, StateReader, StateWriter, STATE_VERSION};
+
+// what came out of one frame of emulation
+pub struct FrameOutput {
+    pub frame: Frame,
+    pub samples: Vec<f32>, // at the APU's sample rate
+    pub frame_count: u64,  // frames run since power on, this one included
+    pub lag: bool,         // the game never read the controllers during it
+}
+
+// how Nes::set_run_ahead gets its frames ahead
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunAheadMode {
+    // save, run ahead and load back on the one machine
+    SingleInstance,
+    // run ahead on a copy of it, the real one is never loaded into
+    SecondInstance,
+}
+
+struct RunAhead {
+    frames: u8,
+    mode: RunAheadMode,
+    state: Vec<u8>, // reused every frame
+    shadow: Option<Box<Nes>>,
+}
+
+/*
+ the whole console: CPU, and through the bus the PPU, APU, cartridge and whatever's plugged in.
+ frontends load a ROM, set the buttons and call run_frame once per frame
+*/
+pub struct Nes {
+    pub cpu: CPU<Bus>,
+    rom: Vec<u8>, // the file, for power cycling
+    region: Region,
+    ram_init: RamInit,
+    audio_smoothing: bool,
+    palette: Option<Palette>,
+    frame_count: u64,
+    halted_at: Option<u16>,
+    rewind: Option<Rewind>,
+    run_ahead: Option<RunAhead>,
+}
+
+impl Nes {
+    pub fn from_rom(raw: &[u8]) -> Result<Nes, String> {
+        Nes::with_core(raw, CpuCore::Instruction)
+    }
+
+    // CpuCore::CycleExact for games and test ROMs that care about every bus access
+    pub fn with_core(raw: &[u8], core: CpuCore) -> Result<Nes, String> {
+        let cartridge = Cartridge::new(raw)?;
+        let region = cartridge.region;
+        let mut cpu = CPU::with_core(Bus::new(cartridge), core);
+        cpu.reset();
+
+        Ok(Nes { cpu, rom: raw.to_vec(), region, ram_init: RamInit::Zeros, audio_smoothing: false, palette: None, frame_count: 0, halted_at: None, rewind: None, run_ahead: None })
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    // overrides what the header says. it's a different console, so this power cycles
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.power_cycle();
+    }
+
+    // what RAM holds at power on, see power_on.rs. power cycles to get it there
+    pub fn set_ram_init(&mut self, init: RamInit) {
+        self.ram_init = init;
+        self.power_cycle();
+    }
+
+    // runs until the PPU finishes a picture, which is the start of vblank
+    pub fn run_frame(&mut self) -> FrameOutput {
+        if let Some(mut rewind) = self.rewind.take() {
+            rewind.record(self.frame_count, self.save_inputs(), || self.save_state());
+            self.rewind = Some(rewind);
+        }
+
+        let mut output = self.emulate_frame();
+        if let Some(mut run_ahead) = self.run_ahead.take() {
+            if self.halted_at.is_none() {
+                output.frame = self.run_ahead_with(&mut run_ahead);
+            }
+            self.run_ahead = Some(run_ahead);
+        }
+        output
+    }
+
+    fn emulate_frame(&mut self) -> FrameOutput {
+        self.cpu.bus.take_input_polled();
+
+        while self.halted_at.is_none() {
+            if !self.cpu.step() {
+                self.halted_at = Some(self.cpu.program_counter.wrapping_sub(1));
+                break;
+            }
+            if self.cpu.bus.ppu.take_frame_complete() {
+                break;
+            }
+        }
+
+        self.frame_count += 1;
+        FrameOutput {
+            frame: self.cpu.bus.ppu.frame.clone(),
+            samples: self.cpu.bus.apu.take_samples(),
+            frame_count: self.frame_count,
+            lag: !self.cpu.bus.take_input_polled(),
+        }
+    }
+
+    // where the CPU hit a BRK or jammed, nothing runs after that
+    pub fn halted_at(&self) -> Option<u16> {
+        self.halted_at
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    // port 0-3, held until the next call, other ports are ignored. see Bus::set_buttons
+    pub fn set_input(&mut self, port: usize, buttons: ButtonState) {
+        self.cpu.bus.set_buttons(port, buttons);
+    }
+
+    pub fn set_audio_smoothing(&mut self, smooth: bool) {
+        self.audio_smoothing = smooth;
+        self.cpu.bus.cartridge.set_audio_smoothing(smooth);
+    }
+
+    // the .pal the frames get shown with, None for the region's own. the Zapper sees the screen in it
+    pub fn set_palette(&mut self, palette: Option<Palette>) {
+        if let Some(shadow) = self.run_ahead.as_mut().and_then(|run_ahead| run_ahead.shadow.as_mut()) {
+            shadow.set_palette(palette.clone());
+        }
+        self.cpu.bus.set_palette(palette.clone());
+        self.palette = palette;
+    }
+
+    /*
+     power off and on again. everything starts over from the ROM except what outlives the power:
+     battery backed PRG RAM, the devices in the ports, the palette and the audio settings
+    */
+    pub fn power_cycle(&mut self) {
+        let mut cartridge = Cartridge::new(&self.rom).expect("the ROM loaded once already");
+        cartridge.set_audio_smoothing(self.audio_smoothing);
+        if cartridge.battery {
+            cartridge.load_prg_ram(self.cpu.bus.cartridge.prg_ram());
+        }
+
+        let old = &mut self.cpu.bus;
+        let mut bus = Bus::new(cartridge);
+        bus.set_palette(self.palette.clone());
+        bus.set_region(self.region);
+        bus.init_ram(self.ram_init);
+        std::mem::swap(&mut bus.ports, &mut old.ports);
+        std::mem::swap(&mut bus.expansion, &mut old.expansion);
+        bus.apu.set_sample_rate(old.apu.sample_rate());
+        if old.apu.stems_enabled() {
+            bus.apu.enable_stems();
+        }
+
+        self.cpu = CPU::with_core(bus, self.cpu.core());
+        self.cpu.reset();
+        self.frame_count = 0;
+        self.halted_at = None;
+        if let Some(rewind) = &mut self.rewind {
+            rewind.clear();
+        }
+    }
+
+    /*
+     the whole machine, see savestate.rs for the format. what's set up from outside stays as it
+     is on load: the CPU core, the sample rate, the RAM fill, audio smoothing and which devices
+     are plugged in. the state has to come from the same ROM, and with the same devices plugged in
+    */
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::new();
+        self.save_state_into(&mut state);
+        state
+    }
+
+    // over whatever's in state, keeping its allocation
+    pub fn save_state_into(&self, state: &mut Vec<u8>) {
+        let bus = &self.cpu.bus;
+        let mut w = StateWriter::reuse(std::mem::take(state));
+        w.chunk(b"NES ", |w| {
+            w.u32(bus.cartridge.crc32());
+            self.region.save(w);
+            w.u64(self.frame_count);
+            w.option(&self.halted_at, |w, pc| w.u16(*pc));
+        });
+        w.chunk(b"CPU ", |w| self.cpu.save(w));
+        w.chunk(b"BUS ", |w| bus.save(w));
+        w.chunk(b"PPU ", |w| bus.ppu.save(w));
+        w.chunk(b"APU ", |w| bus.apu.save(w));
+        w.chunk(b"CART", |w| bus.cartridge.save(w));
+        w.chunk(b"INPT", |w| self.save_devices(w));
+        *state = w.finish();
+    }
+
+    fn save_devices(&self, w: &mut StateWriter) {
+        let bus = &self.cpu.bus;
+        for device in &bus.ports {
+            device.save_state(w);
+        }
+        w.option(&bus.expansion, |w, device| device.save_state(w));
+    }
+
+    fn load_devices(&mut self, r: &mut StateReader) -> Result<(), String> {
+        let bus = &mut self.cpu.bus;
+        for device in bus.ports.iter_mut() {
+            device.load_state(r)?;
+        }
+        let expansion = r.bool()?;
+        match &mut bus.expansion {
+            Some(device) if expansion => device.load_state(r),
+            None if !expansion => Ok(()),
+            _ => Err("save state has a different expansion port device".to_string()),
+        }
+    }
+
+    // just what the player's holding, the devices and the mic, which rewind replays frame by frame
+    fn save_inputs(&self) -> Vec<u8> {
+        let mut w = StateWriter::headerless();
+        self.save_devices(&mut w);
+        w.bool(self.cpu.bus.microphone);
+        w.finish()
+    }
+
+    fn load_inputs(&mut self, inputs: &[u8]) -> Result<(), String> {
+        let mut r = StateReader::new(inputs);
+        self.load_devices(&mut r)?;
+        self.cpu.bus.microphone = r.bool()?;
+        Ok(())
+    }
+
+    // a state that doesn't load leaves the machine as it was
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let chunks = StateChunks::parse(data)?;
+        if chunks.version > STATE_VERSION {
+            return Err(format!("save state is version {}, this build reads up to {}", chunks.version, STATE_VERSION));
+        }
+        let crc = chunks.get(b"NES ")?.u32()?;
+        if crc != self.cpu.bus.cartridge.crc32() {
+            return Err(format!("save state is for ROM {:08x}, this is {:08x}", crc, self.cpu.bus.cartridge.crc32()));
+        }
+
+        let backup = self.save_state();
+        let result = self.load_chunks(&chunks);
+        if result.is_err() {
+            let chunks = StateChunks::parse(&backup).expect("our own state parses");
+            self.load_chunks(&chunks).expect("our own state loads");
+        } else if let Some(rewind) = &mut self.rewind {
+            // what it remembers is from another timeline now
+            rewind.clear();
+        }
+        result
+    }
+
+    // one this machine saved itself, so there's nothing to check and no need for a backup
+    fn load_own_state(&mut self, data: &[u8]) {
+        let chunks = StateChunks::parse(data).expect("our own state parses");
+        self.load_chunks(&chunks).expect("our own state loads");
+    }
+
+    fn load_chunks(&mut self, chunks: &StateChunks) -> Result<(), String> {
+        let mut r = chunks.get(b"NES ")?;
+        r.u32()?;
+        let mut region = self.region;
+        region.load(&mut r)?;
+        if region != self.region {
+            self.region = region;
+            self.cpu.bus.set_region(region);
+        }
+        self.frame_count = r.u64()?;
+        self.halted_at = r.option(|r| r.u16())?;
+
+        Snapshot::load(&mut self.cpu, &mut chunks.get(b"CPU ")?)?;
+        let bus = &mut self.cpu.bus;
+        bus.load(&mut chunks.get(b"BUS ")?)?;
+        bus.ppu.load(&mut chunks.get(b"PPU ")?)?;
+        bus.apu.load(&mut chunks.get(b"APU ")?)?;
+        bus.cartridge.load(&mut chunks.get(b"CART")?)?;
+        bus.ppu.set_mirroring(bus.cartridge.mirroring());
+        self.load_devices(&mut chunks.get(b"INPT")?)
+    }
+
+    // keep the last few seconds so they can be run backwards, see rewind.rs. None turns it off
+    pub fn set_rewind(&mut self, config: Option<RewindConfig>) {
+        self.rewind = config.map(Rewind::new);
+    }
+
+    /*
+     back to frames ago, or as far as the buffer goes, and returns the frame count it got to.
+     frame_count() frames have been run afterwards, the picture is the last of them, and the
+     controllers hold what they did then
+    */
+    pub fn rewind(&mut self, frames: u64) -> Result<u64, String> {
+        let mut rewind = self.rewind.take().ok_or("rewind is off")?;
+        let result = self.rewind_with(&mut rewind, self.frame_count.saturating_sub(frames));
+        self.rewind = Some(rewind);
+        result
+    }
+
+    fn rewind_with(&mut self, rewind: &mut Rewind, frame: u64) -> Result<u64, String> {
+        let point = rewind.restore(frame).ok_or("nothing to rewind to yet")?;
+        self.load_state(&point.state)?;
+        for input in &point.inputs {
+            self.load_inputs(input)?;
+            self.emulate_frame();
+        }
+        Ok(self.frame_count)
+    }
+
+    /*
+     run-ahead hides the game's own input lag. after each frame the machine runs frames more
+     with the same buttons held and run_frame hands back the picture from the last of those, then
+     everything goes back to how it was after the real frame. games that take a frame or two to
+     react to a button then look like they react straight away. frames is 1-4, 0 turns it off.
+     the sound is always the real frame's.
+     SingleInstance loads the state back into this machine, so the frames run ahead also go into
+     the event and VGM logs, and APU stems captured so far are lost. SecondInstance runs them on a
+     second machine that takes this one's state each frame, it costs the memory of another console
+     but leaves this one alone
+    */
+    pub fn set_run_ahead(&mut self, frames: u8, mode: RunAheadMode) -> Result<(), String> {
+        self.run_ahead = match frames {
+            0 => None,
+            1..=4 => Some(RunAhead { frames, mode, state: Vec::new(), shadow: None }),
+            _ => return Err(format!("can't run {} frames ahead, 4 at most", frames)),
+        };
+        Ok(())
+    }
+
+    fn run_ahead_with(&mut self, run_ahead: &mut RunAhead) -> Frame {
+        self.save_state_into(&mut run_ahead.state);
+        match run_ahead.mode {
+            RunAheadMode::SingleInstance => {
+                let frame = self.run_hidden(run_ahead.frames);
+                self.load_own_state(&run_ahead.state);
+                frame
+            },
+            RunAheadMode::SecondInstance => {
+                let shadow = run_ahead.shadow.get_or_insert_with(|| {
+                    let mut shadow = Nes::with_core(&self.rom, self.cpu.core()).expect("the ROM loaded once already");
+                    shadow.cpu.bus.apu.set_sample_rate(self.cpu.bus.apu.sample_rate());
+                    shadow.set_palette(self.palette.clone());
+                    Box::new(shadow)
+                });
+                // the devices go over with the machine, then come back as they were
+                let inputs = self.save_inputs();
+                self.swap_devices(shadow);
+                shadow.load_own_state(&run_ahead.state);
+                let frame = shadow.run_hidden(run_ahead.frames);
+                self.swap_devices(shadow);
+                self.load_inputs(&inputs).expect("our own devices load");
+                frame
+            },
+        }
+    }
+
+    fn run_hidden(&mut self, frames: u8) -> Frame {
+        for _ in 1..frames {
+            self.emulate_frame();
+        }
+        self.emulate_frame().frame
+    }
+
+    fn swap_devices(&mut self, other: &mut Nes) {
+        let (bus, other) = (&mut self.cpu.bus, &mut other.cpu.bus);
+        std::mem::swap(&mut bus.ports, &mut other.ports);
+        std::mem::swap(&mut bus.expansion, &mut other.expansion);
+    }
+
+    // numbered save states in a directory, named after the ROM so games don't share slots
+    pub fn slot_path(&self, dir: &Path, slot: u8) -> PathBuf {
+        dir.join(format!("{:08x}.state{}", self.cpu.bus.cartridge.crc32(), slot))
+    }
+
+    pub fn save_slot(&self, dir: &Path, slot: u8) -> Result<PathBuf, String> {
+        let path = self.slot_path(dir, slot);
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        fs::write(&path, self.save_state()).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(path)
+    }
+
+    pub fn load_slot(&mut self, dir: &Path, slot: u8) -> Result<(), String> {
+        let path = self.slot_path(dir, slot);
+        let data = fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        self.load_state(&data).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // the reset button, RAM, the CPU's A, X and Y and the mapper's registers survive it
+    pub fn soft_reset(&mut self) {
+        self.cpu.bus.ppu.reset();
+        self.cpu.bus.apu.reset();
+        self.cpu.soft_reset();
+        self.halted_at = None;
+        // the reset isn't in the recorded input, so nothing before it can be replayed
+        if let Some(rewind) = &mut self.rewind {
+            rewind.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Mem;
+    use crate::cartridge::test::test_rom;
+    use crate::cpu::CPUStatus;
+
+    #[test]
+    fn test_run_frame() {
+        // JMP * with NMI off, frames still come out
+        let mut nes = Nes::from_rom(&test_rom(&[0x4C, 0x00, 0x80], &[])).unwrap();
+
+        let first = nes.run_frame();
+        let second = nes.run_frame();
+        assert_eq!((first.frame_count, second.frame_count), (1, 2));
+        assert!(second.lag);
+        // a whole frame of audio at 44.1kHz
+        assert!((second.samples.len() as f64 - 44_100.0 / 60.0988).abs() < 2.0);
+        assert_eq!(nes.halted_at(), None);
+    }
+
+    #[test]
+    fn test_lag_frames() {
+        // LDA #$01; STA $4016; LDA #$00; STA $4016; LDA $4016; JMP *
+        let program = [0xA9, 0x01, 0x8D, 0x16, 0x40, 0xA9, 0x00, 0x8D, 0x16, 0x40, 0xAD, 0x16, 0x40, 0x4C, 0x0D, 0x80];
+        let mut nes = Nes::from_rom(&test_rom(&program, &[])).unwrap();
+        nes.set_input(0, ButtonState { a: true, ..Default::default() });
+
+        assert!(!nes.run_frame().lag);
+        assert!(nes.run_frame().lag);
+        assert_eq!(nes.cpu.register_a & 1, 1);
+    }
+
+    #[test]
+    fn test_resets() {
+        let mut raw = test_rom(&[0x4C, 0x00, 0x80], &[]);
+        raw[6] |= 0b10; // battery
+        let mut nes = Nes::from_rom(&raw).unwrap();
+        nes.run_frame();
+        nes.cpu.bus.mem_write(0x0000, 0x42);
+        nes.cpu.bus.mem_write(0x6000, 0x99);
+        nes.cpu.bus.mem_write(0x2000, 0x80);
+        nes.cpu.register_a = 0x11;
+        nes.cpu.register_y = 0x33;
+        nes.cpu.stack_pointer = 0xFF - 0xFD; // S = $FD
+        nes.cpu.status.remove(CPUStatus::InterruptDisable);
+
+        nes.soft_reset();
+        assert_eq!((nes.cpu.register_a, nes.cpu.register_y), (0x11, 0x33));
+        assert_eq!(nes.cpu.get_stack_pointer(), 0x01FA);
+        assert!(nes.cpu.status.contains(CPUStatus::InterruptDisable));
+        assert_eq!(nes.cpu.bus.mem_read(0x0000), 0x42);
+        assert!(!nes.cpu.bus.ppu.ctrl.generate_vblank_nmi());
+        assert_eq!(nes.cpu.program_counter, 0x8000);
+        assert_eq!(nes.frame_count(), 1);
+
+        nes.power_cycle();
+        assert_eq!(nes.cpu.bus.mem_read(0x0000), 0);
+        assert_eq!(nes.cpu.bus.mem_read(0x6000), 0x99);
+        assert_eq!(nes.frame_count(), 0);
+    }
+
+    #[test]
+    fn test_cores_agree() {
+        // NMI on, then INC $10,X forever
+        let raw = test_rom(&[0xA9, 0x80, 0x8D, 0x00, 0x20, 0xF6, 0x10, 0x4C, 0x05, 0x80], &[]);
+        let mut fast = Nes::from_rom(&raw).unwrap();
+        let mut exact = Nes::with_core(&raw, CpuCore::CycleExact).unwrap();
+
+        for _ in 0..3 {
+            fast.run_frame();
+            exact.run_frame();
+        }
+        assert_eq!(exact.cpu.cycles, fast.cpu.cycles);
+        assert_eq!(exact.cpu.bus.mem_read(0x10), fast.cpu.bus.mem_read(0x10));
+
+        exact.power_cycle();
+        assert_eq!(exact.cpu.core(), CpuCore::CycleExact);
+    }
+
+    #[test]
+    fn test_ram_init() {
+        // CHR RAM, and a battery
+        let mut raw = test_rom(&[0x4C, 0x00, 0x80], &[]);
+        raw[5] = 0;
+        raw[6] |= 0b10;
+        raw.truncate(raw.len() - 0x2000);
+        let mut nes = Nes::from_rom(&raw).unwrap();
+        nes.cpu.bus.mem_write(0x6000, 0x99);
+        assert_eq!(nes.cpu.bus.mem_read(0x0700), 0);
+
+        nes.set_ram_init(RamInit::Ones);
+        assert_eq!(nes.cpu.bus.mem_read(0x0700), 0xFF);
+        assert_eq!(nes.cpu.bus.ppu.oam_data[0], 0xFF);
+        assert_eq!(nes.cpu.bus.ppu.palette_table[0], 0x3F);
+        assert_eq!(nes.cpu.bus.cartridge.chr_rom[0x1FFF], 0xFF);
+        assert_eq!(nes.cpu.bus.mem_read(0x6000), 0x99);
+
+        let ram = |nes: &mut Nes| (0..0x800).map(|addr| nes.cpu.bus.mem_read(addr)).collect::<Vec<u8>>();
+        nes.set_ram_init(RamInit::Random(1));
+        let first = ram(&mut nes);
+        nes.power_cycle();
+        assert_eq!(ram(&mut nes), first);
+        nes.set_ram_init(RamInit::Random(2));
+        assert_ne!(ram(&mut nes), first);
+    }
+
+    // NMI and rendering on, a square wave playing, then INC $10 forever
+    const SAVE_STATE_PROGRAM: [u8; 35] = [
+        0xA9, 0x80, 0x8D, 0x00, 0x20, 0xA9, 0x1E, 0x8D, 0x01, 0x20, 0xA9, 0x01, 0x8D, 0x15, 0x40, 0xA9, 0xBF, 0x8D,
+        0x00, 0x40, 0xA9, 0x80, 0x8D, 0x02, 0x40, 0xA9, 0x01, 0x8D, 0x03, 0x40, 0xE6, 0x10, 0x4C, 0x1E, 0x80,
+    ];
+
+    #[test]
+    fn test_save_state_is_exact() {
+        let mut nes = Nes::from_rom(&test_rom(&SAVE_STATE_PROGRAM, &[])).unwrap();
+        nes.set_ram_init(RamInit::Random(3));
+        for _ in 0..5 {
+            nes.run_frame();
+        }
+        let state = nes.save_state();
+
+        let run = |nes: &mut Nes| {
+            let mut out = Vec::new();
+            for _ in 0..3 {
+                let frame = nes.run_frame();
+                out.push((frame.frame.pixels, frame.samples, frame.frame_count));
+            }
+            (out, nes.cpu.cycles, nes.cpu.bus.mem_read(0x10))
+        };
+        let first = run(&mut nes);
+        assert!(first.0[0].1.iter().any(|&s| s != first.0[0].1[0]));
+
+        nes.load_state(&state).unwrap();
+        assert_eq!(nes.frame_count(), 5);
+        assert_eq!(run(&mut nes), first);
+        // and into a machine that just powered on
+        let mut fresh = Nes::from_rom(&test_rom(&SAVE_STATE_PROGRAM, &[])).unwrap();
+        fresh.load_state(&state).unwrap();
+        assert_eq!(run(&mut fresh), first);
+        assert_eq!(fresh.save_state(), nes.save_state());
+    }
+
+    #[test]
+    fn test_save_state_checks() {
+        let mut nes = Nes::from_rom(&test_rom(&SAVE_STATE_PROGRAM, &[])).unwrap();
+        nes.run_frame();
+        let mut state = nes.save_state();
+
+        // other ROMs' states don't load
+        let mut other = Nes::from_rom(&test_rom(&[0x4C, 0x00, 0x80], &[])).unwrap();
+        assert!(other.load_state(&state).unwrap_err().starts_with("save state is for ROM"));
+
+        // a cut off state leaves the machine alone
+        nes.run_frame();
+        let before = nes.save_state();
+        assert!(nes.load_state(&state[..state.len() - 1]).is_err());
+        assert_eq!(nes.save_state(), before);
+
+        // a newer format isn't guessed at
+        let mut newer = state.clone();
+        newer[4..8].copy_from_slice(&(STATE_VERSION + 1).to_le_bytes());
+        assert!(nes.load_state(&newer).unwrap_err().starts_with("save state is version"));
+
+        // chunks it doesn't know are skipped
+        state.extend_from_slice(b"NEW!\x02\x00\x00\x00hi");
+        nes.load_state(&state).unwrap();
+        assert_eq!(nes.frame_count(), 1);
+    }
+
+    #[test]
+    fn test_save_slots() {
+        let dir = std::env::temp_dir().join(format!("nes-emulator-slots-{}", std::process::id()));
+        let mut nes = Nes::from_rom(&test_rom(&SAVE_STATE_PROGRAM, &[])).unwrap();
+        nes.run_frame();
+        let path = nes.save_slot(&dir, 2).unwrap();
+        assert_eq!(path, nes.slot_path(&dir, 2));
+
+        nes.run_frame();
+        nes.load_slot(&dir, 2).unwrap();
+        assert_eq!(nes.frame_count(), 1);
+        assert!(nes.load_slot(&dir, 3).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rewind() {
+        // NMI on, then add up what's read from the controller in $10 forever
+        let program = [
+            0xA9, 0x80, 0x8D, 0x00, 0x20, 0xA9, 0x01, 0x8D, 0x16, 0x40, 0xA9, 0x00, 0x8D, 0x16, 0x40, 0xAD, 0x16, 0x40,
+            0x65, 0x10, 0x85, 0x10, 0x4C, 0x05, 0x80,
+        ];
+        let mut nes = Nes::from_rom(&test_rom(&program, &[])).unwrap();
+        assert!(nes.rewind(1).is_err());
+        nes.set_rewind(Some(RewindConfig { interval: 4, frames: 100, memory: 1 << 20 }));
+        assert!(nes.rewind(1).is_err());
+
+        let press = |nes: &mut Nes, frame: u64| nes.set_input(0, ButtonState { a: frame.is_multiple_of(3), ..Default::default() });
+        let mut states = Vec::new();
+        for frame in 0..30 {
+            states.push(nes.save_state());
+            press(&mut nes, frame);
+            nes.run_frame();
+        }
+
+        // between snapshots, so it has to run forward from the one at 20
+        assert_eq!(nes.rewind(7), Ok(23));
+        assert!(nes.save_state() == states[23]);
+        press(&mut nes, 23);
+        nes.run_frame();
+        assert!(nes.save_state() == states[24]);
+
+        // further than there is goes to the start
+        assert_eq!(nes.rewind(100), Ok(0));
+        assert_eq!((nes.cpu.program_counter, nes.cpu.bus.mem_read(0x10)), (0x8000, 0));
+
+        // a reset starts it over
+        for _ in 0..6 {
+            nes.run_frame();
+        }
+        nes.soft_reset();
+        assert!(nes.rewind(1).is_err());
+    }
+
+    #[test]
+    fn test_rewind_replays_the_microphone() {
+        // the same program as test_rewind, the mic shows up in bit 2 of $4016
+        let program = [
+            0xA9, 0x80, 0x8D, 0x00, 0x20, 0xA9, 0x01, 0x8D, 0x16, 0x40, 0xA9, 0x00, 0x8D, 0x16, 0x40, 0xAD, 0x16, 0x40,
+            0x65, 0x10, 0x85, 0x10, 0x4C, 0x05, 0x80,
+        ];
+        let mut nes = Nes::from_rom(&test_rom(&program, &[])).unwrap();
+        nes.set_rewind(Some(RewindConfig { interval: 4, frames: 100, memory: 1 << 20 }));
+
+        let mut states = Vec::new();
+        for frame in 0..30 {
+            states.push(nes.save_state());
+            nes.cpu.bus.microphone = (25..27).contains(&frame);
+            nes.run_frame();
+        }
+
+        // from the snapshot at 24, through the frames the mic was on
+        assert_eq!(nes.rewind(3), Ok(27));
+        assert!(nes.save_state() == states[27]);
+    }
+
+    #[test]
+    fn test_run_ahead() {
+        // the controller summed into $10 as in test_rewind, and an NMI handler that puts $10 plus
+        // the frame number in the backdrop color
+        let program = [
+            0xA9, 0x80, 0x8D, 0x00, 0x20, 0xA9, 0x01, 0x8D, 0x16, 0x40, 0xA9, 0x00, 0x8D, 0x16, 0x40, 0xAD, 0x16, 0x40,
+            0x65, 0x10, 0x85, 0x10, 0x4C, 0x05, 0x80,
+        ];
+        let nmi = [
+            0x48, 0xE6, 0x11, 0xA9, 0x3F, 0x8D, 0x06, 0x20, 0xA9, 0x00, 0x8D, 0x06, 0x20, 0xA5, 0x11, 0x18, 0x65, 0x10,
+            0x29, 0x3F, 0x8D, 0x07, 0x20, 0xA9, 0x00, 0x8D, 0x06, 0x20, 0x8D, 0x06, 0x20, 0x68, 0x40,
+        ];
+        let mut raw = test_rom(&program, &[]);
+        raw[16 + 0x3F00..16 + 0x3F00 + nmi.len()].copy_from_slice(&nmi);
+
+        let mut plain = Nes::from_rom(&raw).unwrap();
+        let mut single = Nes::from_rom(&raw).unwrap();
+        let mut second = Nes::from_rom(&raw).unwrap();
+        assert!(single.set_run_ahead(5, RunAheadMode::SingleInstance).is_err());
+        single.set_run_ahead(2, RunAheadMode::SingleInstance).unwrap();
+        second.set_run_ahead(2, RunAheadMode::SecondInstance).unwrap();
+
+        let mut frames = Vec::new();
+        let mut shown = Vec::new();
+        for frame in 0..24 {
+            let buttons = ButtonState { a: frame >= 10, ..Default::default() };
+            for nes in [&mut plain, &mut single, &mut second] {
+                nes.set_input(0, buttons);
+            }
+            let real = plain.run_frame();
+            let (a, b) = (single.run_frame(), second.run_frame());
+            // the real machines and their sound don't notice
+            assert_eq!((&a.samples, &b.samples), (&real.samples, &real.samples));
+            assert!(single.save_state() == plain.save_state());
+            assert!(second.save_state() == plain.save_state());
+            assert_eq!(a.frame.pixels, b.frame.pixels);
+
+            frames.push(real.frame.pixels);
+            shown.push(a.frame.pixels);
+        }
+
+        // once the button's been down for a while, what's shown is 2 frames in the future
+        assert_ne!(frames[20], frames[22]);
+        for frame in 12..22 {
+            assert!(shown[frame] == frames[frame + 2]);
+        }
+    }
+
+    #[test]
+    fn test_regions() {
+        let mut raw = test_rom(&[0x4C, 0x00, 0x80], &[]);
+        raw[7] = 0b0000_1000; // NES 2.0
+        raw[12] = 1; // PAL
+        let mut nes = Nes::from_rom(&raw).unwrap();
+        assert_eq!(nes.region(), Region::Pal);
+
+        // 312 lines of 341 dots at 3.2 dots a cycle
+        nes.run_frame();
+        let start = nes.cpu.cycles;
+        nes.run_frame();
+        assert!((nes.cpu.cycles - start).abs_diff(33_248) < 8);
+        assert!((nes.run_frame().samples.len() as f64 - 44_100.0 / 50.007).abs() < 2.0);
+
+        // the Dendy runs 3 dots a cycle and puts vblank 50 lines further down
+        nes.set_region(Region::Dendy);
+        nes.run_frame();
+        assert_eq!(nes.cpu.bus.ppu.scanline, 291);
+        let start = nes.cpu.cycles;
+        nes.run_frame();
+        assert!((nes.cpu.cycles - start).abs_diff(35_464) < 8);
+    }
+
+    #[test]
+    fn test_brk_halts() {
+        let mut nes = Nes::from_rom(&test_rom(&[0xEA, 0x00], &[])).unwrap();
+        nes.run_frame();
+        assert_eq!(nes.halted_at(), Some(0x8001));
+    }
+}
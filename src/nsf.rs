@@ -0,0 +1,659 @@
+use bitflags::bitflags;
+
+use crate::apu::APU;
+use crate::bus::Mem;
+use crate::cpu::CPU;
+use crate::mapper::namco163::Namco163Audio;
+use crate::mapper::vrc6::Vrc6Audio;
+use crate::mapper::vrc7::Vrc7Audio;
+use crate::region::Region;
+use crate::wav::AudioCapture;
+
+const NSF_TAG: &[u8] = b"NESM\x1A";
+const NSFE_TAG: &[u8] = b"NSFE";
+const HEADER_SIZE: usize = 128;
+
+// where the player keeps its JSR to INIT/PLAY. nothing lives at $4100 on a real NES
+const DRIVER: u16 = 0x4100;
+const DRIVER_RETURN: u16 = DRIVER + 3;
+
+const BANK_SIZE: usize = 0x1000;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct ExpansionChips: u8 {
+        const Vrc6 = 0b0000_0001;
+        const Vrc7 = 0b0000_0010;
+        const Fds = 0b0000_0100;
+        const Mmc5 = 0b0000_1000;
+        const Namco163 = 0b0001_0000;
+        const Sunsoft5B = 0b0010_0000;
+        const Vt02 = 0b0100_0000;
+    }
+}
+
+// chips whose audio the player can actually make
+pub const SUPPORTED_CHIPS: ExpansionChips = ExpansionChips::Vrc6.union(ExpansionChips::Vrc7).union(ExpansionChips::Namco163);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NsfRegion {
+    Ntsc,
+    Pal,
+    Dual,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackInfo {
+    pub title: Option<String>,
+    pub length_ms: Option<u32>,
+    pub fade_ms: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Nsf {
+    pub version: u8,
+    pub songs: u8,
+    pub starting_song: u8, // 1 based
+    pub load_addr: u16,
+    pub init_addr: u16,
+    pub play_addr: u16,
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    pub ripper: String,
+    pub ntsc_speed: u16, // microseconds between PLAY calls
+    pub pal_speed: u16,
+    pub banks: Option<[u8; 8]>, // Some if the tune bankswitches
+    pub region: NsfRegion,
+    pub chips: ExpansionChips,
+    pub data: Vec<u8>,
+    pub tracks: Vec<TrackInfo>,
+    pub playlist: Option<Vec<u8>>,
+}
+
+impl Nsf {
+    pub fn parse(raw: &[u8]) -> Result<Nsf, String> {
+        if raw.starts_with(NSF_TAG) {
+            Nsf::parse_nsf(raw)
+        } else if raw.starts_with(NSFE_TAG) {
+            let mut nsf = Nsf::empty();
+            nsf.read_chunks(&raw[4..], true)?;
+            nsf.finish()?;
+            Ok(nsf)
+        } else {
+            Err("not an NSF or NSFe file".to_string())
+        }
+    }
+
+    fn empty() -> Nsf {
+        Nsf {
+            version: 1,
+            songs: 1,
+            starting_song: 1,
+            load_addr: 0x8000,
+            init_addr: 0x8000,
+            play_addr: 0x8000,
+            title: String::new(),
+            artist: String::new(),
+            copyright: String::new(),
+            ripper: String::new(),
+            ntsc_speed: 16639,
+            pal_speed: 19997,
+            banks: None,
+            region: NsfRegion::Ntsc,
+            chips: ExpansionChips::empty(),
+            data: Vec::new(),
+            tracks: Vec::new(),
+            playlist: None,
+        }
+    }
+
+    fn parse_nsf(raw: &[u8]) -> Result<Nsf, String> {
+        if raw.len() < HEADER_SIZE {
+            return Err("NSF header is cut short".to_string());
+        }
+        let word = |at: usize| u16::from_le_bytes([raw[at], raw[at + 1]]);
+
+        let mut nsf = Nsf::empty();
+        nsf.version = raw[5];
+        nsf.songs = raw[6];
+        nsf.starting_song = raw[7];
+        nsf.load_addr = word(0x08);
+        nsf.init_addr = word(0x0A);
+        nsf.play_addr = word(0x0C);
+        nsf.title = c_string(&raw[0x0E..0x2E]);
+        nsf.artist = c_string(&raw[0x2E..0x4E]);
+        nsf.copyright = c_string(&raw[0x4E..0x6E]);
+        nsf.ntsc_speed = word(0x6E);
+        nsf.pal_speed = word(0x78);
+        nsf.region = match raw[0x7A] & 0b11 {
+            0 => NsfRegion::Ntsc,
+            1 => NsfRegion::Pal,
+            _ => NsfRegion::Dual,
+        };
+        nsf.chips = ExpansionChips::from_bits_truncate(raw[0x7B]);
+
+        let banks: [u8; 8] = raw[0x70..0x78].try_into().unwrap();
+        if banks.iter().any(|&bank| bank != 0) {
+            nsf.banks = Some(banks);
+        }
+
+        // NSF2 can say how long the program is and put NSFe style metadata after it
+        let data_len = u32::from_le_bytes([raw[0x7D], raw[0x7E], raw[0x7F], 0]) as usize;
+        let body = &raw[HEADER_SIZE..];
+        if nsf.version >= 2 && data_len > 0 {
+            if data_len > body.len() {
+                return Err("NSF2 program length runs past the end of the file".to_string());
+            }
+            nsf.data = body[..data_len].to_vec();
+            nsf.read_chunks(&body[data_len..], false)?;
+        } else {
+            nsf.data = body.to_vec();
+        }
+
+        nsf.finish()?;
+        Ok(nsf)
+    }
+
+    // NSFe chunks: u32 length, 4 byte id, then the data. an upper case id means we have to understand it
+    fn read_chunks(&mut self, mut raw: &[u8], standalone: bool) -> Result<(), String> {
+        let mut seen_info = !standalone;
+
+        while raw.len() >= 8 {
+            let len = u32::from_le_bytes(raw[0..4].try_into().unwrap()) as usize;
+            let id: [u8; 4] = raw[4..8].try_into().unwrap();
+            let data = raw.get(8..8 + len).ok_or(format!("{} chunk runs past the end of the file", String::from_utf8_lossy(&id)))?;
+            raw = &raw[8 + len..];
+
+            match &id {
+                b"INFO" if standalone => {
+                    if data.len() < 9 {
+                        return Err("INFO chunk is too short".to_string());
+                    }
+                    self.load_addr = u16::from_le_bytes([data[0], data[1]]);
+                    self.init_addr = u16::from_le_bytes([data[2], data[3]]);
+                    self.play_addr = u16::from_le_bytes([data[4], data[5]]);
+                    self.region = match data[6] & 0b11 {
+                        0 => NsfRegion::Ntsc,
+                        1 => NsfRegion::Pal,
+                        _ => NsfRegion::Dual,
+                    };
+                    self.chips = ExpansionChips::from_bits_truncate(data[7]);
+                    self.songs = data[8];
+                    self.starting_song = data.get(9).copied().unwrap_or(0).saturating_add(1);
+                    seen_info = true;
+                },
+                b"DATA" if standalone => self.data = data.to_vec(),
+                b"BANK" => {
+                    let mut banks = [0u8; 8];
+                    banks[..data.len().min(8)].copy_from_slice(&data[..data.len().min(8)]);
+                    self.banks = Some(banks);
+                },
+                b"RATE" => {
+                    if data.len() >= 2 {
+                        self.ntsc_speed = u16::from_le_bytes([data[0], data[1]]);
+                    }
+                    if data.len() >= 4 {
+                        self.pal_speed = u16::from_le_bytes([data[2], data[3]]);
+                    }
+                },
+                b"NEND" => break,
+                b"auth" => {
+                    let mut fields = data.split(|&b| b == 0).map(|s| String::from_utf8_lossy(s).into_owned());
+                    for target in [&mut self.title, &mut self.artist, &mut self.copyright, &mut self.ripper] {
+                        if let Some(field) = fields.next() {
+                            *target = field;
+                        }
+                    }
+                },
+                b"tlbl" => {
+                    for (i, name) in data.split(|&b| b == 0).enumerate() {
+                        if !name.is_empty() {
+                            self.track_mut(i).title = Some(String::from_utf8_lossy(name).into_owned());
+                        }
+                    }
+                },
+                b"time" | b"fade" => {
+                    for (i, ms) in data.chunks_exact(4).enumerate() {
+                        // negative means use the default
+                        let ms = i32::from_le_bytes(ms.try_into().unwrap());
+                        let ms = (ms >= 0).then_some(ms as u32);
+                        let track = self.track_mut(i);
+                        if &id == b"time" { track.length_ms = ms } else { track.fade_ms = ms }
+                    }
+                },
+                b"plst" => self.playlist = Some(data.to_vec()),
+                _ if id[0].is_ascii_uppercase() => {
+                    return Err(format!("don't know how to handle the {} chunk", String::from_utf8_lossy(&id)));
+                },
+                _ => {}, // optional chunk we don't care about
+            }
+        }
+
+        if !seen_info {
+            return Err("NSFe is missing its INFO chunk".to_string());
+        }
+        Ok(())
+    }
+
+    fn track_mut(&mut self, index: usize) -> &mut TrackInfo {
+        if self.tracks.len() <= index {
+            self.tracks.resize(index + 1, TrackInfo::default());
+        }
+        &mut self.tracks[index]
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        if self.songs == 0 {
+            return Err("NSF has no songs".to_string());
+        }
+        if self.data.is_empty() {
+            return Err("NSF has no program data".to_string());
+        }
+        if self.banks.is_none() && self.load_addr < 0x8000 {
+            return Err(format!("load address {:04X} is below $8000", self.load_addr));
+        }
+        self.starting_song = self.starting_song.clamp(1, self.songs);
+        self.tracks.resize(self.songs as usize, TrackInfo::default());
+        Ok(())
+    }
+
+    pub fn unsupported_chips(&self) -> ExpansionChips {
+        self.chips - SUPPORTED_CHIPS
+    }
+
+    // track numbers are 1 based, in playlist order if there is one
+    pub fn track_order(&self) -> Vec<u8> {
+        match &self.playlist {
+            Some(list) => list.iter().filter(|&&t| t < self.songs).map(|t| t + 1).collect(),
+            None => (1..=self.songs).collect(),
+        }
+    }
+
+    pub fn track_listing(&self) -> String {
+        let mut out = String::new();
+        for track in self.track_order() {
+            let info = &self.tracks[track as usize - 1];
+            let title = info.title.clone().unwrap_or(format!("track {}", track));
+            let length = info.length_ms.map(|ms| format!("{}:{:02}", ms / 60_000, ms / 1000 % 60)).unwrap_or("-".to_string());
+            out.push_str(&format!("{:3}  {:5}  {}\n", track, length, title));
+        }
+        out
+    }
+}
+
+fn c_string(raw: &[u8]) -> String {
+    let end = raw.iter().position(|&b| b == 0).unwrap_or(raw.len());
+    String::from_utf8_lossy(&raw[..end]).into_owned()
+}
+
+/*
+ NSF player memory map
+ $0000-$07FF RAM
+ $4000-$4017 APU
+ $4100-$4103 player driver
+ $5FF8-$5FFF bank select, one 4KB page of the tune for each of $8000-$FFFF
+ $6000-$7FFF RAM
+ $8000-$FFFF tune
+*/
+pub struct NsfBus {
+    pub apu: APU,
+    ram: [u8; 0x800],
+    prg_ram: [u8; 0x2000],
+    driver: [u8; 4],
+    rom: Vec<u8>, // whole 4KB pages
+    banks: [u8; 8],
+    vrc6: Option<Vrc6Audio>,
+    vrc7: Option<Vrc7Audio>,
+    pub n163: Option<Namco163Audio>,
+}
+
+impl NsfBus {
+    pub fn new(nsf: &Nsf) -> Self {
+        // the data is loaded at the load address's offset into its page, bankswitched or not
+        let padding = match nsf.banks {
+            Some(_) => nsf.load_addr as usize & 0xFFF,
+            None => nsf.load_addr as usize - 0x8000,
+        };
+        let mut rom = vec![0u8; padding];
+        rom.extend(&nsf.data);
+        rom.resize(rom.len().div_ceil(BANK_SIZE).max(8) * BANK_SIZE, 0);
+
+        NsfBus {
+            apu: APU::new(),
+            ram: [0; 0x800],
+            prg_ram: [0; 0x2000],
+            driver: [0; 4],
+            rom,
+            banks: nsf.banks.unwrap_or([0, 1, 2, 3, 4, 5, 6, 7]),
+            vrc6: nsf.chips.contains(ExpansionChips::Vrc6).then(Vrc6Audio::new),
+            vrc7: nsf.chips.contains(ExpansionChips::Vrc7).then(Vrc7Audio::new),
+            n163: nsf.chips.contains(ExpansionChips::Namco163).then(Namco163Audio::new),
+        }
+    }
+
+    fn rom_read(&self, addr: u16) -> u8 {
+        let page = self.banks[(addr as usize - 0x8000) / BANK_SIZE] as usize % (self.rom.len() / BANK_SIZE);
+        self.rom[page * BANK_SIZE + (addr as usize & 0xFFF)]
+    }
+}
+
+impl Mem for NsfBus {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x0000 ..= 0x1FFF => self.ram[addr as usize & 0x7FF],
+            0x4015 => self.apu.read_status(),
+            0x4800 ..= 0x4FFF => self.n163.as_mut().map_or(0, |n163| n163.read_data()),
+            DRIVER ..= DRIVER_RETURN => self.driver[(addr - DRIVER) as usize],
+            0x6000 ..= 0x7FFF => self.prg_ram[addr as usize - 0x6000],
+            0x8000 ..= 0xFFFF => self.rom_read(addr),
+            _ => 0,
+        }
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x0000 ..= 0x1FFF => self.ram[addr as usize & 0x7FF] = data,
+            0x4000 ..= 0x4013 | 0x4015 | 0x4017 => self.apu.write_register(addr, data),
+            DRIVER ..= DRIVER_RETURN => self.driver[(addr - DRIVER) as usize] = data,
+            0x5FF8 ..= 0x5FFF => self.banks[addr as usize - 0x5FF8] = data,
+            0x6000 ..= 0x7FFF => self.prg_ram[addr as usize - 0x6000] = data,
+            0x9000 ..= 0x9003 | 0xA000 ..= 0xA002 | 0xB000 ..= 0xB002 => {
+                if let Some(vrc6) = &mut self.vrc6 {
+                    vrc6.write(addr, data);
+                }
+            },
+            0x9010 => {
+                if let Some(vrc7) = &mut self.vrc7 {
+                    vrc7.select(data);
+                }
+            },
+            0x9030 => {
+                if let Some(vrc7) = &mut self.vrc7 {
+                    vrc7.write(data);
+                }
+            },
+            0x4800 ..= 0x4FFF => {
+                if let Some(n163) = &mut self.n163 {
+                    n163.write_data(data);
+                }
+            },
+            0xF800 ..= 0xFFFF => {
+                if let Some(n163) = &mut self.n163 {
+                    n163.write_address(data);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn tick(&mut self, cycles: u16) {
+        for _ in 0..cycles {
+            let mut expansion = 0.0;
+            if let Some(vrc6) = &mut self.vrc6 {
+                vrc6.clock();
+                expansion += vrc6.output();
+            }
+            if let Some(vrc7) = &mut self.vrc7 {
+                vrc7.clock();
+                expansion += vrc7.output();
+            }
+            if let Some(n163) = &mut self.n163 {
+                n163.clock();
+                expansion += n163.output();
+            }
+            self.apu.expansion_output = expansion;
+            self.apu.tick();
+            if let Some(addr) = self.apu.dmc.dma_address() {
+                let data = self.rom_read(addr);
+                self.apu.dmc.fill(data);
+            }
+        }
+    }
+
+    fn poll_irq(&mut self) -> bool {
+        self.apu.irq()
+    }
+}
+
+pub struct NsfPlayer {
+    pub nsf: Nsf,
+    pub cpu: CPU<NsfBus>,
+    pub smooth_n163: bool,
+    play_period: f64, // CPU cycles between PLAY calls
+    next_play: f64,
+}
+
+impl NsfPlayer {
+    pub fn new(nsf: Nsf) -> Self {
+        let cpu = CPU::with_bus(NsfBus::new(&nsf));
+        NsfPlayer { nsf, cpu, smooth_n163: false, play_period: 0.0, next_play: 0.0 }
+    }
+
+    // track is 1 based. sets the machine up and runs INIT
+    pub fn start_track(&mut self, track: u8) -> Result<(), String> {
+        if track == 0 || track > self.nsf.songs {
+            return Err(format!("track {} doesn't exist, there are {}", track, self.nsf.songs));
+        }
+
+        let sample_rate = self.cpu.bus.apu.sample_rate();
+        self.cpu = CPU::with_bus(NsfBus::new(&self.nsf));
+        self.cpu.bus.apu.set_sample_rate(sample_rate);
+        if let Some(n163) = &mut self.cpu.bus.n163 {
+            n163.smooth = self.smooth_n163;
+        }
+        self.cpu.reset();
+
+        // silence everything the way the spec asks before INIT
+        for addr in 0x4000..=0x4013 {
+            self.cpu.mem_write(addr, 0);
+        }
+        self.cpu.mem_write(0x4015, 0x0F);
+        self.cpu.mem_write(0x4017, 0x40);
+
+        // PAL only tunes get a PAL APU and clock, dual ones play as NTSC
+        let (speed, pal) = match self.nsf.region {
+            NsfRegion::Pal => (self.nsf.pal_speed, 1),
+            _ => (self.nsf.ntsc_speed, 0),
+        };
+        self.cpu.bus.apu.set_region(self.region());
+        self.play_period = speed.max(1) as f64 * self.region().cpu_clock() as f64 / 1_000_000.0;
+
+        self.call(self.nsf.init_addr, track - 1, pal)?;
+        self.next_play = self.cpu.cycles as f64;
+        Ok(())
+    }
+
+    fn region(&self) -> Region {
+        match self.nsf.region {
+            NsfRegion::Pal => Region::Pal,
+            _ => Region::Ntsc,
+        }
+    }
+
+    // JSRs to the routine through the driver and runs until it comes back
+    fn call(&mut self, addr: u16, a: u8, x: u8) -> Result<(), String> {
+        let [lo, hi] = addr.to_le_bytes();
+        self.cpu.load_at(DRIVER, &[0x20, lo, hi]);
+        self.cpu.register_a = a;
+        self.cpu.register_x = x;
+        self.cpu.program_counter = DRIVER;
+
+        // give up on a routine that hasn't returned after a second, some PLAYs never do
+        let limit = self.region().cpu_clock() as u64;
+        let start = self.cpu.cycles;
+        while self.cpu.program_counter != DRIVER_RETURN {
+            if !self.cpu.step() {
+                return Err(format!("hit a BRK at {:04X}", self.cpu.program_counter.wrapping_sub(1)));
+            }
+            if self.cpu.cycles - start > limit {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // PLAY as many times as fit in this many CPU cycles, idling in between
+    pub fn run_cycles(&mut self, cycles: u64) -> Result<(), String> {
+        let end = self.cpu.cycles + cycles;
+        while self.cpu.cycles < end {
+            if self.cpu.cycles as f64 >= self.next_play {
+                self.next_play += self.play_period;
+                self.call(self.nsf.play_addr, 0, 0)?;
+                continue;
+            }
+
+            let idle = (self.next_play.ceil() as u64).min(end) - self.cpu.cycles;
+            let idle = idle.clamp(1, u16::MAX as u64) as u16;
+            self.cpu.bus.tick(idle);
+            self.cpu.cycles += idle as u64;
+        }
+        Ok(())
+    }
+
+    // plays a track for seconds, then fades it out over fade seconds on top
+    pub fn render(&mut self, track: u8, seconds: f64, fade: f64, sample_rate: u32, stems: bool) -> Result<AudioCapture, String> {
+        self.cpu.bus.apu.set_sample_rate(sample_rate);
+        self.start_track(track)?;
+        let mut capture = AudioCapture::new(&mut self.cpu.bus.apu, sample_rate, stems);
+
+        let clock = self.region().cpu_clock();
+        let total = ((seconds + fade) * clock as f64) as u64;
+        let chunk = clock as u64 / 60;
+        let mut done = 0;
+        while done < total {
+            let cycles = chunk.min(total - done);
+            self.run_cycles(cycles)?;
+            capture.collect(&mut self.cpu.bus.apu);
+            done += cycles;
+        }
+
+        let fade_samples = (fade * sample_rate as f64) as usize;
+        apply_fade(&mut capture.mix, fade_samples);
+        for stem in capture.stems.iter_mut().flatten() {
+            apply_fade(stem, fade_samples);
+        }
+        Ok(capture)
+    }
+}
+
+// linear fade to silence over the last `len` samples
+pub fn apply_fade(samples: &mut [f32], len: usize) {
+    let len = len.min(samples.len());
+    let start = samples.len() - len;
+    for (i, sample) in samples[start..].iter_mut().enumerate() {
+        *sample *= 1.0 - (i + 1) as f32 / len as f32;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::apu::CPU_CLOCK_NTSC;
+
+    // INIT stores the song number at $00, PLAY counts calls in $01
+    fn test_nsf(banks: [u8; 8]) -> Vec<u8> {
+        let mut raw = vec![0u8; HEADER_SIZE];
+        raw[..5].copy_from_slice(NSF_TAG);
+        raw[5] = 1;
+        raw[6] = 3;
+        raw[7] = 2;
+        raw[0x08..0x0A].copy_from_slice(&0x8000u16.to_le_bytes());
+        raw[0x0A..0x0C].copy_from_slice(&0x8000u16.to_le_bytes());
+        raw[0x0C..0x0E].copy_from_slice(&0x8003u16.to_le_bytes());
+        raw[0x0E..0x13].copy_from_slice(b"Tune\0");
+        raw[0x6E..0x70].copy_from_slice(&16639u16.to_le_bytes());
+        raw[0x70..0x78].copy_from_slice(&banks);
+        // STA $00; RTS; INC $01; RTS
+        raw.extend([0x85, 0x00, 0x60, 0xE6, 0x01, 0x60]);
+        raw
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let nsf = Nsf::parse(&test_nsf([0; 8])).unwrap();
+
+        assert_eq!((nsf.songs, nsf.starting_song), (3, 2));
+        assert_eq!(nsf.title, "Tune");
+        assert_eq!(nsf.banks, None);
+        assert_eq!(nsf.track_order(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_play_gets_called_at_the_header_rate() {
+        let mut player = NsfPlayer::new(Nsf::parse(&test_nsf([0; 8])).unwrap());
+        player.start_track(3).unwrap();
+        assert_eq!(player.cpu.mem_read(0x00), 2);
+
+        player.run_cycles(CPU_CLOCK_NTSC as u64).unwrap();
+        let calls = player.cpu.mem_read(0x01);
+        assert!((60..=61).contains(&calls), "{}", calls);
+    }
+
+    #[test]
+    fn test_pal_tunes_run_at_pal_speed() {
+        let mut raw = test_nsf([0; 8]);
+        raw[0x78..0x7A].copy_from_slice(&19997u16.to_le_bytes());
+        raw[0x7A] = 1;
+        let mut player = NsfPlayer::new(Nsf::parse(&raw).unwrap());
+        player.start_track(1).unwrap();
+
+        player.run_cycles(Region::Pal.cpu_clock() as u64).unwrap();
+        let calls = player.cpu.mem_read(0x01);
+        assert!((50..=51).contains(&calls), "{}", calls);
+    }
+
+    #[test]
+    fn test_bankswitching() {
+        // everything mapped to page 0 mirrors the code all over $8000-$FFFF
+        let mut player = NsfPlayer::new(Nsf::parse(&test_nsf([0, 0, 0, 0, 0, 0, 0, 0x10])).unwrap());
+        player.start_track(1).unwrap();
+        assert_eq!(player.cpu.mem_read(0xF000), 0x85);
+
+        player.cpu.mem_write(0x5FFF, 0);
+        player.cpu.mem_write(0x5FF9, 1);
+        assert_eq!(player.cpu.mem_read(0x9000), 0x00);
+        assert_eq!(player.cpu.mem_read(0xF000), 0x85);
+    }
+
+    #[test]
+    fn test_nsfe_metadata() {
+        let mut raw = NSFE_TAG.to_vec();
+        let mut chunk = |id: &[u8], data: &[u8]| {
+            raw.extend((data.len() as u32).to_le_bytes());
+            raw.extend(id);
+            raw.extend(data);
+        };
+        chunk(b"INFO", &[0x00, 0x80, 0x00, 0x80, 0x03, 0x80, 0, 0, 2, 0]);
+        chunk(b"DATA", &[0x85, 0x00, 0x60, 0xE6, 0x01, 0x60]);
+        chunk(b"auth", b"Game\0Composer\0\0Ripper\0");
+        chunk(b"tlbl", b"Intro\0Boss\0");
+        chunk(b"time", &[&90_000i32.to_le_bytes()[..], &(-1i32).to_le_bytes()].concat());
+        chunk(b"plst", &[1, 0]);
+        chunk(b"NEND", &[]);
+
+        let nsf = Nsf::parse(&raw).unwrap();
+        assert_eq!((nsf.title.as_str(), nsf.artist.as_str(), nsf.ripper.as_str()), ("Game", "Composer", "Ripper"));
+        assert_eq!(nsf.tracks[0], TrackInfo { title: Some("Intro".to_string()), length_ms: Some(90_000), fade_ms: None });
+        assert_eq!(nsf.track_order(), vec![2, 1]);
+        assert_eq!(nsf.track_listing(), "  2  -      Boss\n  1  1:30   Intro\n");
+    }
+
+    #[test]
+    fn test_nsfe_starting_song_out_of_range() {
+        let mut raw = NSFE_TAG.to_vec();
+        for (id, data) in [(&b"INFO"[..], &[0x00, 0x80, 0x00, 0x80, 0x03, 0x80, 0, 0, 2, 0xFF][..]), (b"DATA", &[0x60]), (b"NEND", &[])] {
+            raw.extend((data.len() as u32).to_le_bytes());
+            raw.extend(id);
+            raw.extend(data);
+        }
+        assert_eq!(Nsf::parse(&raw).unwrap().starting_song, 2);
+    }
+
+    #[test]
+    fn test_fade() {
+        let mut samples = vec![1.0; 8];
+        apply_fade(&mut samples, 4);
+        assert_eq!(samples, vec![1.0, 1.0, 1.0, 1.0, 0.75, 0.5, 0.25, 0.0]);
+    }
+}
@@ -18,6 +18,11 @@ pub enum OpCodeName {
     ASL, BCC, BCS, BEQ, BIT,
     BMI, BNE, BPL, BVC, BVS,
     CLC, CLD, CLI, CLV, CMP,
-    CPX,
+    CPX, CPY, DEC, DEX, DEY,
+    EOR, INC, INY, JMP, LDY,
+    LSR, NOP, ORA, ROL, ROR,
+    SBC, SEC, SED, SEI, STX,
+    STY, TAY, TSX, TXA, TXS,
+    TYA,
 }
 
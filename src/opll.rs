@@ -0,0 +1,504 @@
+use std::f32::consts::PI;
+
+use crate::savestate::{Snapshot, StateReader, StateWriter};
+
+/*
+ a YM2413 (OPLL) style FM core. 2 operators per channel, a modulator feeding a carrier,
+ with 15 instruments baked in and one that's programmable through registers $00-$07.
+ produces one sample per clock(), which on the real chip is every 72 master clocks.
+ only the melodic side is here, the rhythm mode of the YM2413 isn't
+ https://www.nesdev.org/wiki/VRC7_audio
+*/
+
+pub const CLOCKS_PER_SAMPLE: u32 = 72;
+
+// 8 bytes in the same layout as registers $00-$07
+pub type Patch = [u8; 8];
+
+// the VRC7's own instrument ROM, as dumped from the die
+pub const VRC7_PATCHES: [Patch; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27],
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12],
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12],
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27],
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28],
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4],
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07],
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17],
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01],
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02],
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12],
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16],
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02],
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6],
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06],
+];
+
+// frequency multiplier, doubled so the ×0.5 setting stays whole
+const MULTIPLIERS: [u32; 16] = [1, 2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 20, 24, 24, 30, 30];
+
+// key scale level at block 7 in dB, by the top 4 bits of fnum. drops 6dB per block below
+const KSL_TABLE: [f32; 16] = [
+    0.0, 18.0, 24.0, 27.75, 30.0, 32.25, 33.75, 35.25, 36.0, 37.5, 38.25, 39.0, 39.75, 40.5, 41.25, 42.0,
+];
+
+// how far the modulator's last two outputs push its own phase, in radians at full level
+const FEEDBACK: [f32; 8] = [0.0, PI / 16.0, PI / 8.0, PI / 4.0, PI / 2.0, PI, 2.0 * PI, 4.0 * PI];
+
+// how far a full level modulator moves the carrier's phase
+const MODULATION_DEPTH: f32 = 4.0 * PI;
+
+// vibrato is 8 steps of 1024 samples (about 6Hz), as a fraction of fnum over 256
+const VIBRATO: [i32; 8] = [0, 1, 2, 1, 0, -1, -2, -1];
+const VIBRATO_STEP: u32 = 1024;
+
+// tremolo is a triangle of about 3.7Hz, up to 4.8dB deep
+const TREMOLO_PERIOD: u32 = 13_432;
+const TREMOLO_DEPTH: f32 = 4.8;
+
+// the envelope is 7 bits of 0.375dB steps, all the way down is silence
+const ENVELOPE_STEP_DB: f32 = 0.375;
+const ENVELOPE_MAX: u8 = 127;
+
+// envelope rate accumulator overflows at this, see Operator::rate_steps
+const RATE_THRESHOLD: u32 = 4 << 13;
+
+const PHASE_BITS: u32 = 19;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum EnvelopeState {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    #[default]
+    Off,
+}
+
+// one operator's settings out of a patch
+struct OperatorPatch {
+    tremolo: bool,
+    vibrato: bool,
+    sustained: bool, // the EG type bit, holds at the sustain level while the key is down
+    key_scale_rate: bool,
+    multiplier: u32,
+    key_scale_level: u8,
+    rectified: bool, // half wave, only the positive side of the sine
+    attack: u8,
+    decay: u8,
+    sustain_level: u8,
+    release: u8,
+}
+
+impl OperatorPatch {
+    // operator 0 is the modulator, 1 the carrier
+    fn decode(patch: &Patch, operator: usize) -> Self {
+        let flags = patch[operator];
+        OperatorPatch {
+            tremolo: flags & 0b1000_0000 != 0,
+            vibrato: flags & 0b0100_0000 != 0,
+            sustained: flags & 0b0010_0000 != 0,
+            key_scale_rate: flags & 0b0001_0000 != 0,
+            multiplier: MULTIPLIERS[(flags & 0x0F) as usize],
+            key_scale_level: patch[2 + operator] >> 6,
+            rectified: patch[3] & (0b1000 << operator) != 0,
+            attack: patch[4 + operator] >> 4,
+            decay: patch[4 + operator] & 0x0F,
+            sustain_level: patch[6 + operator] >> 4,
+            release: patch[6 + operator] & 0x0F,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+struct Operator {
+    phase: u32, // PHASE_BITS of fraction of a cycle
+    envelope: u8,
+    state: EnvelopeState,
+    rate_accumulator: u32,
+    output: f32,
+    last_output: f32, // the one before, the modulator's feedback averages both
+}
+
+impl Operator {
+    fn key_on(&mut self) {
+        self.state = EnvelopeState::Attack;
+        self.phase = 0;
+        self.rate_accumulator = 0;
+    }
+
+    fn key_off(&mut self) {
+        if self.state != EnvelopeState::Off {
+            self.state = EnvelopeState::Release;
+        }
+    }
+
+    // how many envelope steps this sample gets at a 0-15 rate. each extra rate level is a
+    // quarter faster and every 4 levels doubles
+    fn rate_steps(&mut self, rate: u8, key_scale: u8) -> u32 {
+        if rate == 0 {
+            return 0;
+        }
+        let effective = (rate as u32 * 4 + key_scale as u32).min(63);
+        self.rate_accumulator += (4 + effective % 4) << (effective / 4);
+        let steps = self.rate_accumulator / RATE_THRESHOLD;
+        self.rate_accumulator %= RATE_THRESHOLD;
+        steps
+    }
+
+    fn clock_envelope(&mut self, patch: &OperatorPatch, key_scale: u8, sustain_flag: bool) {
+        match self.state {
+            EnvelopeState::Attack => {
+                if patch.attack == 15 {
+                    self.envelope = 0;
+                } else {
+                    for _ in 0..self.rate_steps(patch.attack, key_scale) {
+                        self.envelope = self.envelope.saturating_sub((self.envelope >> 3) + 1);
+                    }
+                }
+                if self.envelope == 0 {
+                    self.state = EnvelopeState::Decay;
+                }
+            },
+            EnvelopeState::Decay => {
+                let steps = self.rate_steps(patch.decay, key_scale);
+                self.decay_by(steps);
+                // sustain level is in 3dB steps
+                if self.envelope >= patch.sustain_level * 8 {
+                    self.state = EnvelopeState::Sustain;
+                }
+            },
+            EnvelopeState::Sustain => {
+                // percussive sounds keep fading at the release rate while the key is down
+                if !patch.sustained {
+                    let steps = self.rate_steps(patch.release, key_scale);
+                    self.decay_by(steps);
+                }
+            },
+            EnvelopeState::Release => {
+                let rate = if sustain_flag {
+                    5
+                } else if patch.sustained {
+                    patch.release
+                } else {
+                    7
+                };
+                let steps = self.rate_steps(rate, key_scale);
+                self.decay_by(steps);
+                if self.envelope >= ENVELOPE_MAX {
+                    self.state = EnvelopeState::Off;
+                }
+            },
+            EnvelopeState::Off => self.envelope = ENVELOPE_MAX,
+        }
+    }
+
+    fn decay_by(&mut self, steps: u32) {
+        self.envelope = (self.envelope as u32 + steps).min(ENVELOPE_MAX as u32) as u8;
+    }
+
+    // phase_offset in radians, attenuation in dB on top of the envelope
+    fn compute(&mut self, phase_offset: f32, attenuation: f32, rectified: bool) -> f32 {
+        let angle = 2.0 * PI * self.phase as f32 / (1 << PHASE_BITS) as f32 + phase_offset;
+        let mut wave = angle.sin();
+        if rectified && wave < 0.0 {
+            wave = 0.0;
+        }
+
+        let total = attenuation + self.envelope as f32 * ENVELOPE_STEP_DB;
+        let output = if self.state == EnvelopeState::Off { 0.0 } else { wave * 10f32.powf(-total / 20.0) };
+        self.last_output = self.output;
+        self.output = output;
+        output
+    }
+}
+
+#[derive(Clone, Default)]
+struct Channel {
+    fnum: u16, // 9 bits
+    block: u8,
+    key: bool,
+    sustain: bool,
+    instrument: u8, // 0 is the custom patch
+    volume: u8, // 0 is loudest, 3dB steps
+    operators: [Operator; 2],
+}
+
+#[derive(Clone)]
+pub struct Opll {
+    patches: [Patch; 15],
+    custom: Patch,
+    channels: [Channel; 6],
+    sample_count: u32,
+    output: f32,
+}
+
+impl Opll {
+    pub fn new(patches: [Patch; 15]) -> Self {
+        Opll {
+            patches,
+            custom: [0; 8],
+            channels: Default::default(),
+            sample_count: 0,
+            output: 0.0,
+        }
+    }
+
+    pub fn write(&mut self, register: u8, data: u8) {
+        let channel = (register & 0x0F) as usize;
+        match register {
+            0x00..=0x07 => self.custom[register as usize] = data,
+            0x10..=0x15 => self.channels[channel].fnum = self.channels[channel].fnum & 0x100 | data as u16,
+            0x20..=0x25 => {
+                let ch = &mut self.channels[channel];
+                ch.fnum = ch.fnum & 0xFF | ((data & 1) as u16) << 8;
+                ch.block = (data >> 1) & 0b111;
+                ch.sustain = data & 0b0010_0000 != 0;
+
+                let key = data & 0b0001_0000 != 0;
+                if key && !ch.key {
+                    ch.operators.iter_mut().for_each(Operator::key_on);
+                } else if !key && ch.key {
+                    ch.operators.iter_mut().for_each(Operator::key_off);
+                }
+                ch.key = key;
+            },
+            0x30..=0x35 => {
+                self.channels[channel].instrument = data >> 4;
+                self.channels[channel].volume = data & 0x0F;
+            },
+            _ => {},
+        }
+    }
+
+    fn patch(&self, instrument: u8) -> Patch {
+        match instrument {
+            0 => self.custom,
+            n => self.patches[n as usize - 1],
+        }
+    }
+
+    // one output sample
+    pub fn clock(&mut self) {
+        let vibrato = VIBRATO[(self.sample_count / VIBRATO_STEP) as usize % 8];
+        let tremolo_phase = self.sample_count % TREMOLO_PERIOD;
+        let tremolo = TREMOLO_DEPTH * (1.0 - (2.0 * tremolo_phase as f32 / TREMOLO_PERIOD as f32 - 1.0).abs());
+        self.sample_count = self.sample_count.wrapping_add(1);
+
+        let mut sum = 0.0;
+        for index in 0..self.channels.len() {
+            let patch = self.patch(self.channels[index].instrument);
+            sum += Self::clock_channel(&mut self.channels[index], &patch, vibrato, tremolo);
+        }
+        self.output = sum;
+    }
+
+    fn clock_channel(channel: &mut Channel, patch: &Patch, vibrato: i32, tremolo: f32) -> f32 {
+        let mut outputs = [0.0; 2];
+
+        for (n, operator) in channel.operators.iter_mut().enumerate() {
+            let settings = OperatorPatch::decode(patch, n);
+
+            let key_scale = if settings.key_scale_rate {
+                channel.block << 1 | (channel.fnum >> 8) as u8
+            } else {
+                channel.block >> 1
+            };
+            operator.clock_envelope(&settings, key_scale, channel.sustain);
+
+            let mut fnum = channel.fnum as i32;
+            if settings.vibrato {
+                fnum += fnum * vibrato / 256;
+            }
+            let increment = ((fnum as u32) << channel.block) * settings.multiplier / 2;
+            operator.phase = (operator.phase + increment) & ((1 << PHASE_BITS) - 1);
+
+            let mut attenuation = key_scale_level(channel.fnum, channel.block, settings.key_scale_level);
+            if settings.tremolo {
+                attenuation += tremolo;
+            }
+
+            outputs[n] = if n == 0 {
+                // total level is 0.75dB steps, and only the modulator has one
+                attenuation += (patch[2] & 0x3F) as f32 * 0.75;
+                let feedback = FEEDBACK[(patch[3] & 0b111) as usize] * (operator.output + operator.last_output) / 2.0;
+                operator.compute(feedback, attenuation, settings.rectified)
+            } else {
+                attenuation += channel.volume as f32 * 3.0;
+                operator.compute(outputs[0] * MODULATION_DEPTH, attenuation, settings.rectified)
+            };
+        }
+        outputs[1]
+    }
+
+    // sum of the carriers, a channel at full level swings between -1 and 1
+    pub fn output(&self) -> f32 {
+        self.output
+    }
+}
+
+// in dB. KSL 1, 2 and 3 are 1.5, 3 and 6dB an octave
+fn key_scale_level(fnum: u16, block: u8, ksl: u8) -> f32 {
+    if ksl == 0 {
+        return 0.0;
+    }
+    let base = (KSL_TABLE[(fnum >> 5) as usize] - 6.0 * (7 - block) as f32).max(0.0);
+    base * [0.0, 0.5, 1.0, 2.0][ksl as usize]
+}
+
+impl Snapshot for Operator {
+    fn save(&self, w: &mut StateWriter) {
+        w.u32(self.phase);
+        w.u8(self.envelope);
+        w.u8(self.state as u8);
+        w.u32(self.rate_accumulator);
+        w.f32(self.output);
+        w.f32(self.last_output);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.phase = r.u32()?;
+        self.envelope = r.u8()?;
+        self.state = match r.u8()? {
+            0 => EnvelopeState::Attack,
+            1 => EnvelopeState::Decay,
+            2 => EnvelopeState::Sustain,
+            3 => EnvelopeState::Release,
+            _ => EnvelopeState::Off,
+        };
+        self.rate_accumulator = r.u32()?;
+        self.output = r.f32()?;
+        self.last_output = r.f32()?;
+        Ok(())
+    }
+}
+
+// the instrument ROM is part of the chip, only the custom patch is saved
+impl Snapshot for Opll {
+    fn save(&self, w: &mut StateWriter) {
+        w.bytes(&self.custom);
+        for channel in &self.channels {
+            w.u16(channel.fnum);
+            w.u8(channel.block);
+            w.bool(channel.key);
+            w.bool(channel.sustain);
+            w.u8(channel.instrument);
+            w.u8(channel.volume);
+            for operator in &channel.operators {
+                operator.save(w);
+            }
+        }
+        w.u32(self.sample_count);
+        w.f32(self.output);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        r.bytes(&mut self.custom)?;
+        for channel in self.channels.iter_mut() {
+            channel.fnum = r.u16()?;
+            channel.block = r.u8()?;
+            channel.key = r.bool()?;
+            channel.sustain = r.bool()?;
+            channel.instrument = r.u8()?;
+            channel.volume = r.u8()?;
+            for operator in channel.operators.iter_mut() {
+                operator.load(r)?;
+            }
+        }
+        self.sample_count = r.u32()?;
+        self.output = r.f32()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 3_579_545 / CLOCKS_PER_SAMPLE;
+
+    // a plain sine on the carrier, the modulator turned all the way down
+    fn sine_patch(opll: &mut Opll) {
+        for (register, data) in [0x20, 0x21, 0x3F, 0x00, 0xF0, 0xF0, 0x0F, 0x0F].into_iter().enumerate() {
+            opll.write(register as u8, data);
+        }
+    }
+
+    #[test]
+    fn test_pitch() {
+        let mut opll = Opll::new(VRC7_PATCHES);
+        sine_patch(&mut opll);
+        opll.write(0x30, 0x00);
+        opll.write(0x10, 0x21); // fnum 289, block 4 is about 438Hz
+        opll.write(0x20, 0b0001_1001);
+
+        let mut crossings = 0;
+        let mut last = 0.0;
+        for _ in 0..SAMPLE_RATE {
+            opll.clock();
+            if last <= 0.0 && opll.output() > 0.0 {
+                crossings += 1;
+            }
+            last = opll.output();
+        }
+
+        let expected = 289.0 * SAMPLE_RATE as f32 * 16.0 / (1 << PHASE_BITS) as f32;
+        assert!((crossings as f32 - expected).abs() < 2.0, "{} crossings, wanted {}", crossings, expected);
+    }
+
+    #[test]
+    fn test_volume_and_release() {
+        let mut opll = Opll::new(VRC7_PATCHES);
+        sine_patch(&mut opll);
+        opll.write(0x10, 0x21);
+        opll.write(0x20, 0b0001_1001);
+
+        let peak = |opll: &mut Opll| {
+            let mut peak: f32 = 0.0;
+            for _ in 0..1000 {
+                opll.clock();
+                peak = peak.max(opll.output().abs());
+            }
+            peak
+        };
+
+        opll.write(0x30, 0x00);
+        let loud = peak(&mut opll);
+        opll.write(0x30, 0x02); // 6dB down
+        let quiet = peak(&mut opll);
+        assert!(loud > 0.99);
+        assert!((quiet - 0.5).abs() < 0.02);
+
+        opll.write(0x20, 0b0000_1001);
+        for _ in 0..SAMPLE_RATE {
+            opll.clock();
+        }
+        assert_eq!(opll.output(), 0.0);
+    }
+
+    #[test]
+    fn test_builtin_instruments_make_sound() {
+        for instrument in 1..=15u8 {
+            let mut opll = Opll::new(VRC7_PATCHES);
+            opll.write(0x33, instrument << 4);
+            opll.write(0x13, 0x80);
+            opll.write(0x23, 0b0001_1000);
+
+            let mut peak: f32 = 0.0;
+            for _ in 0..SAMPLE_RATE / 10 {
+                opll.clock();
+                peak = peak.max(opll.output().abs());
+            }
+            assert!(peak > 0.05, "instrument {} peaked at {}", instrument, peak);
+        }
+    }
+
+    #[test]
+    fn test_key_scale_level() {
+        assert_eq!(key_scale_level(0x1FF, 7, 0), 0.0);
+        assert_eq!(key_scale_level(0x1FF, 7, 2), 42.0);
+        assert_eq!(key_scale_level(0x1FF, 7, 3), 84.0);
+        assert_eq!(key_scale_level(0x000, 7, 3), 0.0);
+        assert_eq!(key_scale_level(0x1FF, 0, 2), 0.0);
+    }
+}
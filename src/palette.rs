@@ -0,0 +1,301 @@
+use std::fs;
+use std::path::Path;
+
+// a palette entry is addressed by a 9 bit index: the low 6 bits are the color from palette ram,
+// the upper 3 bits are the PPUMASK emphasis bits (bit 6 = red, bit 7 = green, bit 8 = blue on the 2C02)
+pub const PALETTE_SIZE: usize = 64;
+pub const EMPHASIS_PALETTE_SIZE: usize = PALETTE_SIZE * 8;
+
+// PPUMASK bits the palette cares about
+pub const MASK_GREYSCALE: u8 = 0b0000_0001;
+pub const MASK_EMPHASIS: u8 = 0b1110_0000;
+
+// how much the non-emphasized channels get darkened on the composite PPUs.
+// nesdev measured somewhere around 0.746 to 0.84 depending on the console, this sits in between
+const EMPHASIS_ATTENUATION: f32 = 0.816;
+
+// the usual 2C02 palette
+pub static SYSTEM_PALETTE: [(u8, u8, u8); PALETTE_SIZE] = [
+    (0x80, 0x80, 0x80), (0x00, 0x3D, 0xA6), (0x00, 0x12, 0xB0), (0x44, 0x00, 0x96),
+    (0xA1, 0x00, 0x5E), (0xC7, 0x00, 0x28), (0xBA, 0x06, 0x00), (0x8C, 0x17, 0x00),
+    (0x5C, 0x2F, 0x00), (0x10, 0x45, 0x00), (0x05, 0x4A, 0x00), (0x00, 0x47, 0x2E),
+    (0x00, 0x41, 0x66), (0x00, 0x00, 0x00), (0x05, 0x05, 0x05), (0x05, 0x05, 0x05),
+    (0xC7, 0xC7, 0xC7), (0x00, 0x77, 0xFF), (0x21, 0x55, 0xFF), (0x82, 0x37, 0xFA),
+    (0xEB, 0x2F, 0xB5), (0xFF, 0x29, 0x50), (0xFF, 0x22, 0x00), (0xD6, 0x32, 0x00),
+    (0xC4, 0x62, 0x00), (0x35, 0x80, 0x00), (0x05, 0x8F, 0x00), (0x00, 0x8A, 0x55),
+    (0x00, 0x99, 0xCC), (0x21, 0x21, 0x21), (0x09, 0x09, 0x09), (0x09, 0x09, 0x09),
+    (0xFF, 0xFF, 0xFF), (0x0F, 0xD7, 0xFF), (0x69, 0xA2, 0xFF), (0xD4, 0x80, 0xFF),
+    (0xFF, 0x45, 0xF3), (0xFF, 0x61, 0x8B), (0xFF, 0x88, 0x33), (0xFF, 0x9C, 0x12),
+    (0xFA, 0xBC, 0x20), (0x9F, 0xE3, 0x0E), (0x2B, 0xF0, 0x35), (0x0C, 0xF0, 0xA4),
+    (0x05, 0xFB, 0xFF), (0x5E, 0x5E, 0x5E), (0x0D, 0x0D, 0x0D), (0x0D, 0x0D, 0x0D),
+    (0xFF, 0xFF, 0xFF), (0xA6, 0xFC, 0xFF), (0xB3, 0xEC, 0xFF), (0xDA, 0xAB, 0xEB),
+    (0xFF, 0xA8, 0xF9), (0xFF, 0xAB, 0xB3), (0xFF, 0xD2, 0xB0), (0xFF, 0xEF, 0xA6),
+    (0xFF, 0xF7, 0x9C), (0xD7, 0xE8, 0x95), (0xA6, 0xED, 0xAF), (0xA2, 0xF2, 0xDA),
+    (0x99, 0xFF, 0xFC), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11),
+];
+
+// the RGB PPUs output 3 bits per channel, written here as "RGB" octal digits like nesdev does
+// 2C03 and 2C05 share this one
+static RP2C03_PALETTE: [u16; PALETTE_SIZE] = [
+    0o333, 0o014, 0o006, 0o326, 0o403, 0o503, 0o510, 0o420, 0o320, 0o120, 0o031, 0o040, 0o022, 0o000, 0o000, 0o000,
+    0o555, 0o036, 0o027, 0o407, 0o507, 0o704, 0o700, 0o630, 0o430, 0o140, 0o040, 0o053, 0o044, 0o000, 0o000, 0o000,
+    0o777, 0o357, 0o447, 0o637, 0o707, 0o737, 0o740, 0o750, 0o660, 0o360, 0o070, 0o276, 0o077, 0o000, 0o000, 0o000,
+    0o777, 0o567, 0o657, 0o757, 0o747, 0o755, 0o764, 0o772, 0o773, 0o572, 0o473, 0o276, 0o467, 0o000, 0o000, 0o000,
+];
+
+// the 2C04s are the same set of 64 colors shuffled around so vs. system boards can't be swapped
+static RP2C04_0001_PALETTE: [u16; PALETTE_SIZE] = [
+    0o755, 0o637, 0o700, 0o447, 0o044, 0o120, 0o222, 0o704, 0o777, 0o333, 0o750, 0o503, 0o403, 0o660, 0o320, 0o777,
+    0o357, 0o653, 0o310, 0o360, 0o467, 0o657, 0o764, 0o027, 0o760, 0o276, 0o000, 0o200, 0o666, 0o444, 0o707, 0o014,
+    0o003, 0o567, 0o757, 0o070, 0o077, 0o022, 0o053, 0o507, 0o000, 0o420, 0o747, 0o510, 0o407, 0o006, 0o740, 0o000,
+    0o000, 0o140, 0o555, 0o031, 0o572, 0o326, 0o770, 0o630, 0o020, 0o036, 0o040, 0o111, 0o773, 0o737, 0o430, 0o473,
+];
+
+static RP2C04_0002_PALETTE: [u16; PALETTE_SIZE] = [
+    0o000, 0o750, 0o430, 0o572, 0o473, 0o737, 0o044, 0o567, 0o700, 0o407, 0o773, 0o747, 0o777, 0o637, 0o467, 0o040,
+    0o020, 0o357, 0o510, 0o666, 0o053, 0o360, 0o200, 0o447, 0o222, 0o707, 0o003, 0o276, 0o657, 0o320, 0o000, 0o326,
+    0o403, 0o764, 0o740, 0o757, 0o036, 0o310, 0o555, 0o006, 0o507, 0o760, 0o333, 0o120, 0o027, 0o000, 0o660, 0o777,
+    0o653, 0o111, 0o070, 0o630, 0o022, 0o014, 0o704, 0o140, 0o000, 0o077, 0o420, 0o770, 0o755, 0o503, 0o031, 0o444,
+];
+
+static RP2C04_0004_PALETTE: [u16; PALETTE_SIZE] = [
+    0o430, 0o326, 0o044, 0o660, 0o000, 0o755, 0o014, 0o630, 0o555, 0o310, 0o070, 0o003, 0o764, 0o770, 0o040, 0o572,
+    0o737, 0o200, 0o027, 0o747, 0o000, 0o222, 0o510, 0o740, 0o653, 0o053, 0o447, 0o140, 0o403, 0o000, 0o473, 0o357,
+    0o503, 0o031, 0o420, 0o006, 0o407, 0o507, 0o333, 0o704, 0o022, 0o666, 0o036, 0o020, 0o111, 0o773, 0o444, 0o707,
+    0o757, 0o777, 0o320, 0o700, 0o760, 0o276, 0o777, 0o467, 0o000, 0o750, 0o637, 0o567, 0o360, 0o657, 0o077, 0o120,
+];
+
+// which chip is drawing the picture. the composite one darkens for emphasis, the RGB ones max out the channel
+// (2C04-0003 isn't in here yet, a dumped .pal works for it in the meantime)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
+pub enum PpuModel {
+    RP2C02,
+    RP2C03,
+    RP2C04_0001,
+    RP2C04_0002,
+    RP2C04_0004,
+    RP2C05,
+    RP2C07, // PAL
+    UA6538, // Dendy
+}
+
+impl PpuModel {
+    pub fn is_rgb(&self) -> bool {
+        !matches!(self, PpuModel::RP2C02 | PpuModel::RP2C07 | PpuModel::UA6538)
+    }
+
+    // the 50Hz PPUs have the red and green emphasis bits the other way round
+    pub fn swaps_red_green(&self) -> bool {
+        matches!(self, PpuModel::RP2C07 | PpuModel::UA6538)
+    }
+}
+
+#[derive(Clone)]
+pub struct Palette {
+    colors: Vec<(u8, u8, u8)>, // always EMPHASIS_PALETTE_SIZE long
+    rgb_ppu: bool,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::new(PpuModel::RP2C02)
+    }
+}
+
+impl Palette {
+    pub fn new(model: PpuModel) -> Self {
+        let base: Vec<(u8, u8, u8)> = match model {
+            PpuModel::RP2C02 | PpuModel::RP2C07 | PpuModel::UA6538 => SYSTEM_PALETTE.to_vec(),
+            PpuModel::RP2C03 | PpuModel::RP2C05 => RP2C03_PALETTE.iter().map(|&c| rgb333(c)).collect(),
+            PpuModel::RP2C04_0001 => RP2C04_0001_PALETTE.iter().map(|&c| rgb333(c)).collect(),
+            PpuModel::RP2C04_0002 => RP2C04_0002_PALETTE.iter().map(|&c| rgb333(c)).collect(),
+            PpuModel::RP2C04_0004 => RP2C04_0004_PALETTE.iter().map(|&c| rgb333(c)).collect(),
+        };
+
+        let mut palette = Palette::from_base(&base, model.is_rgb());
+        if model.swaps_red_green() {
+            palette.colors = (0..EMPHASIS_PALETTE_SIZE)
+                .map(|index| {
+                    let emphasis = index / PALETTE_SIZE;
+                    let swapped = emphasis & 0b100 | (emphasis & 0b001) << 1 | (emphasis & 0b010) >> 1;
+                    palette.colors[swapped * PALETTE_SIZE + index % PALETTE_SIZE]
+                })
+                .collect();
+        }
+        palette
+    }
+
+    // a .pal file is either 64 colors, or 512 colors with every emphasis combination already baked in
+    pub fn from_pal(data: &[u8]) -> Result<Palette, String> {
+        let colors: Vec<(u8, u8, u8)> = data.chunks_exact(3).map(|c| (c[0], c[1], c[2])).collect();
+
+        match data.len() {
+            len if len == PALETTE_SIZE * 3 => Ok(Palette::from_base(&colors, false)),
+            len if len == EMPHASIS_PALETTE_SIZE * 3 => Ok(Palette { colors, rgb_ppu: false }),
+            len => Err(format!(
+                "expected a .pal file of {} or {} bytes, got {}",
+                PALETTE_SIZE * 3,
+                EMPHASIS_PALETTE_SIZE * 3,
+                len
+            )),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Palette, String> {
+        let data = fs::read(path.as_ref()).map_err(|e| format!("{}: {}", path.as_ref().display(), e))?;
+        Palette::from_pal(&data)
+    }
+
+    // builds the 7 emphasized copies of a 64 color palette
+    fn from_base(base: &[(u8, u8, u8)], rgb_ppu: bool) -> Palette {
+        let mut colors = Vec::with_capacity(EMPHASIS_PALETTE_SIZE);
+
+        for emphasis in 0..8u8 {
+            for &(r, g, b) in base {
+                colors.push(apply_emphasis((r, g, b), emphasis, rgb_ppu));
+            }
+        }
+
+        Palette { colors, rgb_ppu }
+    }
+
+    pub fn is_rgb(&self) -> bool {
+        self.rgb_ppu
+    }
+
+    // turns a color from palette ram plus the current PPUMASK into a palette index
+    pub fn index(color: u8, mask: u8) -> u16 {
+        let color = if mask & MASK_GREYSCALE != 0 {
+            color & 0x30
+        } else {
+            color & 0x3F
+        };
+
+        ((mask & MASK_EMPHASIS) as u16) << 1 | color as u16
+    }
+
+    pub fn rgb(&self, index: u16) -> (u8, u8, u8) {
+        self.colors[index as usize % EMPHASIS_PALETTE_SIZE]
+    }
+
+    pub fn rgba8888(&self, index: u16) -> [u8; 4] {
+        let (r, g, b) = self.rgb(index);
+        [r, g, b, 0xFF]
+    }
+
+    pub fn rgb565(&self, index: u16) -> u16 {
+        let (r, g, b) = self.rgb(index);
+        (r as u16 >> 3) << 11 | (g as u16 >> 2) << 5 | b as u16 >> 3
+    }
+
+    pub fn to_rgba8888(&self, pixels: &[u16]) -> Vec<u8> {
+        pixels.iter().flat_map(|&p| self.rgba8888(p)).collect()
+    }
+
+    pub fn to_rgb565(&self, pixels: &[u16]) -> Vec<u16> {
+        pixels.iter().map(|&p| self.rgb565(p)).collect()
+    }
+
+    // the 512 color layout, same as a 1536 byte .pal
+    pub fn to_pal(&self) -> Vec<u8> {
+        self.colors.iter().flat_map(|&(r, g, b)| [r, g, b]).collect()
+    }
+}
+
+fn rgb333(color: u16) -> (u8, u8, u8) {
+    let scale = |c: u16| ((c & 0b111) * 255 / 7) as u8;
+    (scale(color >> 6), scale(color >> 3), scale(color))
+}
+
+fn apply_emphasis((r, g, b): (u8, u8, u8), emphasis: u8, rgb_ppu: bool) -> (u8, u8, u8) {
+    if emphasis == 0 {
+        return (r, g, b);
+    }
+
+    let channel = |value: u8, bit: u8| {
+        let emphasized = emphasis & bit != 0;
+        if rgb_ppu {
+            // the RGB PPUs just slam the channel to full
+            if emphasized { 0xFF } else { value }
+        } else if emphasized {
+            value
+        } else {
+            (value as f32 * EMPHASIS_ATTENUATION) as u8
+        }
+    };
+
+    (channel(r, 0b001), channel(g, 0b010), channel(b, 0b100))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_index_greyscale_and_emphasis() {
+        // greyscale drops the hue
+        assert_eq!(Palette::index(0x16, MASK_GREYSCALE), 0x10);
+
+        // red emphasis is bit 5 of the mask, lands on bit 6 of the index
+        assert_eq!(Palette::index(0x16, 0b0010_0000), 0x40 | 0x16);
+        assert_eq!(Palette::index(0x16, 0b1110_0000), 0x1C0 | 0x16);
+    }
+
+    #[test]
+    fn test_emphasis_darkens_other_channels() {
+        let palette = Palette::default();
+
+        let (r, g, b) = palette.rgb(0x20);
+        let (er, eg, eb) = palette.rgb(Palette::index(0x20, 0b0010_0000));
+
+        assert_eq!(er, r);
+        assert!(eg < g);
+        assert!(eb < b);
+    }
+
+    #[test]
+    fn test_rgb_ppu_emphasis_maxes_channel() {
+        let palette = Palette::new(PpuModel::RP2C03);
+
+        assert_eq!(palette.rgb(0x0F), (0, 0, 0));
+        assert_eq!(palette.rgb(Palette::index(0x0F, 0b1000_0000)), (0, 0, 0xFF));
+    }
+
+    #[test]
+    fn test_pal_ppu_swaps_red_and_green_emphasis() {
+        let ntsc = Palette::default();
+        let pal = Palette::new(PpuModel::RP2C07);
+
+        assert_eq!(pal.rgb(0x20), ntsc.rgb(0x20));
+        assert_eq!(pal.rgb(Palette::index(0x20, 0b0010_0000)), ntsc.rgb(Palette::index(0x20, 0b0100_0000)));
+        assert_eq!(pal.rgb(Palette::index(0x20, 0b1100_0000)), ntsc.rgb(Palette::index(0x20, 0b1010_0000)));
+    }
+
+    #[test]
+    fn test_load_pal() {
+        let mut data = vec![0u8; 64 * 3];
+        data[3..6].copy_from_slice(&[1, 2, 3]);
+        let palette = Palette::from_pal(&data).unwrap();
+        assert_eq!(palette.rgb(1), (1, 2, 3));
+
+        // full emphasis files are taken as is
+        let mut data = vec![0u8; 512 * 3];
+        data[(0x40 + 1) * 3] = 0xAB;
+        let palette = Palette::from_pal(&data).unwrap();
+        assert_eq!(palette.rgb(0x41), (0xAB, 0, 0));
+        assert_eq!(palette.to_pal(), data);
+
+        assert!(Palette::from_pal(&[0; 100]).is_err());
+    }
+
+    #[test]
+    fn test_output_formats() {
+        let palette = Palette::default();
+
+        assert_eq!(palette.rgba8888(0x30), [0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(palette.rgb565(0x30), 0xFFFF);
+        assert_eq!(palette.to_rgb565(&[0x0D, 0x30]), vec![0x0000, 0xFFFF]);
+        assert_eq!(palette.to_rgba8888(&[0x0D]), vec![0, 0, 0, 0xFF]);
+    }
+}
@@ -0,0 +1,117 @@
+/*
+ what RAM holds when the console is switched on. real SRAM comes up in whatever state its cells
+ settle into, which is different from console to console and from one power on to the next.
+ games shouldn't care, but some do by accident, so running homebrew under a few of these is a
+ cheap way to catch reads of memory that was never written
+   zeros     every byte $00, what we always did
+   ones      every byte $FF
+   random    a pseudo random fill from a seed, the same seed gives the same RAM
+   pattern   4 bytes of $00 then 4 of $FF, roughly what a lot of consoles come up with
+ https://www.nesdev.org/wiki/CPU_power_up_state
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RamInit {
+    #[default]
+    Zeros,
+    Ones,
+    Random(u64),
+    Pattern,
+}
+
+impl RamInit {
+    // zeros, ones, pattern, random (seed 0) or random:SEED
+    pub fn from_name(name: &str) -> Option<RamInit> {
+        let name = name.to_ascii_lowercase();
+        match name.split_once(':') {
+            Some(("random", seed)) => seed.parse().ok().map(RamInit::Random),
+            Some(_) => None,
+            None => match name.as_str() {
+                "zeros" => Some(RamInit::Zeros),
+                "ones" => Some(RamInit::Ones),
+                "random" => Some(RamInit::Random(0)),
+                "pattern" => Some(RamInit::Pattern),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn filler(self) -> RamFill {
+        let state = match self {
+            RamInit::Random(seed) => seed,
+            _ => 0,
+        };
+        RamFill { init: self, state }
+    }
+}
+
+// fills one block of memory after another. random fills carry on from where the last block
+// stopped so no two blocks come out the same
+pub struct RamFill {
+    init: RamInit,
+    state: u64,
+}
+
+impl RamFill {
+    pub fn fill(&mut self, memory: &mut [u8]) {
+        match self.init {
+            RamInit::Zeros => memory.fill(0x00),
+            RamInit::Ones => memory.fill(0xFF),
+            RamInit::Pattern => {
+                for (addr, byte) in memory.iter_mut().enumerate() {
+                    *byte = if addr & 0b100 == 0 { 0x00 } else { 0xFF };
+                }
+            },
+            RamInit::Random(_) => {
+                for byte in memory.iter_mut() {
+                    *byte = self.next() as u8;
+                }
+            },
+        }
+    }
+
+    // splitmix64, small and good enough for garbage
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn filled(init: RamInit) -> Vec<u8> {
+        let mut memory = vec![0x55; 16];
+        init.filler().fill(&mut memory);
+        memory
+    }
+
+    #[test]
+    fn test_fills() {
+        assert_eq!(filled(RamInit::Zeros), vec![0x00; 16]);
+        assert_eq!(filled(RamInit::Ones), vec![0xFF; 16]);
+        assert_eq!(&filled(RamInit::Pattern)[2..10], &[0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00]);
+
+        assert_eq!(filled(RamInit::Random(7)), filled(RamInit::Random(7)));
+        assert_ne!(filled(RamInit::Random(7)), filled(RamInit::Random(8)));
+
+        // a second block doesn't repeat the first
+        let mut fill = RamInit::Random(7).filler();
+        let (mut a, mut b) = ([0u8; 16], [0u8; 16]);
+        fill.fill(&mut a);
+        fill.fill(&mut b);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(RamInit::from_name("Ones"), Some(RamInit::Ones));
+        assert_eq!(RamInit::from_name("random"), Some(RamInit::Random(0)));
+        assert_eq!(RamInit::from_name("random:1234"), Some(RamInit::Random(1234)));
+        assert_eq!(RamInit::from_name("random:x"), None);
+        assert_eq!(RamInit::from_name("sevens"), None);
+    }
+}
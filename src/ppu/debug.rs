@@ -0,0 +1,378 @@
+// views into the PPU for when the screen looks wrong. none of these touch emulation state
+use std::fmt::Write;
+
+use crate::cartridge::Cartridge;
+use crate::export::{self, ImageFormat};
+use crate::palette::Palette;
+
+use super::{PPU, mirror_palette_addr};
+
+// used for anything drawn on top of the picture, like the scroll window
+const OVERLAY_COLOR: (u8, u8, u8) = (0xFF, 0x00, 0xFF);
+
+// what the pattern table view uses when no palette is picked
+const GREYSCALE_RAMP: [u8; 4] = [0x0F, 0x00, 0x10, 0x30];
+
+// 2 bit color values, [row][column]
+pub type Tile = [[u8; 8]; 8];
+
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub rgb: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Self {
+        Image { width, height, rgb: vec![0; width * height * 3] }
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, (r, g, b): (u8, u8, u8)) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let base = (y * self.width + x) * 3;
+        self.rgb[base..base + 3].copy_from_slice(&[r, g, b]);
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> (u8, u8, u8) {
+        let base = (y * self.width + x) * 3;
+        (self.rgb[base], self.rgb[base + 1], self.rgb[base + 2])
+    }
+
+    // wraps around the edges, scroll windows do
+    fn outline_wrapping(&mut self, x: usize, y: usize, width: usize, height: usize, color: (u8, u8, u8)) {
+        for dx in 0..width {
+            self.set_pixel((x + dx) % self.width, y % self.height, color);
+            self.set_pixel((x + dx) % self.width, (y + height - 1) % self.height, color);
+        }
+        for dy in 0..height {
+            self.set_pixel(x % self.width, (y + dy) % self.height, color);
+            self.set_pixel((x + width - 1) % self.width, (y + dy) % self.height, color);
+        }
+    }
+
+    pub fn encode(&self, format: ImageFormat) -> Vec<u8> {
+        export::encode_rgb(format, self.width, self.height, &self.rgb, &[("Software", "nes-emulator".to_string())])
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sprite {
+    pub index: u8,
+    pub x: u8,
+    pub y: u8,
+    pub tile: u8,
+    pub palette: u8, // 0-3, add 4 for the spot in palette ram
+    pub behind_background: bool,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+}
+
+pub struct Nametable {
+    pub tiles: [u8; 960],     // 32x30 tile indices
+    pub attributes: [u8; 64], // raw attribute bytes
+}
+
+impl Nametable {
+    // which of the 4 background palettes a tile uses
+    pub fn palette_for(&self, column: usize, row: usize) -> u8 {
+        let attribute = self.attributes[(row / 4) * 8 + column / 4];
+        let shift = ((row & 0b10) << 1) | (column & 0b10);
+        (attribute >> shift) & 0b11
+    }
+}
+
+impl PPU {
+    pub fn tile(&self, cart: &Cartridge, table: u8, index: u8) -> Tile {
+        let base = (table as u16 & 1) * 0x1000 + index as u16 * 16;
+        let mut tile = [[0u8; 8]; 8];
+
+        for (row, line) in tile.iter_mut().enumerate() {
+            let lo = self.peek(base + row as u16, cart);
+            let hi = self.peek(base + row as u16 + 8, cart);
+            for (col, pixel) in line.iter_mut().enumerate() {
+                let bit = 7 - col;
+                *pixel = (lo >> bit) & 1 | ((hi >> bit) & 1) << 1;
+            }
+        }
+
+        tile
+    }
+
+    pub fn pattern_table(&self, cart: &Cartridge, table: u8) -> Vec<Tile> {
+        (0..=255).map(|index| self.tile(cart, table, index)).collect()
+    }
+
+    // both tables side by side, 256x128. palette is 0-7 (4-7 are the sprite ones), None draws greys
+    pub fn render_pattern_tables(&self, cart: &Cartridge, palette_index: Option<u8>, palette: &Palette) -> Image {
+        let colors: [(u8, u8, u8); 4] = match palette_index {
+            Some(p) => std::array::from_fn(|i| self.debug_color((p & 0b111) * 4 + i as u8, palette)),
+            None => GREYSCALE_RAMP.map(|c| palette.rgb(c as u16)),
+        };
+
+        let mut image = Image::new(256, 128);
+        for table in 0..2u8 {
+            for (index, tile) in self.pattern_table(cart, table).iter().enumerate() {
+                let (tile_x, tile_y) = (table as usize * 128 + (index % 16) * 8, (index / 16) * 8);
+                draw_tile(&mut image, tile, tile_x, tile_y, &colors, false, false);
+            }
+        }
+        image
+    }
+
+    // one digit per pixel, both tables side by side
+    pub fn pattern_tables_text(&self, cart: &Cartridge) -> String {
+        let tables = [self.pattern_table(cart, 0), self.pattern_table(cart, 1)];
+        let mut out = String::new();
+        for y in 0..128 {
+            for x in 0..256 {
+                let tile = &tables[x / 128][(y / 8) * 16 + (x % 128) / 8];
+                out.push((b'0' + tile[y % 8][x % 8]) as char);
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    // n is the logical nametable 0-3 ($2000/$2400/$2800/$2C00), mirroring already applied
+    pub fn nametable(&self, cart: &Cartridge, n: u8) -> Nametable {
+        let base = 0x2000 + (n as u16 & 0b11) * 0x400;
+        let mut nametable = Nametable { tiles: [0; 960], attributes: [0; 64] };
+
+        for (i, tile) in nametable.tiles.iter_mut().enumerate() {
+            *tile = self.peek(base + i as u16, cart);
+        }
+        for (i, attribute) in nametable.attributes.iter_mut().enumerate() {
+            *attribute = self.peek(base + 0x3C0 + i as u16, cart);
+        }
+        nametable
+    }
+
+    // tile indices as hex, then which palette each tile gets
+    pub fn nametables_text(&self, cart: &Cartridge) -> String {
+        let mut out = String::new();
+        for n in 0..4u8 {
+            let nametable = self.nametable(cart, n);
+            let _ = writeln!(out, "nametable {} (${:04X})", n, 0x2000 + n as u16 * 0x400);
+            for row in 0..30 {
+                let tiles: Vec<String> = (0..32).map(|column| format!("{:02X}", nametable.tiles[row * 32 + column])).collect();
+                let palettes: String = (0..32).map(|column| (b'0' + nametable.palette_for(column, row)) as char).collect();
+                let _ = writeln!(out, "{}  {}", tiles.join(" "), palettes);
+            }
+            out.push('\n');
+        }
+        let (scroll_x, scroll_y) = self.scroll_position();
+        let _ = writeln!(out, "scroll {} {}", scroll_x, scroll_y);
+        out
+    }
+
+    // where the top left of the screen is in the 512x480 nametable space
+    pub fn scroll_position(&self) -> (usize, usize) {
+        let coarse_x = (self.t & super::COARSE_X) as usize;
+        let coarse_y = ((self.t & super::COARSE_Y) >> 5) as usize;
+        let fine_y = ((self.t & super::FINE_Y) >> 12) as usize;
+        let nametable_x = (self.t & super::NAMETABLE_X != 0) as usize;
+        let nametable_y = (self.t & super::NAMETABLE_Y != 0) as usize;
+
+        (
+            nametable_x * 256 + coarse_x * 8 + self.x as usize,
+            nametable_y * 240 + coarse_y * 8 + fine_y,
+        )
+    }
+
+    // all four nametables as a 512x480 picture with the visible screen outlined
+    pub fn render_nametables(&self, cart: &Cartridge, palette: &Palette) -> Image {
+        let table = (self.ctrl.background_pattern_addr() / 0x1000) as u8;
+        let mut image = Image::new(512, 480);
+
+        for n in 0..4u8 {
+            let nametable = self.nametable(cart, n);
+            let (origin_x, origin_y) = ((n as usize & 1) * 256, (n as usize >> 1) * 240);
+
+            for row in 0..30 {
+                for column in 0..32 {
+                    let tile = self.tile(cart, table, nametable.tiles[row * 32 + column]);
+                    let p = nametable.palette_for(column, row);
+                    let colors = std::array::from_fn(|i| self.debug_color(p * 4 + i as u8, palette));
+                    draw_tile(&mut image, &tile, origin_x + column * 8, origin_y + row * 8, &colors, false, false);
+                }
+            }
+        }
+
+        let (scroll_x, scroll_y) = self.scroll_position();
+        image.outline_wrapping(scroll_x, scroll_y, 256, 240, OVERLAY_COLOR);
+        image
+    }
+
+    pub fn sprites(&self) -> Vec<Sprite> {
+        self.oam_data
+            .chunks(4)
+            .enumerate()
+            .map(|(i, entry)| Sprite {
+                index: i as u8,
+                y: entry[0],
+                tile: entry[1],
+                palette: entry[2] & 0b11,
+                behind_background: entry[2] & 0b0010_0000 != 0,
+                flip_horizontal: entry[2] & 0b0100_0000 != 0,
+                flip_vertical: entry[2] & 0b1000_0000 != 0,
+                x: entry[3],
+            })
+            .collect()
+    }
+
+    pub fn sprite_table(&self) -> String {
+        let mut out = String::from(" #    x    y  tile  pal  priority  flip\n");
+        for sprite in self.sprites() {
+            let _ = writeln!(
+                out,
+                "{:02}  {:3}  {:3}   ${:02X}    {}  {:8}  {}{}",
+                sprite.index,
+                sprite.x,
+                sprite.y,
+                sprite.tile,
+                sprite.palette,
+                if sprite.behind_background { "behind" } else { "front" },
+                if sprite.flip_horizontal { "h" } else { "-" },
+                if sprite.flip_vertical { "v" } else { "-" },
+            );
+        }
+        out
+    }
+
+    // every OAM entry in an 8x8 grid, one cell per sprite with a pixel of space between them
+    pub fn render_sprite_sheet(&self, cart: &Cartridge, palette: &Palette) -> Image {
+        let height = self.ctrl.sprite_height() as usize;
+        let (cell_w, cell_h) = (8 + 1, height + 1);
+        let mut image = Image::new(8 * cell_w + 1, 8 * cell_h + 1);
+
+        for sprite in self.sprites() {
+            let (cell_x, cell_y) = (1 + (sprite.index as usize % 8) * cell_w, 1 + (sprite.index as usize / 8) * cell_h);
+            let colors = std::array::from_fn(|i| self.debug_color(16 + sprite.palette * 4 + i as u8, palette));
+
+            let tiles = if height == 16 {
+                let table = sprite.tile & 1;
+                let top = sprite.tile & 0xFE;
+                let (first, second) = if sprite.flip_vertical { (top + 1, top) } else { (top, top + 1) };
+                vec![self.tile(cart, table, first), self.tile(cart, table, second)]
+            } else {
+                let table = (self.ctrl.sprite_pattern_addr() / 0x1000) as u8;
+                vec![self.tile(cart, table, sprite.tile)]
+            };
+
+            for (i, tile) in tiles.iter().enumerate() {
+                draw_tile(&mut image, tile, cell_x, cell_y + i * 8, &colors, sprite.flip_horizontal, sprite.flip_vertical);
+            }
+        }
+        image
+    }
+
+    pub fn palette_ram(&self) -> [u8; 32] {
+        std::array::from_fn(|i| self.palette_table[mirror_palette_addr(i as u16)])
+    }
+
+    pub fn palette_ram_table(&self) -> String {
+        let ram = self.palette_ram();
+        let mut out = String::new();
+        for (i, colors) in ram.chunks(4).enumerate() {
+            let kind = if i < 4 { "bg" } else { "sprite" };
+            let _ = writeln!(out, "{}{}: {:02X} {:02X} {:02X} {:02X}", kind, i % 4, colors[0], colors[1], colors[2], colors[3]);
+        }
+        out
+    }
+
+    // 16x2 swatches, background palettes on top
+    pub fn render_palette_ram(&self, palette: &Palette) -> Image {
+        const SWATCH: usize = 16;
+        let mut image = Image::new(16 * SWATCH, 2 * SWATCH);
+
+        for (i, &color) in self.palette_ram().iter().enumerate() {
+            let rgb = palette.rgb(color as u16 & 0x3F);
+            for y in 0..SWATCH {
+                for x in 0..SWATCH {
+                    image.set_pixel((i % 16) * SWATCH + x, (i / 16) * SWATCH + y, rgb);
+                }
+            }
+        }
+        image
+    }
+
+    // color 0 of every palette is the backdrop, like on the real screen
+    fn debug_color(&self, palette_addr: u8, palette: &Palette) -> (u8, u8, u8) {
+        let addr = if palette_addr.is_multiple_of(4) { 0 } else { palette_addr };
+        palette.rgb(self.palette_table[mirror_palette_addr(addr as u16)] as u16 & 0x3F)
+    }
+}
+
+fn draw_tile(image: &mut Image, tile: &Tile, x: usize, y: usize, colors: &[(u8, u8, u8); 4], flip_h: bool, flip_v: bool) {
+    for row in 0..8 {
+        for col in 0..8 {
+            let value = tile[if flip_v { 7 - row } else { row }][if flip_h { 7 - col } else { col }];
+            image.set_pixel(x + col, y + row, colors[value as usize]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::Mirroring;
+    use crate::cartridge::test::test_rom;
+
+    fn setup() -> (PPU, Cartridge) {
+        // tile 1: left half plane 0, bottom row plane 1
+        let mut chr = vec![0u8; 32];
+        chr[16..24].fill(0xF0);
+        chr[31] = 0xFF;
+        let cart = Cartridge::new(&test_rom(&[], &chr)).unwrap();
+        (PPU::new(Mirroring::Vertical), cart)
+    }
+
+    #[test]
+    fn test_tile_decode() {
+        let (ppu, cart) = setup();
+        let tile = ppu.tile(&cart, 0, 1);
+
+        assert_eq!(tile[0], [1, 1, 1, 1, 0, 0, 0, 0]);
+        assert_eq!(tile[7], [3, 3, 3, 3, 2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn test_sprite_list() {
+        let (mut ppu, _) = setup();
+        ppu.oam_data[4..8].copy_from_slice(&[0x20, 0x05, 0b1110_0010, 0x40]);
+
+        let sprite = ppu.sprites()[1];
+        assert_eq!((sprite.x, sprite.y, sprite.tile, sprite.palette), (0x40, 0x20, 0x05, 2));
+        assert!(sprite.behind_background && sprite.flip_horizontal && sprite.flip_vertical);
+        assert!(ppu.sprite_table().contains("01   64   32   $05    2  behind    hv"));
+    }
+
+    #[test]
+    fn test_scroll_window_outline() {
+        let (mut ppu, mut cart) = setup();
+        ppu.write_register(0x2000, 0b01, &mut cart); // right hand nametable
+        ppu.write_register(0x2005, 16, &mut cart);
+        ppu.write_register(0x2005, 8, &mut cart);
+
+        assert_eq!(ppu.scroll_position(), (256 + 16, 8));
+
+        let image = ppu.render_nametables(&cart, &Palette::default());
+        assert_eq!(image.get_pixel(256 + 16, 8), OVERLAY_COLOR);
+        // wraps back around to the left hand side
+        assert_eq!(image.get_pixel(15, 8), OVERLAY_COLOR);
+    }
+
+    #[test]
+    fn test_palette_ram_mirrors() {
+        let (mut ppu, mut cart) = setup();
+        ppu.mem_write(0x3F00, 0x0F, &mut cart);
+        ppu.mem_write(0x3F05, 0x16, &mut cart);
+
+        let ram = ppu.palette_ram();
+        assert_eq!(ram[0x10], 0x0F);
+        assert_eq!(ram[0x05], 0x16);
+        assert!(ppu.palette_ram_table().starts_with("bg0: 0F 00 00 00\nbg1: 00 16 00 00"));
+    }
+}
@@ -0,0 +1,691 @@
+use crate::cartridge::{Cartridge, Mirroring};
+use crate::mapper::NametableSource;
+use crate::frame::Frame;
+use crate::palette::Palette;
+use crate::power_on::RamFill;
+use crate::region::Region;
+use crate::savestate::{Snapshot, StateReader, StateWriter};
+
+pub mod debug;
+pub mod registers;
+
+use registers::{ControlRegister, MaskRegister, StatusRegister};
+
+// NTSC frame geometry, the other regions get theirs from Region
+pub const DOTS_PER_SCANLINE: u16 = 341;
+pub const SCANLINES_PER_FRAME: u16 = 262;
+pub const VBLANK_SCANLINE: u16 = 241;
+pub const PRE_RENDER_SCANLINE: u16 = 261;
+
+/*
+ the internal scroll registers (v and t) are laid out like this
+ yyy NN YYYYY XXXXX
+ ||| || ||||| +++++-- coarse X scroll
+ ||| || +++++-------- coarse Y scroll
+ ||| ++-------------- nametable select
+ +++----------------- fine Y scroll
+ https://www.nesdev.org/wiki/PPU_scrolling
+*/
+const COARSE_X: u16 = 0x001F;
+const COARSE_Y: u16 = 0x03E0;
+const NAMETABLE_X: u16 = 0x0400;
+const NAMETABLE_Y: u16 = 0x0800;
+const FINE_Y: u16 = 0x7000;
+
+pub struct PPU {
+    pub ctrl: ControlRegister,
+    pub mask: MaskRegister,
+    pub status: StatusRegister,
+    pub oam_addr: u8,
+    pub oam_data: [u8; 256],
+    pub palette_table: [u8; 32],
+    pub vram: [u8; 4096], // only 2KB on the board, four screen carts bring the other 2
+    pub v: u16,           // current vram address
+    pub t: u16,           // temporary vram address, the top left of the screen
+    pub x: u8,            // fine x scroll
+    pub w: bool,          // first/second write toggle for $2005/$2006
+    pub scanline: u16,
+    pub dot: u16,
+    pub frame_count: u64,
+    pub frame: Frame,
+    mirroring: Mirroring,
+    region: Region,
+    read_buffer: u8,
+    io_latch: u8,
+    latch_refreshed: [u64; 8], // the frame each bit of io_latch was last driven on
+    odd_frame: bool,
+    frame_complete: bool,
+    sprite_zero_hit_dot: Option<u16>,
+}
+
+impl PPU {
+    pub fn new(mirroring: Mirroring) -> Self {
+        PPU {
+            ctrl: ControlRegister::empty(),
+            mask: MaskRegister::empty(),
+            status: StatusRegister::empty(),
+            oam_addr: 0,
+            oam_data: [0; 256],
+            palette_table: [0; 32],
+            vram: [0; 4096],
+            v: 0,
+            t: 0,
+            x: 0,
+            w: false,
+            scanline: 0,
+            dot: 0,
+            frame_count: 0,
+            frame: Frame::new(),
+            mirroring,
+            region: Region::Ntsc,
+            read_buffer: 0,
+            io_latch: 0,
+            latch_refreshed: [0; 8],
+            odd_frame: false,
+            frame_complete: false,
+            sprite_zero_hit_dot: None,
+        }
+    }
+
+    // registers, addr is already folded down to $2000-$2007
+    pub fn read_register(&mut self, addr: u16, cart: &mut Cartridge) -> u8 {
+        self.decay_latch();
+
+        // which bits the register actually drives, the rest come from the latch
+        let (data, driven) = match addr {
+            0x2002 => {
+                let data = self.status.bits() | (self.io_latch & 0b0001_1111);
+                self.status.remove(StatusRegister::VblankStarted);
+                self.w = false;
+                (data, 0b1110_0000)
+            },
+            0x2004 => (self.oam_data[self.oam_addr as usize], 0xFF),
+            0x2007 => self.read_data(cart),
+            // the rest are write only, you get whatever is left on the PPU's data bus
+            _ => (self.io_latch, 0),
+        };
+
+        self.refresh_latch(data, driven);
+        data
+    }
+
+    pub fn write_register(&mut self, addr: u16, data: u8, cart: &mut Cartridge) {
+        self.refresh_latch(data, 0xFF);
+
+        match addr {
+            0x2000 => {
+                self.ctrl = ControlRegister::from_bits_truncate(data);
+                self.t = (self.t & !(NAMETABLE_X | NAMETABLE_Y)) | ((data as u16 & 0b11) << 10);
+            },
+            0x2001 => self.mask = MaskRegister::from_bits_truncate(data),
+            0x2003 => self.oam_addr = data,
+            0x2004 => {
+                self.oam_data[self.oam_addr as usize] = data;
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+            },
+            0x2005 => {
+                if !self.w {
+                    self.t = (self.t & !COARSE_X) | (data as u16 >> 3);
+                    self.x = data & 0b111;
+                } else {
+                    self.t = (self.t & !(COARSE_Y | FINE_Y))
+                        | ((data as u16 >> 3) << 5)
+                        | ((data as u16 & 0b111) << 12);
+                }
+                self.w = !self.w;
+            },
+            0x2006 => {
+                if !self.w {
+                    self.t = (self.t & 0x00FF) | ((data as u16 & 0x3F) << 8);
+                } else {
+                    self.t = (self.t & 0xFF00) | data as u16;
+                    self.v = self.t;
+                }
+                self.w = !self.w;
+            },
+            0x2007 => {
+                self.mem_write(self.v, data, cart);
+                self.increment_vram_addr();
+            },
+            _ => {}, // $2002 is read only
+        }
+    }
+
+    pub fn write_oam_dma(&mut self, data: &[u8; 256]) {
+        for &byte in data.iter() {
+            self.oam_data[self.oam_addr as usize] = byte;
+            self.oam_addr = self.oam_addr.wrapping_add(1);
+        }
+    }
+
+    // the data and which bits of it were driven, palette entries are only 6 bits
+    fn read_data(&mut self, cart: &mut Cartridge) -> (u8, u8) {
+        let addr = self.v & 0x3FFF;
+        self.increment_vram_addr();
+
+        match addr {
+            // palette reads skip the buffer, but the buffer still gets the nametable byte underneath
+            0x3F00..=0x3FFF => {
+                self.read_buffer = self.mem_read(addr - 0x1000, cart);
+                ((self.mem_read(addr, cart) & 0x3F) | (self.io_latch & 0xC0), 0x3F)
+            },
+            _ => {
+                let result = self.read_buffer;
+                self.read_buffer = self.mem_read(addr, cart);
+                (result, 0xFF)
+            },
+        }
+    }
+
+    /*
+     the latch is just the capacitance of the PPU's data bus, a bit that isn't driven again
+     leaks back to 0 after about 600ms. writes drive every bit, reads only the ones the register has
+     https://www.nesdev.org/wiki/PPU_registers#Open_bus
+    */
+    fn refresh_latch(&mut self, data: u8, driven: u8) {
+        self.io_latch = (self.io_latch & !driven) | (data & driven);
+        for (bit, refreshed) in self.latch_refreshed.iter_mut().enumerate() {
+            if driven & (1 << bit) != 0 {
+                *refreshed = self.frame_count;
+            }
+        }
+    }
+
+    fn decay_latch(&mut self) {
+        let decay_frames = (0.6 * self.region.frame_rate()) as u64;
+        for (bit, refreshed) in self.latch_refreshed.iter().enumerate() {
+            if self.frame_count.saturating_sub(*refreshed) > decay_frames {
+                self.io_latch &= !(1 << bit);
+            }
+        }
+    }
+
+    fn increment_vram_addr(&mut self) {
+        self.v = self.v.wrapping_add(self.ctrl.vram_addr_increment()) & 0x7FFF;
+    }
+
+    // the PPU's own address space
+    pub fn mem_read(&self, addr: u16, cart: &mut Cartridge) -> u8 {
+        match addr & 0x3FFF {
+            0x0000..=0x1FFF => cart.ppu_read(addr & 0x1FFF),
+            0x2000..=0x3EFF => self.nametable_read(addr, cart),
+            _ => self.palette_table[mirror_palette_addr(addr)],
+        }
+    }
+
+    // reads without side effects on the cartridge, for debug views
+    pub fn peek(&self, addr: u16, cart: &Cartridge) -> u8 {
+        match addr & 0x3FFF {
+            0x0000..=0x1FFF => cart.ppu_peek(addr & 0x1FFF),
+            0x2000..=0x3EFF => self.nametable_read(addr, cart),
+            _ => self.palette_table[mirror_palette_addr(addr)],
+        }
+    }
+
+    pub fn mem_write(&mut self, addr: u16, data: u8, cart: &mut Cartridge) {
+        match addr & 0x3FFF {
+            0x0000..=0x1FFF => cart.ppu_write(addr & 0x1FFF, data),
+            0x2000..=0x3EFF => match cart.nametable(addr) {
+                Some(NametableSource::Chr(offset)) => cart.write_chr(offset, data),
+                Some(NametableSource::Ciram(page)) => self.vram[page * 0x400 + (addr & 0x3FF) as usize] = data,
+                None => self.vram[self.mirror_vram_addr(addr)] = data,
+            },
+            _ => self.palette_table[mirror_palette_addr(addr)] = data,
+        }
+    }
+
+    // some boards hand out nametables themselves, everything else goes by mirroring
+    fn nametable_read(&self, addr: u16, cart: &Cartridge) -> u8 {
+        match cart.nametable(addr) {
+            Some(NametableSource::Chr(offset)) => cart.chr_byte(offset),
+            Some(NametableSource::Ciram(page)) => self.vram[page * 0x400 + (addr & 0x3FF) as usize],
+            None => self.vram[self.mirror_vram_addr(addr)],
+        }
+    }
+
+    // Horizontal:
+    //   [ A ] [ a ]
+    //   [ B ] [ b ]
+    // Vertical:
+    //   [ A ] [ B ]
+    //   [ a ] [ b ]
+    pub fn mirror_vram_addr(&self, addr: u16) -> usize {
+        let index = (addr & 0x0FFF) as usize;
+        let table = index / 0x400;
+        let physical = match self.mirroring {
+            Mirroring::Vertical => table & 1,
+            Mirroring::Horizontal => table >> 1,
+            Mirroring::FourScreen => table,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+        };
+
+        physical * 0x400 + index % 0x400
+    }
+
+    // the reset button. the registers go back to 0 but VRAM, OAM and palettes keep what they had
+    // https://www.nesdev.org/wiki/PPU_power_up_state
+    pub fn reset(&mut self) {
+        self.ctrl = ControlRegister::empty();
+        self.mask = MaskRegister::empty();
+        self.t = 0;
+        self.x = 0;
+        self.w = false;
+        self.read_buffer = 0;
+        self.odd_frame = false;
+    }
+
+    // OAM, palette RAM and the nametables at power on. palette entries are only 6 bits
+    pub fn init_ram(&mut self, fill: &mut RamFill) {
+        fill.fill(&mut self.oam_data);
+        fill.fill(&mut self.palette_table);
+        for entry in self.palette_table.iter_mut() {
+            *entry &= 0x3F;
+        }
+        fill.fill(&mut self.vram);
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    // for mappers that switch mirroring on the fly
+    pub fn set_mirroring(&mut self, mirroring: Mirroring) {
+        self.mirroring = mirroring;
+    }
+
+    pub fn rendering_enabled(&self) -> bool {
+        self.mask.rendering_enabled()
+    }
+
+    // the /NMI pin, low (true here) while in vblank with NMI turned on. the CPU fires on it going
+    // low, so turning NMI on in the middle of vblank fires one right away and reading $2002 just
+    // as vblank starts can lose one
+    pub fn nmi_line(&self) -> bool {
+        self.ctrl.generate_vblank_nmi() && self.status.contains(StatusRegister::VblankStarted)
+    }
+
+    // true once per frame, when vblank starts and `frame` holds a finished picture
+    pub fn take_frame_complete(&mut self) -> bool {
+        std::mem::take(&mut self.frame_complete)
+    }
+
+    // advance by one dot
+    pub fn tick(&mut self, cart: &mut Cartridge) {
+        let rendering = self.rendering_enabled();
+        let pre_render = self.region.scanlines() - 1;
+
+        if self.scanline < 240 && self.dot == 1 {
+            self.render_scanline(cart);
+        }
+
+        if rendering && (self.scanline < 240 || self.scanline == pre_render) {
+            match self.dot {
+                256 => self.increment_y(),
+                257 => self.v = (self.v & !(COARSE_X | NAMETABLE_X)) | (self.t & (COARSE_X | NAMETABLE_X)),
+                280..=304 if self.scanline == pre_render => {
+                    let vertical = COARSE_Y | NAMETABLE_Y | FINE_Y;
+                    self.v = (self.v & !vertical) | (self.t & vertical);
+                },
+                _ => {},
+            }
+        }
+
+        if self.sprite_zero_hit_dot == Some(self.dot) {
+            self.status.insert(StatusRegister::SpriteZeroHit);
+            self.sprite_zero_hit_dot = None;
+        }
+
+        if self.scanline == self.region.vblank_scanline() && self.dot == 1 {
+            self.status.insert(StatusRegister::VblankStarted);
+            self.frame_complete = true;
+        }
+
+        if self.scanline == pre_render && self.dot == 1 {
+            self.status.remove(StatusRegister::VblankStarted | StatusRegister::SpriteZeroHit | StatusRegister::SpriteOverflow);
+        }
+
+        self.dot += 1;
+
+        // odd frames are one dot shorter while rendering, only on NTSC
+        let skip_dot = self.odd_frame && rendering && self.region == Region::Ntsc;
+        if self.scanline == pre_render && self.dot == DOTS_PER_SCANLINE - 1 && skip_dot {
+            self.dot = DOTS_PER_SCANLINE;
+        }
+
+        if self.dot >= DOTS_PER_SCANLINE {
+            self.dot = 0;
+            self.scanline += 1;
+
+            if self.scanline >= self.region.scanlines() {
+                self.scanline = 0;
+                self.frame_count += 1;
+                self.odd_frame = !self.odd_frame;
+            }
+        }
+    }
+
+    fn increment_y(&mut self) {
+        if self.v & FINE_Y != FINE_Y {
+            self.v += 0x1000;
+            return;
+        }
+
+        self.v &= !FINE_Y;
+        let mut coarse_y = (self.v & COARSE_Y) >> 5;
+        if coarse_y == 29 {
+            coarse_y = 0;
+            self.v ^= NAMETABLE_Y;
+        } else if coarse_y == 31 {
+            // out of bounds rows wrap without switching nametables
+            coarse_y = 0;
+        } else {
+            coarse_y += 1;
+        }
+        self.v = (self.v & !COARSE_Y) | (coarse_y << 5);
+    }
+
+    // draws the whole line at once from v as it stands at the start of it.
+    // good enough for splits done in hblank, not for mid-line tricks
+    fn render_scanline(&mut self, cart: &mut Cartridge) {
+        let y = self.scanline as usize;
+
+        // palette ram address for each pixel, 0 means transparent
+        let mut background = [0u8; Frame::WIDTH];
+        if self.mask.contains(MaskRegister::ShowBackground) {
+            self.render_background_line(&mut background, cart);
+
+            if !self.mask.contains(MaskRegister::ShowBackgroundLeft) {
+                background[..8].fill(0);
+            }
+        }
+
+        // (palette ram address, behind background, is sprite 0)
+        let mut sprites: [Option<(u8, bool, bool)>; Frame::WIDTH] = [None; Frame::WIDTH];
+        if self.mask.contains(MaskRegister::ShowSprites) {
+            self.render_sprite_line(&mut sprites, cart);
+
+            if !self.mask.contains(MaskRegister::ShowSpritesLeft) {
+                sprites[..8].fill(None);
+            }
+        }
+
+        for x in 0..Frame::WIDTH {
+            let bg = background[x];
+            let bg_opaque = bg & 0b11 != 0;
+
+            let color_addr = match sprites[x] {
+                Some((sprite, behind, zero)) => {
+                    if zero && bg_opaque && x != 255 && self.sprite_zero_hit_dot.is_none()
+                        && !self.status.contains(StatusRegister::SpriteZeroHit) {
+                        self.sprite_zero_hit_dot = Some(x as u16 + 1);
+                    }
+
+                    if behind && bg_opaque { bg } else { sprite }
+                },
+                None if bg_opaque => bg,
+                None => 0,
+            };
+
+            let color = self.palette_table[mirror_palette_addr(0x3F00 | color_addr as u16)];
+            self.frame.set_pixel(x, y, Palette::index(color, self.mask.bits()));
+        }
+    }
+
+    fn render_background_line(&mut self, line: &mut [u8; Frame::WIDTH], cart: &mut Cartridge) {
+        let fine_y = (self.v & FINE_Y) >> 12;
+        let coarse_y = (self.v & COARSE_Y) >> 5;
+        let mut coarse_x = self.v & COARSE_X;
+        let mut nametable = (self.v >> 10) & 0b11;
+        let mut skip = self.x as usize; // fine x only trims the first tile
+
+        let mut screen_x = 0;
+        while screen_x < Frame::WIDTH {
+            let base = 0x2000 | nametable << 10;
+            let tile = self.mem_read(base | coarse_y << 5 | coarse_x, cart) as u16;
+            let attribute = self.mem_read(base | 0x3C0 | (coarse_y >> 2) << 3 | coarse_x >> 2, cart);
+            let shift = ((coarse_y & 0b10) << 1) | (coarse_x & 0b10);
+            let palette = (attribute >> shift) & 0b11;
+
+            let addr = self.ctrl.background_pattern_addr() + tile * 16 + fine_y;
+            let lo = self.mem_read(addr, cart);
+            let hi = self.mem_read(addr + 8, cart);
+
+            for bit in skip..8 {
+                if screen_x >= Frame::WIDTH {
+                    break;
+                }
+                let value = (lo >> (7 - bit)) & 1 | ((hi >> (7 - bit)) & 1) << 1;
+                line[screen_x] = if value == 0 { 0 } else { palette << 2 | value };
+                screen_x += 1;
+            }
+            skip = 0;
+
+            coarse_x += 1;
+            if coarse_x == 32 {
+                coarse_x = 0;
+                nametable ^= 1;
+            }
+        }
+    }
+
+    fn render_sprite_line(&mut self, line: &mut [Option<(u8, bool, bool)>; Frame::WIDTH], cart: &mut Cartridge) {
+        let y = self.scanline as i32;
+        let height = self.ctrl.sprite_height() as i32;
+
+        let mut found = 0;
+        for i in 0..64 {
+            let sprite = &self.oam_data[i * 4..i * 4 + 4];
+            // sprites show up one line below their Y
+            let row = y - sprite[0] as i32 - 1;
+            if !(0..height).contains(&row) {
+                continue;
+            }
+
+            found += 1;
+            if found > 8 {
+                self.status.insert(StatusRegister::SpriteOverflow);
+                break;
+            }
+
+            let (tile, attributes, sprite_x) = (sprite[1] as u16, sprite[2], sprite[3] as usize);
+            let flip_h = attributes & 0b0100_0000 != 0;
+            let flip_v = attributes & 0b1000_0000 != 0;
+            let behind = attributes & 0b0010_0000 != 0;
+            let palette = 0x10 | (attributes & 0b11) << 2;
+
+            let row = if flip_v { height - 1 - row } else { row } as u16;
+            let addr = if height == 16 {
+                let bank = (tile & 1) * 0x1000;
+                let tile = (tile & 0xFE) + row / 8;
+                bank + tile * 16 + row % 8
+            } else {
+                self.ctrl.sprite_pattern_addr() + tile * 16 + row
+            };
+
+            let lo = self.mem_read(addr, cart);
+            let hi = self.mem_read(addr + 8, cart);
+
+            for col in 0..8 {
+                let x = sprite_x + col;
+                if x >= Frame::WIDTH {
+                    break;
+                }
+                let bit = if flip_h { col } else { 7 - col };
+                let value = (lo >> bit) & 1 | ((hi >> bit) & 1) << 1;
+
+                // lower OAM index wins, so only fill empty spots
+                if value != 0 && line[x].is_none() {
+                    line[x] = Some((palette | value, behind, i == 0));
+                }
+            }
+        }
+    }
+}
+
+// mirroring and the region come from the cartridge and the console, not from here
+impl Snapshot for PPU {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.ctrl.bits());
+        w.u8(self.mask.bits());
+        w.u8(self.status.bits());
+        w.u8(self.oam_addr);
+        w.bytes(&self.oam_data);
+        w.bytes(&self.palette_table);
+        w.bytes(&self.vram);
+        w.u16(self.v);
+        w.u16(self.t);
+        w.u8(self.x);
+        w.bool(self.w);
+        w.u16(self.scanline);
+        w.u16(self.dot);
+        w.u64(self.frame_count);
+        w.u8(self.read_buffer);
+        w.u8(self.io_latch);
+        for refreshed in self.latch_refreshed {
+            w.u64(refreshed);
+        }
+        w.bool(self.odd_frame);
+        w.bool(self.frame_complete);
+        w.option(&self.sprite_zero_hit_dot, |w, dot| w.u16(*dot));
+        // the picture so far, a state from mid frame carries on drawing into it
+        for &pixel in &self.frame.pixels {
+            w.u16(pixel);
+        }
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        self.ctrl = ControlRegister::from_bits_truncate(r.u8()?);
+        self.mask = MaskRegister::from_bits_truncate(r.u8()?);
+        self.status = StatusRegister::from_bits_truncate(r.u8()?);
+        self.oam_addr = r.u8()?;
+        r.bytes(&mut self.oam_data)?;
+        r.bytes(&mut self.palette_table)?;
+        r.bytes(&mut self.vram)?;
+        self.v = r.u16()?;
+        self.t = r.u16()?;
+        self.x = r.u8()?;
+        self.w = r.bool()?;
+        self.scanline = r.u16()?;
+        self.dot = r.u16()?;
+        self.frame_count = r.u64()?;
+        self.read_buffer = r.u8()?;
+        self.io_latch = r.u8()?;
+        for refreshed in self.latch_refreshed.iter_mut() {
+            *refreshed = r.u64()?;
+        }
+        self.odd_frame = r.bool()?;
+        self.frame_complete = r.bool()?;
+        self.sprite_zero_hit_dot = r.option(|r| r.u16())?;
+        for pixel in self.frame.pixels.iter_mut() {
+            *pixel = r.u16()?;
+        }
+        Ok(())
+    }
+}
+
+// $3F10/$3F14/$3F18/$3F1C are the same bytes as $3F00/$3F04/$3F08/$3F0C
+pub fn mirror_palette_addr(addr: u16) -> usize {
+    let index = (addr & 0x1F) as usize;
+    if index >= 0x10 && index.is_multiple_of(4) { index - 0x10 } else { index }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+
+    fn setup() -> (PPU, Cartridge) {
+        let cart = Cartridge::new(&test_rom(&[], &[])).unwrap();
+        (PPU::new(Mirroring::Horizontal), cart)
+    }
+
+    #[test]
+    fn test_vram_writes_and_buffered_reads() {
+        let (mut ppu, mut cart) = setup();
+
+        ppu.write_register(0x2006, 0x23, &mut cart);
+        ppu.write_register(0x2006, 0x05, &mut cart);
+        ppu.write_register(0x2007, 0x66, &mut cart);
+
+        ppu.write_register(0x2006, 0x23, &mut cart);
+        ppu.write_register(0x2006, 0x05, &mut cart);
+        ppu.read_register(0x2007, &mut cart); // fills the buffer
+        assert_eq!(ppu.read_register(0x2007, &mut cart), 0x66);
+    }
+
+    #[test]
+    fn test_horizontal_mirroring() {
+        let (mut ppu, mut cart) = setup();
+
+        ppu.mem_write(0x2005, 0x11, &mut cart);
+        assert_eq!(ppu.mem_read(0x2405, &mut cart), 0x11);
+        assert_eq!(ppu.mem_read(0x2805, &mut cart), 0x00);
+    }
+
+    #[test]
+    fn test_palette_mirrors() {
+        let (mut ppu, mut cart) = setup();
+
+        ppu.mem_write(0x3F10, 0x2C, &mut cart);
+        assert_eq!(ppu.mem_read(0x3F00, &mut cart), 0x2C);
+    }
+
+    #[test]
+    fn test_status_read_clears_vblank_and_latch() {
+        let (mut ppu, mut cart) = setup();
+        ppu.status.insert(StatusRegister::VblankStarted);
+        ppu.w = true;
+
+        assert_eq!(ppu.read_register(0x2002, &mut cart) & 0x80, 0x80);
+        assert!(!ppu.status.contains(StatusRegister::VblankStarted));
+        assert!(!ppu.w);
+    }
+
+    #[test]
+    fn test_io_latch_decays() {
+        let (mut ppu, mut cart) = setup();
+        ppu.write_register(0x2000, 0xFF, &mut cart);
+        ppu.write_register(0x2000, 0x00, &mut cart);
+        ppu.write_register(0x2003, 0x5A, &mut cart);
+
+        // write only registers read back the latch
+        assert_eq!(ppu.read_register(0x2000, &mut cart), 0x5A);
+
+        // $2002 only drives the top 3 bits, so the low ones still run out
+        ppu.frame_count = 20;
+        ppu.status = StatusRegister::empty();
+        assert_eq!(ppu.read_register(0x2002, &mut cart), 0x1A);
+        ppu.frame_count = 40;
+        assert_eq!(ppu.read_register(0x2005, &mut cart), 0x00);
+
+        ppu.write_register(0x2003, 0xC3, &mut cart);
+        ppu.frame_count = 60;
+        assert_eq!(ppu.read_register(0x2001, &mut cart), 0xC3);
+    }
+
+    #[test]
+    fn test_vblank_nmi() {
+        let (mut ppu, mut cart) = setup();
+        ppu.write_register(0x2000, 0b1000_0000, &mut cart);
+
+        while !ppu.take_frame_complete() {
+            ppu.tick(&mut cart);
+        }
+
+        assert_eq!((ppu.scanline, ppu.dot), (VBLANK_SCANLINE, 2));
+        assert!(ppu.nmi_line());
+        ppu.read_register(0x2002, &mut cart);
+        assert!(!ppu.nmi_line());
+    }
+
+    #[test]
+    fn test_scroll_registers() {
+        let (mut ppu, mut cart) = setup();
+
+        ppu.write_register(0x2005, 0b0111_1101, &mut cart); // coarse x 15, fine x 5
+        ppu.write_register(0x2005, 0b0101_1110, &mut cart); // coarse y 11, fine y 6
+        assert_eq!(ppu.x, 0b101);
+        assert_eq!(ppu.t, 6 << 12 | 11 << 5 | 15);
+    }
+}
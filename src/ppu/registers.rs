@@ -0,0 +1,106 @@
+use bitflags::bitflags;
+
+/*
+ $2000 PPUCTRL
+7  bit  0
+---- ----
+VPHB SINN
+|||| ||||
+|||| ||++- Base nametable address
+|||| |+--- VRAM address increment per CPU read/write of PPUDATA (0: add 1; 1: add 32)
+|||| +---- Sprite pattern table address for 8x8 sprites
+|||+------ Background pattern table address
+||+------- Sprite size (0: 8x8; 1: 8x16)
+|+-------- PPU master/slave select
++--------- Generate an NMI at the start of vblank
+*/
+bitflags! {
+    #[derive(Debug, Clone, Copy)]
+    pub struct ControlRegister: u8 {
+        const Nametable1 = 0b0000_0001;
+        const Nametable2 = 0b0000_0010;
+        const VramAddIncrement = 0b0000_0100;
+        const SpritePatternAddr = 0b0000_1000;
+        const BackgroundPatternAddr = 0b0001_0000;
+        const SpriteSize = 0b0010_0000;
+        const MasterSlaveSelect = 0b0100_0000;
+        const GenerateNmi = 0b1000_0000;
+    }
+}
+
+impl ControlRegister {
+    pub fn vram_addr_increment(&self) -> u16 {
+        if self.contains(ControlRegister::VramAddIncrement) { 32 } else { 1 }
+    }
+
+    pub fn sprite_pattern_addr(&self) -> u16 {
+        if self.contains(ControlRegister::SpritePatternAddr) { 0x1000 } else { 0 }
+    }
+
+    pub fn background_pattern_addr(&self) -> u16 {
+        if self.contains(ControlRegister::BackgroundPatternAddr) { 0x1000 } else { 0 }
+    }
+
+    pub fn sprite_height(&self) -> u16 {
+        if self.contains(ControlRegister::SpriteSize) { 16 } else { 8 }
+    }
+
+    pub fn generate_vblank_nmi(&self) -> bool {
+        self.contains(ControlRegister::GenerateNmi)
+    }
+}
+
+/*
+ $2001 PPUMASK
+7  bit  0
+---- ----
+BGRs bMmG
+|||| ||||
+|||| |||+- Greyscale
+|||| ||+-- Show background in leftmost 8 pixels of screen
+|||| |+--- Show sprites in leftmost 8 pixels of screen
+|||| +---- Show background
+|||+------ Show sprites
+||+------- Emphasize red
+|+-------- Emphasize green
++--------- Emphasize blue
+*/
+bitflags! {
+    #[derive(Debug, Clone, Copy)]
+    pub struct MaskRegister: u8 {
+        const Greyscale = 0b0000_0001;
+        const ShowBackgroundLeft = 0b0000_0010;
+        const ShowSpritesLeft = 0b0000_0100;
+        const ShowBackground = 0b0000_1000;
+        const ShowSprites = 0b0001_0000;
+        const EmphasizeRed = 0b0010_0000;
+        const EmphasizeGreen = 0b0100_0000;
+        const EmphasizeBlue = 0b1000_0000;
+    }
+}
+
+impl MaskRegister {
+    pub fn rendering_enabled(&self) -> bool {
+        self.intersects(MaskRegister::ShowBackground | MaskRegister::ShowSprites)
+    }
+}
+
+/*
+ $2002 PPUSTATUS
+7  bit  0
+---- ----
+VSO. ....
+|||| ||||
+|||+-++++- open bus
+||+------- Sprite overflow
+|+-------- Sprite 0 hit
++--------- Vblank started
+*/
+bitflags! {
+    #[derive(Debug, Clone, Copy)]
+    pub struct StatusRegister: u8 {
+        const SpriteOverflow = 0b0010_0000;
+        const SpriteZeroHit = 0b0100_0000;
+        const VblankStarted = 0b1000_0000;
+    }
+}
@@ -0,0 +1,152 @@
+use crate::clock::{NTSC_CPU_DIVIDER, NTSC_MASTER_CLOCK, NTSC_PPU_DIVIDER};
+use crate::palette::PpuModel;
+use crate::savestate::{Snapshot, StateReader, StateWriter};
+
+// master clock / (341 * 262 - 0.5) / 4 dots per frame, NTSC
+pub const NTSC_FRAME_RATE: f64 = 60.0988;
+pub const PAL_FRAME_RATE: f64 = 50.0070;
+
+pub const PAL_MASTER_CLOCK: u64 = 26_601_712;
+
+/*
+ which console the game is running on. they all share a CPU core and a PPU design, but they're
+ clocked differently and the 50Hz ones draw 312 lines a frame
+              master clock   CPU    PPU   lines  vblank NMI
+   NTSC       21.477272 MHz  /12    /4    262    241
+   PAL        26.601712 MHz  /16    /5    312    241
+   Dendy      26.601712 MHz  /15    /5    312    291
+ the PAL APU has its own noise, DMC and frame counter periods. the Dendy keeps the NTSC ones
+ https://www.nesdev.org/wiki/Cycle_reference_chart
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    Dendy,
+}
+
+impl Region {
+    // the NES 2.0 CPU/PPU timing byte. multi-region games get NTSC
+    pub fn from_header(timing: u8) -> Region {
+        match timing & 0b11 {
+            1 => Region::Pal,
+            3 => Region::Dendy,
+            _ => Region::Ntsc,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Region> {
+        match name.to_ascii_lowercase().as_str() {
+            "ntsc" => Some(Region::Ntsc),
+            "pal" => Some(Region::Pal),
+            "dendy" => Some(Region::Dendy),
+            _ => None,
+        }
+    }
+
+    pub fn master_clock(self) -> u64 {
+        match self {
+            Region::Ntsc => NTSC_MASTER_CLOCK,
+            Region::Pal | Region::Dendy => PAL_MASTER_CLOCK,
+        }
+    }
+
+    // master clock ticks per CPU cycle
+    pub fn cpu_divider(self) -> u64 {
+        match self {
+            Region::Ntsc => NTSC_CPU_DIVIDER,
+            Region::Pal => 16,
+            Region::Dendy => 15,
+        }
+    }
+
+    // master clock ticks per PPU dot
+    pub fn ppu_divider(self) -> u64 {
+        match self {
+            Region::Ntsc => NTSC_PPU_DIVIDER,
+            Region::Pal | Region::Dendy => 5,
+        }
+    }
+
+    // CPU cycles per second, rounded
+    pub fn cpu_clock(self) -> u32 {
+        (self.master_clock() as f64 / self.cpu_divider() as f64).round() as u32
+    }
+
+    pub fn scanlines(self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    // where the vblank flag goes up and NMI fires
+    pub fn vblank_scanline(self) -> u16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    pub fn frame_rate(self) -> f64 {
+        match self {
+            Region::Ntsc => NTSC_FRAME_RATE,
+            Region::Pal | Region::Dendy => PAL_FRAME_RATE,
+        }
+    }
+
+    pub fn frames_for_seconds(self, seconds: f64) -> u64 {
+        (seconds * self.frame_rate()).ceil() as u64
+    }
+
+    pub fn ppu_model(self) -> PpuModel {
+        match self {
+            Region::Ntsc => PpuModel::RP2C02,
+            Region::Pal => PpuModel::RP2C07,
+            Region::Dendy => PpuModel::UA6538,
+        }
+    }
+}
+
+impl Snapshot for Region {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(*self as u8);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String> {
+        *self = match r.u8()? {
+            0 => Region::Ntsc,
+            1 => Region::Pal,
+            2 => Region::Dendy,
+            n => return Err(format!("save state has region {}, which isn't one", n)),
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_clocks() {
+        assert_eq!(Region::Ntsc.cpu_clock(), 1_789_773);
+        assert_eq!(Region::Pal.cpu_clock(), 1_662_607);
+        assert_eq!(Region::Dendy.cpu_clock(), 1_773_447);
+
+        // 341 dots a line, and NTSC drops one every other frame
+        let rate = |region: Region, dots: f64| region.master_clock() as f64 / region.ppu_divider() as f64 / dots;
+        assert!((rate(Region::Ntsc, 341.0 * 262.0 - 0.5) - NTSC_FRAME_RATE).abs() < 0.0001);
+        assert!((rate(Region::Pal, 341.0 * 312.0) - PAL_FRAME_RATE).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_from_header() {
+        assert_eq!(Region::from_header(0), Region::Ntsc);
+        assert_eq!(Region::from_header(1), Region::Pal);
+        assert_eq!(Region::from_header(2), Region::Ntsc);
+        assert_eq!(Region::from_header(3), Region::Dendy);
+        assert_eq!(Region::from_name("PAL"), Some(Region::Pal));
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::VecDeque;
+
+/*
+ running the game backwards. every interval frames the whole machine goes into a ring buffer,
+ and in between only what the controllers held each frame. to go back, the nearest snapshot at or
+ before the frame we want is loaded and the frames after it are run again with the same input,
+ which lands exactly on the frame because emulation is deterministic.
+
+ whole states are big and mostly the same from one snapshot to the next, so only the newest is
+ kept as it is. every other one is stored as its difference from the one after it (xor, with the
+ runs of zeros left out), and getting one back means undoing differences from the newest down.
+ the oldest snapshots go when there are more than frames / interval of them or the whole buffer
+ needs more than memory bytes
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RewindConfig {
+    pub interval: u64, // frames between snapshots
+    pub frames: u64,   // how far back it goes
+    pub memory: usize, // bytes, the buffer never holds more than this (or one snapshot)
+}
+
+impl Default for RewindConfig {
+    // 10 seconds at 60fps
+    fn default() -> Self {
+        RewindConfig { interval: 10, frames: 600, memory: 32 << 20 }
+    }
+}
+
+struct Entry {
+    frame: u64,
+    undo: Vec<u8>,        // turns the next snapshot into this one, empty on the oldest
+    inputs: Vec<Vec<u8>>, // the input devices at the start of each frame since this snapshot
+}
+
+pub struct Rewind {
+    config: RewindConfig,
+    entries: VecDeque<Entry>,
+    newest: Vec<u8>,
+    memory: usize,
+}
+
+// what Rewind::restore hands back: a state and the input for each frame to run after it
+pub struct RewindPoint {
+    pub state: Vec<u8>,
+    pub inputs: Vec<Vec<u8>>,
+}
+
+impl Rewind {
+    pub fn new(config: RewindConfig) -> Self {
+        let config = RewindConfig { interval: config.interval.max(1), ..config };
+        Rewind { config, entries: VecDeque::new(), newest: Vec::new(), memory: 0 }
+    }
+
+    pub fn config(&self) -> RewindConfig {
+        self.config
+    }
+
+    // the earliest frame it can go back to
+    pub fn oldest_frame(&self) -> Option<u64> {
+        self.entries.front().map(|entry| entry.frame)
+    }
+
+    // bytes held, the newest snapshot included
+    pub fn memory(&self) -> usize {
+        self.memory
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.newest = Vec::new();
+        self.memory = 0;
+    }
+
+    /*
+     called at the start of every frame with what the input devices hold. the state is only asked
+     for when a snapshot is due. frames that don't follow on from the last one (a state was loaded,
+     the power went off) start the buffer over
+    */
+    pub fn record<F: FnOnce() -> Vec<u8>>(&mut self, frame: u64, input: Vec<u8>, state: F) {
+        let next = self.entries.back().map(|entry| entry.frame + entry.inputs.len() as u64);
+        if next.is_some_and(|next| next != frame) {
+            self.clear();
+        }
+
+        let due = self.entries.back().is_none_or(|entry| frame - entry.frame >= self.config.interval);
+        if due {
+            let state = state();
+            let undo = if self.entries.is_empty() { Vec::new() } else { diff(&state, &self.newest) };
+            self.memory = self.memory + undo.len() + state.len() - self.newest.len();
+            self.newest = state;
+            self.entries.push_back(Entry { frame, undo, inputs: Vec::new() });
+        }
+
+        self.memory += input.len();
+        self.entries.back_mut().expect("there's a snapshot").inputs.push(input);
+        self.trim();
+    }
+
+    /*
+     the newest snapshot at or before frame, or the oldest there is if frame has gone out of the
+     buffer, with the inputs to get from it to frame. everything after frame is forgotten
+    */
+    pub fn restore(&mut self, frame: u64) -> Option<RewindPoint> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        let keep = self.entries.iter().rposition(|entry| entry.frame <= frame).unwrap_or(0);
+
+        for entry in self.entries.drain(keep + 1..).rev() {
+            apply(&mut self.newest, &entry.undo);
+        }
+        let entry = self.entries.back_mut().expect("kept one");
+        entry.inputs.truncate(frame.saturating_sub(entry.frame) as usize);
+        let inputs = entry.inputs.clone();
+        self.memory = self.count_memory();
+
+        Some(RewindPoint { state: self.newest.clone(), inputs })
+    }
+
+    fn trim(&mut self) {
+        let most = (self.config.frames / self.config.interval).max(1) as usize;
+        while self.entries.len() > 1 && (self.entries.len() > most || self.memory > self.config.memory) {
+            let entry = self.entries.pop_front().expect("more than one");
+            self.memory -= entry.inputs.iter().map(Vec::len).sum::<usize>();
+            // nothing comes before the new oldest, so its undo isn't needed
+            let oldest = self.entries.front_mut().expect("more than one");
+            self.memory -= std::mem::take(&mut oldest.undo).len();
+        }
+    }
+
+    fn count_memory(&self) -> usize {
+        let entries = self.entries.iter().map(|entry| entry.undo.len() + entry.inputs.iter().map(Vec::len).sum::<usize>());
+        self.newest.len() + entries.sum::<usize>()
+    }
+}
+
+/*
+ what turns from into to: to's length, then runs of
+   skip u32  length u32  that many bytes to xor in
+ bytes past the end of from count as zero. short runs of matching bytes stay inside a run,
+ they'd cost more as a skip
+*/
+fn diff(from: &[u8], to: &[u8]) -> Vec<u8> {
+    const GAP: usize = 8;
+
+    let xor = |i: usize| to[i] ^ from.get(i).copied().unwrap_or(0);
+    let mut out = (to.len() as u32).to_le_bytes().to_vec();
+    let (mut i, mut last) = (0, 0);
+    while i < to.len() {
+        if xor(i) == 0 {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        let mut end = i;
+        while i < to.len() && i - end <= GAP {
+            if xor(i) != 0 {
+                end = i + 1;
+            }
+            i += 1;
+        }
+        out.extend_from_slice(&((start - last) as u32).to_le_bytes());
+        out.extend_from_slice(&((end - start) as u32).to_le_bytes());
+        out.extend((start..end).map(xor));
+        last = end;
+        i = end;
+    }
+    out
+}
+
+fn apply(data: &mut Vec<u8>, diff: &[u8]) {
+    let word = |at: usize| u32::from_le_bytes(diff[at..at + 4].try_into().expect("4 bytes")) as usize;
+
+    data.resize(word(0), 0);
+    let (mut at, mut pos) = (4, 0);
+    while at < diff.len() {
+        pos += word(at);
+        let len = word(at + 4);
+        at += 8;
+        for (byte, x) in data[pos..pos + len].iter_mut().zip(&diff[at..at + len]) {
+            *byte ^= x;
+        }
+        at += len;
+        pos += len;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let from: Vec<u8> = (0..200).map(|i| i as u8).collect();
+        let mut to = from.clone();
+        to[3] = 0xFF;
+        to[5] = 0xFF;
+        to[150] = 0;
+        to.truncate(180);
+        let delta = diff(&from, &to);
+        assert!(delta.len() < 40);
+
+        let mut data = from.clone();
+        apply(&mut data, &delta);
+        assert_eq!(data, to);
+        apply(&mut data, &diff(&to, &from));
+        assert_eq!(data, from);
+    }
+
+    #[test]
+    fn test_ring_buffer() {
+        let state = |n: u8| move || vec![n; 64];
+        let mut rewind = Rewind::new(RewindConfig { interval: 4, frames: 12, memory: 1 << 20 });
+        for frame in 0..20 {
+            rewind.record(frame, vec![frame as u8], state(frame as u8));
+        }
+        // snapshots at 8, 12 and 16
+        assert_eq!(rewind.oldest_frame(), Some(8));
+
+        let point = rewind.restore(14).unwrap();
+        assert_eq!(point.state, vec![12; 64]);
+        assert_eq!(point.inputs, vec![vec![12], vec![13]]);
+        // gone past the start, so the oldest
+        let point = rewind.restore(2).unwrap();
+        assert_eq!((point.state[0], point.inputs.len()), (8, 0));
+        assert_eq!(rewind.memory(), 64);
+
+        // the frames it's told about have to follow on
+        rewind.record(30, vec![0], state(30));
+        assert_eq!(rewind.oldest_frame(), Some(30));
+
+        let mut small = Rewind::new(RewindConfig { interval: 1, frames: 100, memory: 200 });
+        for frame in 0..20 {
+            small.record(frame, Vec::new(), state(frame as u8));
+            assert!(small.memory() <= 200);
+        }
+        assert!(small.oldest_frame() > Some(0));
+    }
+}
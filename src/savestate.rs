@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+
+/*
+ save states. a state is a header and then chunks, one per part of the machine
+   "NESS"  version u32
+   tag [u8; 4]  length u32  that many bytes
+   ...
+ everything is little endian. a loader skips chunks it doesn't know and ignores anything past
+ the fields it reads at the end of a chunk, so later versions can add both without breaking
+ older ones. the version is there for when a chunk has to change shape instead, and states
+ from a version newer than STATE_VERSION are refused
+*/
+pub const STATE_MAGIC: [u8; 4] = *b"NESS";
+pub const STATE_VERSION: u32 = 1;
+
+// anything that can be put into a save state and brought back exactly as it was
+pub trait Snapshot {
+    fn save(&self, w: &mut StateWriter);
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), String>;
+}
+
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+// a whole state, header and all, the same as StateWriter::new
+impl Default for StateWriter {
+    fn default() -> Self {
+        StateWriter::new()
+    }
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        StateWriter::reuse(Vec::new())
+    }
+
+    // no magic or version, for bits of state kept on their own like rewind's input for a frame.
+    // StateChunks won't parse these, they're read straight through a StateReader
+    pub fn headerless() -> Self {
+        StateWriter { data: Vec::new() }
+    }
+
+    // writes over a buffer from an earlier state, so taking one every frame doesn't allocate
+    pub fn reuse(mut data: Vec<u8>) -> Self {
+        data.clear();
+        let mut w = StateWriter { data };
+        w.bytes(&STATE_MAGIC);
+        w.u32(STATE_VERSION);
+        w
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+
+    // whatever f writes, under a tag with its length in front
+    pub fn chunk<F: FnOnce(&mut StateWriter)>(&mut self, tag: &[u8; 4], f: F) {
+        self.bytes(tag);
+        let start = self.data.len();
+        self.u32(0);
+        f(self);
+        let len = (self.data.len() - start - 4) as u32;
+        self.data[start..start + 4].copy_from_slice(&len.to_le_bytes());
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn i16(&mut self, value: i16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn f32(&mut self, value: f32) {
+        self.u32(value.to_bits());
+    }
+
+    pub fn f64(&mut self, value: f64) {
+        self.u64(value.to_bits());
+    }
+
+    // sizes and offsets go in as u64 whatever the host's usize is
+    pub fn usize(&mut self, value: usize) {
+        self.u64(value as u64);
+    }
+
+    // as they are, the reader has to know how many there are
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    // with the length in front
+    pub fn vec(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.bytes(bytes);
+    }
+
+    pub fn option<T, F: FnOnce(&mut StateWriter, &T)>(&mut self, value: &Option<T>, f: F) {
+        self.bool(value.is_some());
+        if let Some(value) = value {
+            f(self, value);
+        }
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        StateReader { data, pos: 0 }
+    }
+
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.pos + len;
+        if end > self.data.len() {
+            return Err("save state ends early".to_string());
+        }
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, String> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub fn i16(&mut self) -> Result<i16, String> {
+        Ok(i16::from_le_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    pub fn f32(&mut self) -> Result<f32, String> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    pub fn f64(&mut self) -> Result<f64, String> {
+        Ok(f64::from_bits(self.u64()?))
+    }
+
+    pub fn usize(&mut self) -> Result<usize, String> {
+        usize::try_from(self.u64()?).map_err(|_| "save state has a size too big for this machine".to_string())
+    }
+
+    // fills all of into
+    pub fn bytes(&mut self, into: &mut [u8]) -> Result<(), String> {
+        into.copy_from_slice(self.take(into.len())?);
+        Ok(())
+    }
+
+    // a length prefixed block that has to be exactly as big as into, e.g. RAM on the cartridge
+    pub fn vec_into(&mut self, into: &mut [u8], what: &str) -> Result<(), String> {
+        let len = self.u32()? as usize;
+        if len != into.len() {
+            return Err(format!("save state has {} bytes of {}, this machine has {}", len, what, into.len()));
+        }
+        self.bytes(into)
+    }
+
+    pub fn vec(&mut self) -> Result<Vec<u8>, String> {
+        let len = self.u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    pub fn option<T, F: FnOnce(&mut StateReader<'a>) -> Result<T, String>>(&mut self, f: F) -> Result<Option<T>, String> {
+        if self.bool()? { f(self).map(Some) } else { Ok(None) }
+    }
+}
+
+// the header checked and the chunks pulled out by tag
+pub struct StateChunks<'a> {
+    pub version: u32,
+    chunks: HashMap<[u8; 4], &'a [u8]>,
+}
+
+impl<'a> StateChunks<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<StateChunks<'a>, String> {
+        let mut r = StateReader::new(data);
+        if r.take(4).ok() != Some(&STATE_MAGIC[..]) {
+            return Err("not a save state".to_string());
+        }
+        let version = r.u32()?;
+
+        let mut chunks = HashMap::new();
+        while r.pos < data.len() {
+            let tag: [u8; 4] = r.array()?;
+            let len = r.u32()? as usize;
+            chunks.insert(tag, r.take(len)?);
+        }
+        Ok(StateChunks { version, chunks })
+    }
+
+    pub fn get(&self, tag: &[u8; 4]) -> Result<StateReader<'a>, String> {
+        self.chunks
+            .get(tag)
+            .map(|data| StateReader::new(data))
+            .ok_or(format!("save state has no {} chunk", String::from_utf8_lossy(tag).trim_end()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_chunks_round_trip() {
+        let mut w = StateWriter::new();
+        w.chunk(b"ONE ", |w| {
+            w.u16(0x1234);
+            w.option(&Some(-5i16), |w, v| w.i16(*v));
+            w.vec(&[1, 2, 3]);
+        });
+        w.chunk(b"NEW!", |w| w.u64(99));
+        let data = w.finish();
+
+        let chunks = StateChunks::parse(&data).unwrap();
+        assert_eq!(chunks.version, STATE_VERSION);
+        let mut r = chunks.get(b"ONE ").unwrap();
+        assert_eq!(r.u16(), Ok(0x1234));
+        assert_eq!(r.option(|r| r.i16()), Ok(Some(-5)));
+        let mut ram = [0u8; 3];
+        assert_eq!(r.vec_into(&mut ram, "RAM"), Ok(()));
+        assert_eq!(ram, [1, 2, 3]);
+        assert!(r.u8().is_err());
+
+        assert_eq!(chunks.get(b"TWO ").err().unwrap(), "save state has no TWO chunk");
+        assert!(StateChunks::parse(b"NESS\x01\x00\x00\x00ONE \x10\x00\x00\x00").is_err());
+        assert!(StateChunks::parse(b"PNG").is_err());
+    }
+}
@@ -0,0 +1,148 @@
+use std::fs;
+use std::path::Path;
+
+use crate::bus::Bus;
+use crate::joypad::JoypadButton;
+
+/*
+ input scripts, one line per change of buttons. buttons stay held until a later line changes them
+
+   # frame  pad 1         pad 2 (optional)
+   0        .
+   60       start
+   62       .
+   120      right+a       b
+   300      .             mic
+
+ '.' means nothing held. button names are a, b, select, start, up, down, left, right.
+ mic on pad 2 is the Famicom microphone, which lives on the second controller
+*/
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InputScript {
+    entries: Vec<(u64, [JoypadButton; 2], bool)>, // sorted by frame, the bool is the mic
+}
+
+impl InputScript {
+    pub fn parse(text: &str) -> Result<InputScript, String> {
+        let mut entries = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let error = |msg: String| format!("line {}: {}", number + 1, msg);
+            let mut fields = line.split_whitespace();
+            let frame = fields.next().unwrap_or("");
+            let frame: u64 = frame.parse().map_err(|_| error(format!("{} isn't a frame number", frame)))?;
+
+            let mut pads = [JoypadButton::empty(); 2];
+            let mut mic = false;
+            for (n, pad) in pads.iter_mut().enumerate() {
+                if let Some(field) = fields.next() {
+                    let mut names: Vec<&str> = field.split('+').collect();
+                    if n == 1 {
+                        let before = names.len();
+                        names.retain(|name| !name.eq_ignore_ascii_case("mic"));
+                        mic = names.len() != before;
+                        if names.is_empty() {
+                            continue;
+                        }
+                    }
+                    *pad = parse_buttons(&names.join("+")).map_err(error)?;
+                }
+            }
+            if fields.next().is_some() {
+                return Err(error("only two pads".to_string()));
+            }
+
+            entries.push((frame, pads, mic));
+        }
+
+        // stable, so later lines for the same frame win
+        entries.sort_by_key(|(frame, _, _)| *frame);
+        Ok(InputScript { entries })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<InputScript, String> {
+        let text = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.as_ref().display(), e))?;
+        InputScript::parse(&text)
+    }
+
+    // what both pads are holding during this frame
+    pub fn buttons_at(&self, frame: u64) -> [JoypadButton; 2] {
+        self.entry_at(frame).map(|(_, pads, _)| *pads).unwrap_or_default()
+    }
+
+    pub fn mic_at(&self, frame: u64) -> bool {
+        self.entry_at(frame).is_some_and(|(_, _, mic)| *mic)
+    }
+
+    fn entry_at(&self, frame: u64) -> Option<&(u64, [JoypadButton; 2], bool)> {
+        self.entries.iter().take_while(|(start, _, _)| *start <= frame).last()
+    }
+
+    // sets everything the script says for this frame
+    pub fn apply(&self, frame: u64, bus: &mut Bus) {
+        let [pad1, pad2] = self.buttons_at(frame);
+        bus.set_buttons(0, pad1.into());
+        bus.set_buttons(1, pad2.into());
+        bus.microphone = self.mic_at(frame);
+    }
+}
+
+fn parse_buttons(field: &str) -> Result<JoypadButton, String> {
+    if field == "." {
+        return Ok(JoypadButton::empty());
+    }
+
+    field.split('+').try_fold(JoypadButton::empty(), |held, name| {
+        let button = match name.to_ascii_lowercase().as_str() {
+            "a" => JoypadButton::A,
+            "b" => JoypadButton::B,
+            "select" => JoypadButton::Select,
+            "start" => JoypadButton::Start,
+            "up" => JoypadButton::Up,
+            "down" => JoypadButton::Down,
+            "left" => JoypadButton::Left,
+            "right" => JoypadButton::Right,
+            _ => return Err(format!("unknown button {}", name)),
+        };
+        Ok(held | button)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_buttons_hold_until_changed() {
+        let script = InputScript::parse("# intro\n60 start\n62 .   # let go\n120 right+A b\n").unwrap();
+
+        assert_eq!(script.buttons_at(0), [JoypadButton::empty(); 2]);
+        assert_eq!(script.buttons_at(61), [JoypadButton::Start, JoypadButton::empty()]);
+        assert_eq!(script.buttons_at(62), [JoypadButton::empty(); 2]);
+        assert_eq!(script.buttons_at(5000), [JoypadButton::Right | JoypadButton::A, JoypadButton::B]);
+    }
+
+    #[test]
+    fn test_mic() {
+        let script = InputScript::parse("10 a mic\n20 . b+mic\n30 .").unwrap();
+
+        assert!(!script.mic_at(0));
+        assert_eq!(script.buttons_at(10), [JoypadButton::A, JoypadButton::empty()]);
+        assert!(script.mic_at(10));
+        assert_eq!(script.buttons_at(20), [JoypadButton::empty(), JoypadButton::B]);
+        assert!(script.mic_at(25));
+        assert!(!script.mic_at(30));
+        assert!(InputScript::parse("0 mic").is_err());
+    }
+
+    #[test]
+    fn test_errors_name_the_line() {
+        assert_eq!(InputScript::parse("0 .\nten a").unwrap_err(), "line 2: ten isn't a frame number");
+        assert_eq!(InputScript::parse("0 turbo").unwrap_err(), "line 1: unknown button turbo");
+    }
+}
@@ -0,0 +1,185 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::checksum::crc32;
+use crate::region::Region;
+
+// VGM 1.71, only the NES APU part of it. the header is a fixed 0x100 bytes and the
+// commands follow straight after
+const HEADER_SIZE: usize = 0x100;
+const VERSION: u32 = 0x0000_0171;
+const VGM_SAMPLE_RATE: u64 = 44_100;
+
+const CMD_NES_APU_WRITE: u8 = 0xB4;
+const CMD_WAIT: u8 = 0x61;
+const CMD_WAIT_NTSC_FRAME: u8 = 0x62;
+const CMD_WAIT_PAL_FRAME: u8 = 0x63;
+const CMD_WAIT_SHORT: u8 = 0x70; // 0x7n waits n + 1 samples
+const CMD_DATA_BLOCK: u8 = 0x67;
+const CMD_END: u8 = 0x66;
+const DATA_BLOCK_NES_RAM: u8 = 0xC2; // first two bytes are where in APU memory it goes
+
+pub struct VgmLogger {
+    commands: Vec<u8>,
+    cpu_clock: u32,
+    frame_rate: u32,
+    start_cycle: u64,
+    samples: u64, // how many samples the waits so far add up to
+    loop_point: Option<(usize, u64)>, // command offset and sample count at the loop
+    sent_samples: HashSet<(u16, u32)>, // DMC samples already in the file, by address and crc
+}
+
+impl VgmLogger {
+    // cycle is the CPU cycle count logging starts at, everything else is timed from there
+    pub fn new(cycle: u64) -> Self {
+        VgmLogger::with_region(cycle, Region::Ntsc)
+    }
+
+    // the region decides how long a CPU cycle is
+    pub fn with_region(cycle: u64, region: Region) -> Self {
+        VgmLogger {
+            commands: Vec::new(),
+            cpu_clock: region.cpu_clock(),
+            frame_rate: region.frame_rate().round() as u32,
+            start_cycle: cycle,
+            samples: 0,
+            loop_point: None,
+            sent_samples: HashSet::new(),
+        }
+    }
+
+    // addr is $4000-$401F
+    pub fn write(&mut self, cycle: u64, addr: u16, data: u8) {
+        self.wait_until(cycle);
+        self.commands.extend([CMD_NES_APU_WRITE, (addr - 0x4000) as u8, data]);
+    }
+
+    // sample data the DMC is about to play, so the player has it in its copy of memory
+    pub fn dmc_sample(&mut self, cycle: u64, addr: u16, bytes: &[u8]) {
+        if !self.sent_samples.insert((addr, crc32(bytes))) {
+            return;
+        }
+
+        self.wait_until(cycle);
+        self.commands.extend([CMD_DATA_BLOCK, CMD_END, DATA_BLOCK_NES_RAM]);
+        self.commands.extend((bytes.len() as u32 + 2).to_le_bytes());
+        self.commands.extend(addr.to_le_bytes());
+        self.commands.extend(bytes);
+    }
+
+    // the file loops back to here once it reaches the end
+    pub fn mark_loop(&mut self, cycle: u64) {
+        self.wait_until(cycle);
+        self.loop_point = Some((self.commands.len(), self.samples));
+    }
+
+    fn wait_until(&mut self, cycle: u64) {
+        let target = (cycle.saturating_sub(self.start_cycle)) * VGM_SAMPLE_RATE / self.cpu_clock as u64;
+        let mut wait = target.saturating_sub(self.samples);
+        self.samples += wait;
+
+        while wait > 0 {
+            match wait {
+                735 => {
+                    self.commands.push(CMD_WAIT_NTSC_FRAME);
+                    wait = 0;
+                },
+                882 => {
+                    self.commands.push(CMD_WAIT_PAL_FRAME);
+                    wait = 0;
+                },
+                1..=16 => {
+                    self.commands.push(CMD_WAIT_SHORT + wait as u8 - 1);
+                    wait = 0;
+                },
+                _ => {
+                    let chunk = wait.min(u16::MAX as u64);
+                    self.commands.push(CMD_WAIT);
+                    self.commands.extend((chunk as u16).to_le_bytes());
+                    wait -= chunk;
+                },
+            }
+        }
+    }
+
+    // the whole file, ending at this cycle
+    pub fn finish(mut self, cycle: u64) -> Vec<u8> {
+        self.wait_until(cycle);
+        self.commands.push(CMD_END);
+
+        let mut out = vec![0u8; HEADER_SIZE];
+        let mut put = |at: usize, value: u32| out[at..at + 4].copy_from_slice(&value.to_le_bytes());
+
+        put(0x00, u32::from_le_bytes(*b"Vgm "));
+        put(0x04, (HEADER_SIZE + self.commands.len() - 4) as u32); // EOF offset
+        put(0x08, VERSION);
+        put(0x18, self.samples as u32);
+        if let Some((offset, samples)) = self.loop_point {
+            put(0x1C, (HEADER_SIZE + offset - 0x1C) as u32);
+            put(0x20, (self.samples - samples) as u32);
+        }
+        put(0x24, self.frame_rate); // rate
+        put(0x34, (HEADER_SIZE - 0x34) as u32); // data offset
+        put(0x84, self.cpu_clock); // NES APU clock
+
+        out.extend(self.commands);
+        out
+    }
+
+    pub fn save<P: AsRef<Path>>(self, path: P, cycle: u64) -> io::Result<()> {
+        fs::write(path, self.finish(cycle))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::apu::CPU_CLOCK_NTSC;
+
+    fn cycles_for(samples: u64) -> u64 {
+        (samples * CPU_CLOCK_NTSC as u64).div_ceil(VGM_SAMPLE_RATE)
+    }
+
+    #[test]
+    fn test_writes_and_waits() {
+        let mut log = VgmLogger::new(1000);
+        log.write(1000, 0x4015, 0x0F);
+        log.write(1000 + cycles_for(735), 0x4000, 0xBF);
+        log.write(1000 + cycles_for(740), 0x4003, 0x08);
+        let file = log.finish(1000 + cycles_for(70_740));
+
+        assert_eq!(&file[0..4], b"Vgm ");
+        assert_eq!(u32::from_le_bytes(file[0x08..0x0C].try_into().unwrap()), 0x171);
+        assert_eq!(u32::from_le_bytes(file[0x18..0x1C].try_into().unwrap()), 70_740);
+        assert_eq!(u32::from_le_bytes(file[0x04..0x08].try_into().unwrap()) as usize, file.len() - 4);
+        assert_eq!(
+            &file[HEADER_SIZE..],
+            &[0xB4, 0x15, 0x0F, 0x62, 0xB4, 0x00, 0xBF, 0x74, 0xB4, 0x03, 0x08, 0x61, 0xFF, 0xFF, 0x61, 0x71, 0x11, 0x66][..]
+        );
+    }
+
+    #[test]
+    fn test_loop_point() {
+        let mut log = VgmLogger::new(0);
+        log.write(0, 0x4015, 0x01);
+        log.mark_loop(cycles_for(100));
+        log.write(cycles_for(100), 0x4003, 0x08);
+        let file = log.finish(cycles_for(300));
+
+        let loop_offset = u32::from_le_bytes(file[0x1C..0x20].try_into().unwrap()) as usize + 0x1C;
+        assert_eq!(file[loop_offset], CMD_NES_APU_WRITE);
+        assert_eq!(u32::from_le_bytes(file[0x20..0x24].try_into().unwrap()), 200);
+    }
+
+    #[test]
+    fn test_dmc_samples_are_only_sent_once() {
+        let mut log = VgmLogger::new(0);
+        log.dmc_sample(0, 0xC000, &[1, 2, 3]);
+        log.dmc_sample(0, 0xC000, &[1, 2, 3]);
+        let file = log.finish(0);
+
+        assert_eq!(&file[HEADER_SIZE..], &[0x67, 0x66, 0xC2, 5, 0, 0, 0, 0x00, 0xC0, 1, 2, 3, 0x66][..]);
+    }
+}
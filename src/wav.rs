@@ -0,0 +1,149 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::apu::{self, APU, STEM_NAMES};
+use crate::nes::Nes;
+use crate::script::InputScript;
+
+// 16 bit mono PCM
+pub fn encode_wav(samples: &[f32], sample_rate: u32) -> Vec<u8> {
+    let data_len = samples.len() as u32 * 2;
+    let mut out = Vec::with_capacity(44 + data_len as usize);
+
+    out.extend(b"RIFF");
+    out.extend((36 + data_len).to_le_bytes());
+    out.extend(b"WAVE");
+
+    out.extend(b"fmt ");
+    out.extend(16u32.to_le_bytes());
+    out.extend(1u16.to_le_bytes()); // PCM
+    out.extend(1u16.to_le_bytes()); // mono
+    out.extend(sample_rate.to_le_bytes());
+    out.extend((sample_rate * 2).to_le_bytes()); // bytes per second
+    out.extend(2u16.to_le_bytes()); // bytes per sample
+    out.extend(16u16.to_le_bytes());
+
+    out.extend(b"data");
+    out.extend(data_len.to_le_bytes());
+    for &sample in samples {
+        out.extend(apu::to_i16(sample).to_le_bytes());
+    }
+    out
+}
+
+pub fn save_wav<P: AsRef<Path>>(path: P, samples: &[f32], sample_rate: u32) -> io::Result<()> {
+    fs::write(path, encode_wav(samples, sample_rate))
+}
+
+// audio pulled out of the APU frame by frame
+pub struct AudioCapture {
+    pub sample_rate: u32,
+    pub mix: Vec<f32>,
+    pub stems: Option<Vec<Vec<f32>>>, // same order as STEM_NAMES
+}
+
+impl AudioCapture {
+    // sets the APU up to match, stems cost a resampler per channel so they're opt in
+    pub fn new(apu: &mut APU, sample_rate: u32, stems: bool) -> Self {
+        apu.set_sample_rate(sample_rate);
+        if stems {
+            apu.enable_stems();
+        }
+        AudioCapture {
+            sample_rate,
+            mix: Vec::new(),
+            stems: stems.then(|| vec![Vec::new(); STEM_NAMES.len()]),
+        }
+    }
+
+    pub fn collect(&mut self, apu: &mut APU) {
+        self.extend(apu.take_samples(), apu.take_stems());
+    }
+
+    // for when the mix has already been taken out, e.g. by Nes::run_frame
+    pub fn extend(&mut self, mix: Vec<f32>, stems: Option<Vec<Vec<f32>>>) {
+        self.mix.extend(mix);
+        if let (Some(stems), Some(new)) = (&mut self.stems, stems) {
+            for (stem, samples) in stems.iter_mut().zip(new) {
+                stem.extend(samples);
+            }
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        save_wav(path, &self.mix, self.sample_rate)
+    }
+
+    // dir/pulse1.wav, dir/pulse2.wav and so on. returns the files written
+    pub fn save_stems<P: AsRef<Path>>(&self, dir: P) -> io::Result<Vec<PathBuf>> {
+        let Some(stems) = &self.stems else {
+            return Ok(Vec::new());
+        };
+
+        fs::create_dir_all(&dir)?;
+        let mut written = Vec::new();
+        for (name, samples) in STEM_NAMES.iter().zip(stems) {
+            let path = dir.as_ref().join(format!("{}.wav", name));
+            save_wav(&path, samples, self.sample_rate)?;
+            written.push(path);
+        }
+        Ok(written)
+    }
+}
+
+// runs a ROM with nothing on screen and returns what it played
+pub fn render_audio(rom: &[u8], frames: u64, script: Option<&InputScript>, sample_rate: u32, stems: bool) -> Result<AudioCapture, String> {
+    let mut nes = Nes::from_rom(rom)?;
+    let mut capture = AudioCapture::new(&mut nes.cpu.bus.apu, sample_rate, stems);
+
+    for frame in 0..frames {
+        if let Some(script) = script {
+            script.apply(frame, &mut nes.cpu.bus);
+        }
+
+        let output = nes.run_frame();
+        capture.extend(output.samples, nes.cpu.bus.apu.take_stems());
+        if nes.halted_at().is_some() {
+            break;
+        }
+    }
+
+    Ok(capture)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use crate::region::NTSC_FRAME_RATE;
+
+    #[test]
+    fn test_wav_header() {
+        let wav = encode_wav(&[0.0, 1.0, -1.0], 44_100);
+
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(wav[4..8].try_into().unwrap()), 36 + 6);
+        assert_eq!(u32::from_le_bytes(wav[24..28].try_into().unwrap()), 44_100);
+        assert_eq!(u32::from_le_bytes(wav[40..44].try_into().unwrap()), 6);
+        assert_eq!(&wav[44..], &[0x00, 0x00, 0xFF, 0x7F, 0x01, 0x80]);
+    }
+
+    #[test]
+    fn test_render_a_second_of_square_wave() {
+        // LDA #$01; STA $4015; LDA #$BF; STA $4000; LDA #$FD; STA $4002; LDA #$08; STA $4003; JMP *
+        let program = [
+            0xA9, 0x01, 0x8D, 0x15, 0x40, 0xA9, 0xBF, 0x8D, 0x00, 0x40,
+            0xA9, 0xFD, 0x8D, 0x02, 0x40, 0xA9, 0x08, 0x8D, 0x03, 0x40, 0x4C, 0x14, 0x80,
+        ];
+        let capture = render_audio(&test_rom(&program, &[]), 60, None, 44_100, true).unwrap();
+
+        // the first frame is short, vblank comes before a whole frame has run
+        let expected = 60.0 / NTSC_FRAME_RATE * 44_100.0;
+        assert!((capture.mix.len() as f64 - expected).abs() < 44_100.0 / NTSC_FRAME_RATE);
+        let stems = capture.stems.unwrap();
+        assert!(stems[0].iter().any(|&s| s.abs() > 0.05));
+        // the triangle idles at 15 so it gets a thump from the filters at power on, the rest stay silent
+        assert!([1, 3, 4, 5].iter().all(|&i| stems[i].iter().all(|&s| s == 0.0)));
+    }
+}